@@ -0,0 +1,212 @@
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::sync::Arc;
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest, HttpResponse};
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::StatusCode;
+use actix_web::http::header::{ContentType, AUTHORIZATION};
+use futures_util::future::LocalBoxFuture;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use crate::structs::api::DeliveryError;
+use crate::utils;
+use crate::utils::JsonConfigs;
+
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// Reachable without a key: liveness and readiness probes, and the WhatsApp webhook, which
+/// Meta calls with its own verify token and signs with the app secret.
+const PUBLIC_PATHS: [&str; 3] = ["/healthz", "/readyz", "/whatsapp/webhook"];
+
+fn default_bind() -> String {
+    "127.0.0.1".to_string()
+}
+
+fn default_port() -> u16 {
+    1052
+}
+
+/// A key the API accepts. With `bots` set it only works for those bots.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub key: String,
+    #[serde(default)]
+    pub bots: Vec<String>
+}
+
+/// PEM files of the certificate chain and its private key.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TlsConfig {
+    pub certificate: String,
+    pub private_key: String
+}
+
+/// Where the HTTP API listens and who may call it. Without `api_keys` the API stays open,
+/// as it was before keys existed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerConfig {
+    #[serde(default = "default_bind")]
+    pub bind: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    #[serde(default)]
+    pub api_keys: Vec<ApiKey>
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig { bind: default_bind(), port: default_port(), tls: None, api_keys: Vec::new() }
+    }
+}
+
+impl JsonConfigs for ServerConfig {}
+
+impl ServerConfig {
+    /// What a request may touch, or `None` when its key is missing or unknown.
+    pub fn scope(&self, presented: Option<&str>) -> Option<Scope> {
+        if self.api_keys.is_empty() {
+            return Some(Scope::All);
+        }
+        let presented = presented?;
+        let key = self.api_keys.iter().find(|key| constant_time_eq(key.key.as_bytes(), presented.as_bytes()))?;
+        Some(match key.bots.is_empty() {
+            true => Scope::All,
+            false => Scope::Bots(key.bots.clone())
+        })
+    }
+
+    pub fn rustls(&self) -> utils::Result<Option<rustls::ServerConfig>> {
+        let Some(tls) = &self.tls else { return Ok(None) };
+        let mut certificate = std::io::BufReader::new(std::fs::File::open(&tls.certificate)?);
+        let chain = rustls_pemfile::certs(&mut certificate).collect::<Result<Vec<_>, _>>()?;
+        let mut private_key = std::io::BufReader::new(std::fs::File::open(&tls.private_key)?);
+        let Some(key) = rustls_pemfile::private_key(&mut private_key)? else {
+            return Err(utils::Error::Config(format!("no private key in {}", tls.private_key)));
+        };
+        let config = rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(chain, key)
+            .map_err(|e| utils::Error::Config(e.to_string()))?;
+        Ok(Some(config))
+    }
+}
+
+/// Compares without returning early, so response times don't reveal how much of a key matched.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Accepts `Authorization: Bearer <key>` as well as `X-Api-Key: <key>`.
+fn presented_key(request: &ServiceRequest) -> Option<String> {
+    let headers = request.headers();
+    if let Some(key) = headers.get(API_KEY_HEADER).and_then(|value| value.to_str().ok()) {
+        return Some(key.trim().to_string());
+    }
+    let authorization = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    authorization.strip_prefix("Bearer ").map(|key| key.trim().to_string())
+}
+
+fn error_response(code: StatusCode, name: &str, message: String) -> HttpResponse {
+    let error = DeliveryError::new(code.as_u16() as i32, name, message);
+    HttpResponse::build(code)
+        .content_type(ContentType::json())
+        .body(json!({ "status": error.code, "error": error }).to_string())
+}
+
+/// The bots a request's API key may act for. Handlers take it as an extractor and call
+/// [`Scope::check`] with the bot a request targets.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Scope {
+    All,
+    Bots(Vec<String>)
+}
+
+impl Scope {
+    pub fn allows(&self, bot_name: &str) -> bool {
+        match self {
+            Scope::All => true,
+            Scope::Bots(bots) => bots.iter().any(|bot| bot == bot_name)
+        }
+    }
+
+    /// The 403 response for a bot outside the scope.
+    pub fn check(&self, bot_name: &str) -> Result<(), HttpResponse> {
+        match self.allows(bot_name) {
+            true => Ok(()),
+            false => Err(error_response(StatusCode::FORBIDDEN, "FORBIDDEN", format!("the API key can't be used for bot {}", bot_name)))
+        }
+    }
+}
+
+impl FromRequest for Scope {
+    type Error = Error;
+    type Future = Ready<Result<Self, Error>>;
+
+    fn from_request(request: &HttpRequest, _: &mut Payload) -> Self::Future {
+        // Public routes run without a scope, they don't act for any bot.
+        let scope = request.extensions().get::<Scope>().cloned().unwrap_or(Scope::Bots(Vec::new()));
+        ready(Ok(scope))
+    }
+}
+
+/// Rejects requests without a valid API key with 401 and hands the key's [`Scope`] to the
+/// handlers through the request extensions.
+pub struct ApiAuth {
+    config: Arc<ServerConfig>
+}
+
+impl ApiAuth {
+    pub fn new(config: Arc<ServerConfig>) -> Self {
+        ApiAuth { config }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ApiAuth
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = ApiAuthMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ApiAuthMiddleware { service: Rc::new(service), config: self.config.clone() }))
+    }
+}
+
+pub struct ApiAuthMiddleware<S> {
+    service: Rc<S>,
+    config: Arc<ServerConfig>
+}
+
+impl<S, B> Service<ServiceRequest> for ApiAuthMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, request: ServiceRequest) -> Self::Future {
+        if !PUBLIC_PATHS.contains(&request.path()) {
+            let Some(scope) = self.config.scope(presented_key(&request).as_deref()) else {
+                let response = error_response(StatusCode::UNAUTHORIZED, "UNAUTHORIZED", "a valid API key is required".to_string());
+                return Box::pin(ready(Ok(request.into_response(response).map_into_right_body())));
+            };
+            request.extensions_mut().insert(scope);
+        }
+        let service = self.service.clone();
+        Box::pin(async move {
+            service.call(request).await.map(ServiceResponse::map_into_left_body)
+        })
+    }
+}
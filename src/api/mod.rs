@@ -1,44 +1,559 @@
-use actix_web::{HttpResponse, post, Responder, web};
+pub mod auth;
+
+use std::collections::HashMap;
+use actix_web::{delete, get, HttpRequest, HttpResponse, post, Responder, web};
+use actix_multipart::Multipart;
+use actix_web::http::StatusCode;
 use actix_web::http::header::ContentType;
+use base64::Engine;
+use futures_util::StreamExt;
 use serde_json::{json, Value};
-use crate::structs::api::{AddContactRequest, AppData, SendMessageRequest};
-use crate::structs::wrapper::{ChannelData, ChannelTx};
+use tokio::sync::oneshot;
+use crate::api::auth::Scope;
+use crate::bot::whatsapp;
+use crate::structs::api::{AddBotRequest, AddContactRequest, AddContactsRequest, AppData, Attachment, ContactsQuery, DeleteContactsRequest, MediaSource, DeliveryError, DeliveryReply, DialogsQuery, LoginRequest, LoginStatus, PurgeContactsRequest, RescheduleRequest, ScheduleRequest, SendMessageRequest};
+use crate::structs::wrapper::{ChannelData, ChannelTx, ScheduleMode, ScheduledMessage};
 
 
-#[post("send_message")]
-async fn send_message(request: web::Json<SendMessageRequest>, app_data: web::Data<AppData>) -> impl Responder {
-    let request_ref = request.0.clone();
+/// Queues the command for the wrapper and waits until the bot reports how it went.
+async fn dispatch(app_data: &AppData, bot_name: String, data: ChannelData) -> HttpResponse {
+    let result: Value = match command(app_data, bot_name, data).await {
+        // Accepted, but left in the queue for later.
+        Ok(data) if data.is_queued() => json!({ "status": 202, "result": data }),
+        Ok(data) => json!({ "status": 200, "result": data }),
+        Err(error) => json!({ "status": error.code, "error": error })
+    };
+    json_response(result)
+}
+
+async fn command(app_data: &AppData, bot_name: String, data: ChannelData) -> DeliveryReply {
+    let (reply_tx, reply_rx) = oneshot::channel();
     let tx_result = app_data.tx.send(ChannelTx{
-        bot_name: request_ref.messenger,
-        data: ChannelData::SendMessage(request.0.clone())
+        bot_name,
+        data,
+        reply: Some(reply_tx)
     }).await;
-    let result: Value = match tx_result {
-        Ok(_) => json!({ "status": 200 }),
-        Err(e) => {
-            let error = e.to_string();
-            json!({ "status": error })
+    match tx_result {
+        Ok(_) => reply_rx.await
+            .unwrap_or_else(|_| Err(DeliveryError::new(500, "DROPPED", "request was dropped".to_string()))),
+        Err(e) => Err(DeliveryError::new(503, "QUEUE_CLOSED", e.to_string()))
+    }
+}
+
+#[post("send_message")]
+async fn send_message(request: web::Json<SendMessageRequest>, scope: Scope, app_data: web::Data<AppData>) -> impl Responder {
+    let request = request.into_inner();
+    if let Err(response) = scope.check(&request.messenger) {
+        return response;
+    }
+    dispatch(&app_data, request.messenger.clone(), ChannelData::SendMessage(request)).await
+}
+
+/// Multipart variant of `send_message` for files that aren't reachable by URL: the `request`
+/// field holds the usual JSON body and the `file` field becomes its attachment, a document
+/// unless the request names another kind.
+#[post("send_message/upload")]
+async fn send_message_upload(mut payload: Multipart, scope: Scope, app_data: web::Data<AppData>) -> impl Responder {
+    let mut request: Option<SendMessageRequest> = None;
+    let mut file: Option<MediaSource> = None;
+    while let Some(field) = payload.next().await {
+        let mut field = match field {
+            Ok(field) => field,
+            Err(e) => return json_response(json!({ "status": 400, "error": e.to_string() }))
+        };
+        let name = field.name().unwrap_or_default().to_string();
+        let file_name = field.content_disposition()
+            .and_then(|disposition| disposition.get_filename())
+            .unwrap_or_default()
+            .to_string();
+        let mime_type = field.content_type().map(|mime| mime.essence_str().to_string());
+        let mut body = Vec::new();
+        while let Some(chunk) = field.next().await {
+            match chunk {
+                Ok(chunk) => body.extend_from_slice(&chunk),
+                Err(e) => return json_response(json!({ "status": 400, "error": e.to_string() }))
+            }
         }
+        match name.as_str() {
+            "request" => match serde_json::from_slice(&body) {
+                Ok(data) => request = Some(data),
+                Err(e) => return json_response(json!({ "status": 400, "error": e.to_string() }))
+            },
+            "file" => file = Some(MediaSource {
+                data: Some(base64::engine::general_purpose::STANDARD.encode(&body)),
+                file_name,
+                mime_type,
+                url: None
+            }),
+            _ => {}
+        }
+    }
+    let (Some(mut request), Some(file)) = (request, file) else {
+        return json_response(json!({ "status": 400, "error": "both request and file fields are required" }));
     };
+    if let Err(response) = scope.check(&request.messenger) {
+        return response;
+    }
+    let with_file = |source: MediaSource| MediaSource { mime_type: source.mime_type.or(file.mime_type.clone()), ..file.clone() };
+    request.attachment = Some(match request.attachment.take() {
+        Some(Attachment::Photo(source)) => Attachment::Photo(with_file(source)),
+        Some(Attachment::Voice { source, duration }) => Attachment::Voice { source: with_file(source), duration },
+        Some(Attachment::Document(source)) => Attachment::Document(with_file(source)),
+        _ => Attachment::Document(file.clone())
+    });
+    dispatch(&app_data, request.messenger.clone(), ChannelData::SendMessage(request)).await
+}
+
+#[post("add_contact")]
+async fn add_contact(request: web::Json<AddContactRequest>, scope: Scope, app_data: web::Data<AppData>) -> impl Responder {
+    let request = request.into_inner();
+    if let Err(response) = scope.check(&request.messenger) {
+        return response;
+    }
+    dispatch(&app_data, request.messenger.clone(), ChannelData::AddContact(request)).await
+}
+
+/// Imports many contacts at once. `result.contacts` tells for each `api_id` whether it was
+/// imported, linked before, not on Telegram or has to be retried.
+#[post("add_contacts")]
+async fn add_contacts(request: web::Json<AddContactsRequest>, scope: Scope, app_data: web::Data<AppData>) -> impl Responder {
+    let request = request.into_inner();
+    if let Err(response) = scope.check(&request.messenger) {
+        return response;
+    }
+    dispatch(&app_data, request.messenger.clone(), ChannelData::AddContacts(request)).await
+}
+
+fn json_response(result: Value) -> HttpResponse {
     HttpResponse::Ok()
         .content_type(ContentType::json())
         .body(result.to_string())
 }
 
-#[post("add_contact")]
-async fn add_contact(request: web::Json<AddContactRequest>, app_data: web::Data<AppData>) -> impl Responder {
-    let request_ref = request.0.clone();
-    let tx_result = app_data.tx.send(ChannelTx{
-        bot_name: request_ref.messenger,
-        data: ChannelData::AddContact(request.0.clone())
-    }).await;
-    let result: Value = match tx_result {
-        Ok(_) => json!({ "status": 200 }),
+#[get("queue")]
+async fn get_queue(scope: Scope, app_data: web::Data<AppData>) -> impl Responder {
+    let mut queue = app_data.queue.snapshot();
+    queue.pending.retain(|message| scope.allows(&message.bot_name));
+    queue.dead.retain(|message| scope.allows(&message.bot_name));
+    json_response(json!({ "status": 200, "result": queue }))
+}
+
+/// Queue entries are addressed by id, so the scope is checked against the bot that queued it.
+fn check_queued(scope: &Scope, app_data: &AppData, id: &str) -> Result<(), HttpResponse> {
+    match app_data.queue.find(id) {
+        Some(message) => scope.check(&message.bot_name),
+        None => Ok(())
+    }
+}
+
+#[post("queue/dead/{id}/replay")]
+async fn replay_message(id: web::Path<String>, scope: Scope, app_data: web::Data<AppData>) -> impl Responder {
+    if let Err(response) = check_queued(&scope, &app_data, &id) {
+        return response;
+    }
+    match app_data.queue.replay(&id) {
+        true => json_response(json!({ "status": 200 })),
+        false => json_response(json!({ "status": 404 }))
+    }
+}
+
+#[delete("queue/dead/{id}")]
+async fn discard_message(id: web::Path<String>, scope: Scope, app_data: web::Data<AppData>) -> impl Responder {
+    if let Err(response) = check_queued(&scope, &app_data, &id) {
+        return response;
+    }
+    match app_data.queue.discard(&id) {
+        true => json_response(json!({ "status": 200 })),
+        false => json_response(json!({ "status": 404 }))
+    }
+}
+
+#[get("whatsapp/webhook")]
+async fn whatsapp_verify(query: web::Query<HashMap<String, String>>, app_data: web::Data<AppData>) -> impl Responder {
+    let mode = query.get("hub.mode").map(String::as_str);
+    let token = query.get("hub.verify_token").map(String::as_str);
+    match (mode, token, query.get("hub.challenge")) {
+        (Some("subscribe"), Some(token), Some(challenge)) if token == app_data.whatsapp.verify_token => {
+            HttpResponse::Ok().body(challenge.clone())
+        }
+        _ => HttpResponse::Forbidden().finish()
+    }
+}
+
+/// Only notifications signed with the app secret get through, the route needs no API key
+/// and what comes in runs reply rules and reaches the CRM.
+#[post("whatsapp/webhook")]
+async fn whatsapp_webhook(request: HttpRequest, body: web::Bytes, app_data: web::Data<AppData>) -> impl Responder {
+    let signature = request.headers().get(whatsapp::SIGNATURE_HEADER).and_then(|value| value.to_str().ok());
+    if !whatsapp::verify_signature(&app_data.whatsapp.app_secret, &body, signature) {
+        let error = DeliveryError::new(401, "INVALID_SIGNATURE", format!("{} doesn't match the body", whatsapp::SIGNATURE_HEADER));
+        return HttpResponse::Unauthorized()
+            .content_type(ContentType::json())
+            .body(json!({ "status": 401, "error": error }).to_string());
+    }
+    let payload: Value = match serde_json::from_slice(&body) {
+        Ok(payload) => payload,
+        Err(e) => return json_response(json!({ "status": 400, "error": e.to_string() }))
+    };
+    for receipt in whatsapp::parse_statuses(&payload) {
+        let Some(bot) = app_data.bots.find_whatsapp(&receipt.phone_id).and_then(|bot_name| app_data.bots.get(&bot_name)) else { continue };
+        bot.update_status(&receipt.id, receipt.status, receipt.timestamp);
+    }
+    for message in whatsapp::parse_webhook(&payload) {
+        let Some(bot_name) = app_data.bots.find_whatsapp(&message.phone_id) else { continue };
+        let _ = app_data.tx.send(ChannelTx{
+            bot_name,
+            data: ChannelData::ReceiveMessage(message.into()),
+            reply: None
+        }).await;
+    }
+    json_response(json!({ "status": 200 }))
+}
+
+fn login_response(result: crate::utils::Result<LoginStatus>) -> HttpResponse {
+    match result {
+        Ok(status) => json_response(json!({ "status": 200, "result": status })),
+        Err(e) => {
+            let error = DeliveryError::from(&e);
+            json_response(json!({ "status": error.code, "error": error }))
+        }
+    }
+}
+
+fn unknown_bot(bot_name: &str) -> HttpResponse {
+    let error = DeliveryError::unknown_bot(bot_name);
+    json_response(json!({ "status": error.code, "error": error }))
+}
+
+#[get("bots/{name}/login")]
+async fn login_status(name: web::Path<String>, scope: Scope, app_data: web::Data<AppData>) -> impl Responder {
+    if let Err(response) = scope.check(&name) {
+        return response;
+    }
+    let Some(bot) = app_data.bots.get(name.as_str()) else { return unknown_bot(&name) };
+    login_response(Ok(bot.login_status().await))
+}
+
+#[post("bots/{name}/login/start")]
+async fn login_start(name: web::Path<String>, request: Option<web::Json<LoginRequest>>, scope: Scope, app_data: web::Data<AppData>) -> impl Responder {
+    if let Err(response) = scope.check(&name) {
+        return response;
+    }
+    let Some(bot) = app_data.bots.get(name.as_str()) else { return unknown_bot(&name) };
+    let phone = request.and_then(|request| request.into_inner().phone);
+    login_response(bot.request_login(phone).await)
+}
+
+#[post("bots/{name}/login/code")]
+async fn login_code(name: web::Path<String>, request: web::Json<LoginRequest>, scope: Scope, app_data: web::Data<AppData>) -> impl Responder {
+    if let Err(response) = scope.check(&name) {
+        return response;
+    }
+    let Some(bot) = app_data.bots.get(name.as_str()) else { return unknown_bot(&name) };
+    let code = request.into_inner().code.unwrap_or_default();
+    login_response(bot.submit_code(code).await)
+}
+
+#[post("bots/{name}/login/password")]
+async fn login_password(name: web::Path<String>, request: Option<web::Json<LoginRequest>>, scope: Scope, app_data: web::Data<AppData>) -> impl Responder {
+    if let Err(response) = scope.check(&name) {
+        return response;
+    }
+    let Some(bot) = app_data.bots.get(name.as_str()) else { return unknown_bot(&name) };
+    let password = request.and_then(|request| request.into_inner().password);
+    login_response(bot.submit_password(password).await)
+}
+
+#[get("bots")]
+async fn list_bots(scope: Scope, app_data: web::Data<AppData>) -> impl Responder {
+    let mut result = Vec::new();
+    for bot_name in app_data.bots.names().into_iter().filter(|bot_name| scope.allows(bot_name)) {
+        let mut status = match app_data.bots.get(&bot_name) {
+            Some(bot) => bot.status().await,
+            None => match app_data.bots.failure(&bot_name) {
+                Some(status) => status,
+                None => continue
+            }
+        };
+        status.queue_depth = app_data.queue.pending_for(&bot_name);
+        status.name = bot_name;
+        result.push(status);
+    }
+    json_response(json!({ "status": 200, "result": result }))
+}
+
+fn storage_response(result: crate::utils::Result<()>) -> HttpResponse {
+    match result {
+        Ok(_) => json_response(json!({ "status": 200 })),
         Err(e) => {
-            let error = e.to_string();
-            json!({ "status": error })
+            let error = DeliveryError::from(&e);
+            json_response(json!({ "status": error.code, "error": error }))
+        }
+    }
+}
+
+#[post("bots")]
+async fn add_bot(request: web::Json<AddBotRequest>, scope: Scope, app_data: web::Data<AppData>) -> impl Responder {
+    let request = request.into_inner();
+    if let Err(response) = scope.check(&request.name) {
+        return response;
+    }
+    storage_response(app_data.bots.add(&request.name, request.auth).await)
+}
+
+#[delete("bots/{name}")]
+async fn remove_bot(name: web::Path<String>, scope: Scope, app_data: web::Data<AppData>) -> impl Responder {
+    if let Err(response) = scope.check(&name) {
+        return response;
+    }
+    match app_data.bots.remove(&name).await {
+        Ok(false) => unknown_bot(&name),
+        result => storage_response(result.map(|_| ()))
+    }
+}
+
+#[post("bots/{name}/restart")]
+async fn restart_bot(name: web::Path<String>, scope: Scope, app_data: web::Data<AppData>) -> impl Responder {
+    if let Err(response) = scope.check(&name) {
+        return response;
+    }
+    storage_response(app_data.bots.restart(&name).await)
+}
+
+/// The stored dialog list, most recent first. It is kept current from new messages, so it
+/// needs no call to Telegram.
+#[get("bots/{name}/dialogs")]
+async fn list_dialogs(name: web::Path<String>, query: web::Query<DialogsQuery>, scope: Scope, app_data: web::Data<AppData>) -> impl Responder {
+    if let Err(response) = scope.check(&name) {
+        return response;
+    }
+    if app_data.bots.get(name.as_str()).is_none() {
+        return unknown_bot(&name);
+    }
+    let dialogs: Vec<_> = app_data.dialogs.list(&name).into_iter()
+        .filter(|dialog| !query.unread || dialog.unread_count > 0)
+        .collect();
+    json_response(json!({ "status": 200, "result": dialogs }))
+}
+
+/// Fetches the whole dialog list from Telegram again.
+#[post("bots/{name}/dialogs/sync")]
+async fn sync_dialogs(name: web::Path<String>, scope: Scope, app_data: web::Data<AppData>) -> impl Responder {
+    if let Err(response) = scope.check(&name) {
+        return response;
+    }
+    let Some(bot) = app_data.bots.get(name.as_str()) else { return unknown_bot(&name) };
+    match bot.sync_dialogs().await {
+        Ok(dialogs) => json_response(json!({ "status": 200, "result": dialogs })),
+        Err(e) => error_response(DeliveryError::from(&e))
+    }
+}
+
+/// How far a sent message got: `sent`, `delivered`, `read` or `failed`. Telegram messages
+/// are looked up as `<chat_id>:<message_id>`, WhatsApp ones by their `wamid`.
+#[get("bots/{name}/messages/{id}")]
+async fn message_status(path: web::Path<(String, String)>, scope: Scope, app_data: web::Data<AppData>) -> impl Responder {
+    let (name, id) = path.into_inner();
+    if let Err(response) = scope.check(&name) {
+        return response;
+    }
+    match app_data.receipts.get(&name, &id) {
+        Some(message) => json_response(json!({ "status": 200, "result": message })),
+        None => error_response(DeliveryError::new(404, "MESSAGE_NOT_TRACKED", format!("{} has no tracked message {}", name, id)))
+    }
+}
+
+/// A page of the account's contact list with `total`, the length of the whole list.
+#[get("bots/{name}/contacts")]
+async fn list_contacts(name: web::Path<String>, query: web::Query<ContactsQuery>, scope: Scope, app_data: web::Data<AppData>) -> impl Responder {
+    if let Err(response) = scope.check(&name) {
+        return response;
+    }
+    let Some(bot) = app_data.bots.get(name.as_str()) else { return unknown_bot(&name) };
+    match bot.list_contacts().await {
+        Ok(contacts) => {
+            let total = contacts.len();
+            let page: Vec<_> = contacts.into_iter().skip(query.offset).take(query.limit.unwrap_or(100)).collect();
+            json_response(json!({ "status": 200, "result": { "total": total, "contacts": page } }))
+        }
+        Err(e) => error_response(DeliveryError::from(&e))
+    }
+}
+
+/// Deletes the contacts named by user id or phone and returns them.
+#[delete("bots/{name}/contacts")]
+async fn delete_contacts(name: web::Path<String>, request: web::Json<DeleteContactsRequest>, scope: Scope, app_data: web::Data<AppData>) -> impl Responder {
+    if let Err(response) = scope.check(&name) {
+        return response;
+    }
+    let Some(bot) = app_data.bots.get(name.as_str()) else { return unknown_bot(&name) };
+    match bot.delete_contacts(&request.contacts).await {
+        Ok(deleted) => json_response(json!({ "status": 200, "result": deleted })),
+        Err(e) => error_response(DeliveryError::from(&e))
+    }
+}
+
+/// Deletes the contacts the bot imported for one-off sends once they are old enough, Telegram
+/// holds large contact lists against the account.
+#[post("bots/{name}/contacts/purge")]
+async fn purge_contacts(name: web::Path<String>, request: web::Json<PurgeContactsRequest>, scope: Scope, app_data: web::Data<AppData>) -> impl Responder {
+    if let Err(response) = scope.check(&name) {
+        return response;
+    }
+    let Some(bot) = app_data.bots.get(name.as_str()) else { return unknown_bot(&name) };
+    let before = chrono::Utc::now().timestamp() - request.older_than_days as i64 * 86400;
+    match bot.purge_contacts(before).await {
+        Ok(deleted) => json_response(json!({ "status": 200, "result": deleted })),
+        Err(e) => error_response(DeliveryError::from(&e))
+    }
+}
+
+/// Recipients the bot holds sends back from, with why they are unreachable and since when.
+#[get("bots/{name}/reachability")]
+async fn list_unreachable(name: web::Path<String>, scope: Scope, app_data: web::Data<AppData>) -> impl Responder {
+    if let Err(response) = scope.check(&name) {
+        return response;
+    }
+    if app_data.bots.get(name.as_str()).is_none() {
+        return unknown_bot(&name);
+    }
+    json_response(json!({ "status": 200, "result": app_data.reachability.list(&name) }))
+}
+
+/// Lets sends to a recipient through again. The recipient is their messenger id or phone.
+#[delete("bots/{name}/reachability/{recipient}")]
+async fn clear_unreachable(path: web::Path<(String, String)>, scope: Scope, app_data: web::Data<AppData>) -> impl Responder {
+    let (name, recipient) = path.into_inner();
+    if let Err(response) = scope.check(&name) {
+        return response;
+    }
+    let Some(bot) = app_data.bots.get(name.as_str()) else { return unknown_bot(&name) };
+    match bot.clear_unreachable(&recipient) {
+        true => json_response(json!({ "status": 200, "result": "cleared" })),
+        false => error_response(DeliveryError::new(404, "RECIPIENT_NOT_FOUND", format!("{} has no unreachable recipient {}", name, recipient)))
+    }
+}
+
+fn error_response(error: DeliveryError) -> HttpResponse {
+    json_response(json!({ "status": error.code, "error": error }))
+}
+
+fn schedule_not_found(id: &str) -> HttpResponse {
+    error_response(DeliveryError::new(404, "SCHEDULE_NOT_FOUND", format!("nothing is scheduled under {}", id)))
+}
+
+/// Holds a send back until `send_at`. `local` schedules wait in this service; `telegram`
+/// ones are sent right away as Telegram scheduled messages, so they go out even while the
+/// service is down.
+#[post("schedule")]
+async fn schedule_message(request: web::Json<ScheduleRequest>, scope: Scope, app_data: web::Data<AppData>) -> impl Responder {
+    let ScheduleRequest { send_at, mode, mut request } = request.into_inner();
+    if let Err(response) = scope.check(&request.messenger) {
+        return response;
+    }
+    if send_at <= chrono::Utc::now().timestamp() {
+        return error_response(DeliveryError::new(400, "SCHEDULE_DATE_INVALID", "send_at must be in the future".to_string()));
+    }
+    let bot_name = request.messenger.clone();
+    let remote = match mode {
+        ScheduleMode::Local => None,
+        ScheduleMode::Telegram => {
+            request.schedule_date = Some(send_at);
+            match command(&app_data, bot_name.clone(), ChannelData::SendMessage(request.clone())).await {
+                Ok(result) => Some(result),
+                Err(error) => return error_response(error)
+            }
         }
     };
-    HttpResponse::Ok()
+    let scheduled = app_data.scheduler.add(bot_name, mode, send_at, request, remote);
+    json_response(json!({ "status": 200, "result": scheduled }))
+}
+
+#[get("schedule")]
+async fn list_scheduled(scope: Scope, app_data: web::Data<AppData>) -> impl Responder {
+    let scheduled: Vec<ScheduledMessage> = app_data.scheduler.list().into_iter()
+        .filter(|message| scope.allows(&message.bot_name))
+        .collect();
+    json_response(json!({ "status": 200, "result": scheduled }))
+}
+
+/// Telegram-side schedules have to be changed in Telegram first, so their bot must be running.
+fn find_scheduled(app_data: &AppData, scope: &Scope, id: &str) -> Result<ScheduledMessage, HttpResponse> {
+    let Some(scheduled) = app_data.scheduler.get(id) else { return Err(schedule_not_found(id)) };
+    scope.check(&scheduled.bot_name)?;
+    if scheduled.mode == ScheduleMode::Telegram && app_data.bots.get(scheduled.account()).is_none() {
+        return Err(unknown_bot(scheduled.account()));
+    }
+    Ok(scheduled)
+}
+
+#[post("schedule/{id}")]
+async fn reschedule_message(id: web::Path<String>, request: web::Json<RescheduleRequest>, scope: Scope, app_data: web::Data<AppData>) -> impl Responder {
+    let scheduled = match find_scheduled(&app_data, &scope, &id) {
+        Ok(scheduled) => scheduled,
+        Err(response) => return response
+    };
+    if request.send_at <= chrono::Utc::now().timestamp() {
+        return error_response(DeliveryError::new(400, "SCHEDULE_DATE_INVALID", "send_at must be in the future".to_string()));
+    }
+    if let (Some(remote), Some(bot)) = (scheduled.remote.as_ref(), app_data.bots.get(scheduled.account())) {
+        if let Err(e) = bot.reschedule(remote, request.send_at).await {
+            return error_response(DeliveryError::from(&e));
+        }
+    }
+    match app_data.scheduler.reschedule(&id, request.send_at) {
+        Some(scheduled) => json_response(json!({ "status": 200, "result": scheduled })),
+        None => schedule_not_found(&id)
+    }
+}
+
+#[delete("schedule/{id}")]
+async fn cancel_scheduled(id: web::Path<String>, scope: Scope, app_data: web::Data<AppData>) -> impl Responder {
+    let scheduled = match find_scheduled(&app_data, &scope, &id) {
+        Ok(scheduled) => scheduled,
+        Err(response) => return response
+    };
+    if let (Some(remote), Some(bot)) = (scheduled.remote.as_ref(), app_data.bots.get(scheduled.account())) {
+        if let Err(e) = bot.cancel_scheduled(remote).await {
+            return error_response(DeliveryError::from(&e));
+        }
+    }
+    match app_data.scheduler.remove(&id) {
+        Some(_) => json_response(json!({ "status": 200 })),
+        None => schedule_not_found(&id)
+    }
+}
+
+#[get("healthz")]
+async fn healthz() -> impl Responder {
+    json_response(json!({ "status": 200 }))
+}
+
+/// Ready when every bot is authorized and its update loop is connected. Answers 503 otherwise,
+/// so a load balancer or orchestrator can act on the HTTP status alone. The probe needs no key,
+/// so it only gives counts, `GET bots` has the details.
+#[get("readyz")]
+async fn readyz(app_data: web::Data<AppData>) -> impl Responder {
+    let names = app_data.bots.names();
+    let mut ready_bots = 0;
+    for bot_name in names.iter() {
+        // A bot that failed to start has no handle and is not ready.
+        let Some(bot) = app_data.bots.get(bot_name) else { continue };
+        if bot.is_authorized().await && bot.status().await.connected {
+            ready_bots += 1;
+        }
+    }
+    let ready = ready_bots == names.len();
+    let code = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    HttpResponse::build(code)
         .content_type(ContentType::json())
-        .body(result.to_string())
-}
\ No newline at end of file
+        .body(json!({ "status": code.as_u16(), "result": { "ready": ready, "bots": names.len(), "ready_bots": ready_bots } }).to_string())
+}
+
+#[get("metrics")]
+async fn metrics(app_data: web::Data<AppData>) -> impl Responder {
+    let channel_depth = app_data.tx.max_capacity() - app_data.tx.capacity();
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(app_data.metrics.render(channel_depth))
+}
@@ -1,26 +1,27 @@
+pub mod rules;
 pub mod telegram;
+pub mod webhook;
 pub mod whatsapp;
 
-use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 use async_trait::async_trait;
 // use grammers_session::PackedChat;
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use tokio::sync::mpsc::Sender;
 use crate::bot::telegram::{TelegramAuth};
 use crate::bot::whatsapp::{WhatsappAuth};
 use crate::structs::*;
-use crate::structs::api::{AddContactRequest, BotHandler, SendMessageRequest, TelegramMessage, UserData};
-use crate::structs::wrapper::ChannelTx;
+use crate::structs::api::{AddContactRequest, ApiRequest, BotButtons, BotContext, BotHandler, BotStatus, ContactImport, ContactInfo, ContactStatus, DeliveryError, DeliveryResult, LoginStatus, SendMessageRequest, TelegramMessage, UserData, UserHandlers};
+use crate::structs::wrapper::{ChannelTx, Dialog, MessageStatus, Reachability, RecipientStatus, TrackedMessage};
 use crate::utils;
 
-#[derive(PartialEq, Serialize, Deserialize)]
+#[derive(PartialEq, Clone, Serialize, Deserialize)]
 pub enum BotAuth {
     TelegramAuth(TelegramAuth),
     WhatsappAuth(WhatsappAuth),
 }
 
-type MessagesMap = HashMap<String, TelegramMessage>;
-
 // #[derive(Debug, Clone)]
 // pub struct BotChat {
 //     pub chat_id: Option<i64>,
@@ -28,6 +29,7 @@ type MessagesMap = HashMap<String, TelegramMessage>;
 //     pub tg_chat: Option<PackedChat>,
 // }
 
+#[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BotContact {
     pub user_id: i64,
@@ -37,23 +39,219 @@ pub struct BotContact {
 #[async_trait]
 pub trait DocaBot: Send + Sync {
     fn get_bot_name(self) -> String;
-    fn add_handler(&mut self, user: UserData, handler: BotHandler) -> utils::Result<()>;
+    fn add_handler(&self, user: UserData, handler: BotHandler) -> utils::Result<()>;
     async fn sign_in(&mut self, bot_name: String, data: auth::AuthData) -> utils::Result<()>;
     async fn sign_out(&self);
-    async fn send_message(&self, data: SendMessageRequest) -> utils::Result<()>;
-    async fn add_contact(&self, data: AddContactRequest) -> utils::Result<()>;
-    async fn get_dialogs(&self) -> utils::Result<crate::bot::MessagesMap>;
+    async fn login_status(&self) -> LoginStatus;
+    async fn status(&self) -> BotStatus;
+    /// Asks the messenger whether the account can still act, where `status` only reports
+    /// what the bot saw last.
+    async fn is_authorized(&self) -> bool;
+    async fn request_login(&self, phone: Option<String>) -> utils::Result<LoginStatus>;
+    async fn submit_code(&self, code: String) -> utils::Result<LoginStatus>;
+    async fn submit_password(&self, password: Option<String>) -> utils::Result<LoginStatus>;
+    async fn send_message(&self, data: SendMessageRequest) -> utils::Result<DeliveryResult>;
+    async fn add_contact(&self, data: AddContactRequest) -> utils::Result<DeliveryResult>;
+    /// Imports the contacts and reports the users they link to, or that they don't, to the
+    /// backend. Phones linked before are not imported again.
+    async fn import_contacts(&self, contacts: Vec<AddContactRequest>) -> utils::Result<Vec<ContactImport>>;
+    /// Moves a message the messenger holds as scheduled, `message` is what its send returned.
+    async fn reschedule(&self, message: &DeliveryResult, send_at: i64) -> utils::Result<()>;
+    async fn cancel_scheduled(&self, message: &DeliveryResult) -> utils::Result<()>;
+    /// Fetches the account's whole dialog list and stores it in place of the last one.
+    async fn sync_dialogs(&self) -> utils::Result<Vec<Dialog>>;
 
     async fn update_profile_status(&self);
     // async fn custom_handler(&mut self, bot_ctx: BotContext, tx: tokio::sync::mpsc::Sender<ChannelData>);
     async fn message_handler(&self, tx: Sender<ChannelTx>);
-    async fn handle_message(&self, user: String, message: String) -> utils::Result<()>;
-    async fn delete_contacts(&self);
+    async fn handle_message(&self, message: TelegramMessage) -> utils::Result<()>;
+    /// The account's whole contact list.
+    async fn list_contacts(&self) -> utils::Result<Vec<ContactInfo>>;
+    /// Deletes the contacts matching a user id or phone of `contacts`, returns those deleted.
+    async fn delete_contacts(&self, contacts: &[String]) -> utils::Result<Vec<ContactInfo>>;
+    /// Deletes the contacts the bot imported before `before`, a unix time.
+    async fn purge_contacts(&self, before: i64) -> utils::Result<Vec<ContactInfo>>;
+    /// Keeps the error for `status`, the bot itself carries on.
+    fn report_error(&self, error: &utils::Error);
+    /// Whether the account already has a chat with the user, judged from what it has seen
+    /// without asking the messenger.
+    fn has_dialog(&self, user: &UserData) -> bool;
+    /// Records a delivery receipt for a sent message and reports it to the backend.
+    fn update_status(&self, id: &str, status: MessageStatus, at: i64);
+    /// Lets sends through to a recipient marked unreachable, returns whether one was.
+    fn clear_unreachable(&self, recipient: &str) -> bool;
+    /// Whether the phone already links to a user in this run, so importing it would be wasted.
+    fn is_linked(&self, phone: &str) -> bool;
 
     fn start_handle(self, tx: Sender<ChannelTx>);
     fn clone_boxed(&self) -> Box<dyn DocaBot>;
 }
 
+/// The last failure of a bot and when it happened, shared between its clones.
+#[derive(Clone, Default)]
+pub struct LastError(Arc<RwLock<Option<(DeliveryError, i64)>>>);
+
+impl LastError {
+    pub fn set(&self, error: &utils::Error) {
+        *self.0.write().unwrap() = Some((error.into(), chrono::Utc::now().timestamp()));
+    }
+
+    /// Fills `last_error` and `last_error_at` of the status.
+    pub fn apply(&self, status: &mut BotStatus) {
+        if let Some((error, at)) = self.0.read().unwrap().clone() {
+            status.last_error = Some(error);
+            status.last_error_at = Some(at);
+        }
+    }
+}
+
+fn handler_key(reply: &str) -> String {
+    reply.trim().to_lowercase()
+}
+
+/// Keys the handlers by their reply, and also by the title of the button that sends it,
+/// so that a typed "Да" resolves the same way as pressing the "Да" button.
+pub(crate) fn build_handler(handler: &BotHandler, buttons: &[BotButtons]) -> BotHandler {
+    let mut result: BotHandler = handler
+        .iter()
+        .map(|(reply, request)| (handler_key(reply), request.clone()))
+        .collect();
+    for btn in buttons {
+        let Some(request) = result.get(&handler_key(&btn.reply)).cloned() else { continue };
+        result.entry(handler_key(&btn.title)).or_insert(request);
+    }
+    result
+}
+
+/// The handlers currently waiting for this user, without consuming them.
+pub(crate) fn peek_handler(handlers: &RwLock<UserHandlers>, user: &str) -> Option<BotHandler> {
+    handlers.read().unwrap().get(user).cloned()
+}
+
+/// Handlers fire once: the user's whole set is dropped as soon as one of them matches.
+pub(crate) fn take_handler(handlers: &RwLock<UserHandlers>, user: &str, reply: &str) -> Option<ApiRequest> {
+    let mut handlers = handlers.write().unwrap();
+    let request = handlers.get(user)?.get(&handler_key(reply))?.clone();
+    handlers.remove(user);
+    Some(request)
+}
+
+pub(crate) async fn send_handler(mut request: ApiRequest, user: &str, context: &BotContext) -> utils::Result<()> {
+    if let Some(data) = request.data.as_object_mut() {
+        data.entry("context").or_insert(json!({
+            "bot": true,
+            "user_id": user,
+        }));
+    }
+    context.call_backend(&request).await?;
+    Ok(())
+}
+
+/// Tells the backend whether a recipient can be written to, with the same `clients` /
+/// `update` call `add_contact` makes. The backend finds the client by messenger id or phone.
+pub(crate) fn report_reachability(context: &BotContext, status: RecipientStatus) {
+    let context = context.clone();
+    tokio::spawn(async move {
+        let request = ApiRequest {
+            api_url: String::new(),
+            object: "clients".to_string(),
+            command: "update".to_string(),
+            data: json!({
+                "messenger_id": status.messenger_id,
+                "phone": status.phone,
+                "bot": status.bot_name,
+                "reachability": status.reachability,
+                "reason": status.reason
+            })
+        };
+        if let Err(e) = context.call_backend(&request).await {
+            log::error!("[{}] Can't report that {} is {}: {}", context.bot_name, status.recipient, status.reachability, e);
+        }
+    });
+}
+
+/// Marks the recipient as unreachable when the error says so.
+pub(crate) fn check_reachability(context: &BotContext, user: &UserData, error: &utils::Error) {
+    let Some(reachability) = Reachability::classify(error.name()) else { return };
+    if let Some(status) = context.reachability.set(&context.bot_name, user, reachability, error.name()) {
+        log::warn!("[{}] {} is {}, further sends are held back", context.bot_name, status.recipient, reachability);
+        report_reachability(context, status);
+    }
+}
+
+/// Lets sends through to the recipient again and tells the backend. Returns whether there was
+/// a status to clear.
+pub(crate) fn clear_reachability(context: &BotContext, messenger_id: Option<&str>, phone: &str, reason: &str) -> bool {
+    let cleared = context.reachability.clear(&context.bot_name, messenger_id, phone);
+    let found = !cleared.is_empty();
+    for mut status in cleared {
+        status.reachability = Reachability::Reachable;
+        status.reason = reason.to_string();
+        status.since = chrono::Utc::now().timestamp();
+        report_reachability(context, status);
+    }
+    found
+}
+
+/// The error for sends held back since the recipient became unreachable.
+pub(crate) fn unreachable(status: &RecipientStatus) -> utils::Error {
+    utils::Error::request(403, "RECIPIENT_UNREACHABLE", format!("{} is {} ({}), clear the status to write to them again", status.recipient, status.reachability, status.reason))
+}
+
+/// Tells the backend that a sent message moved on, as `messages` / `status` with the tracked
+/// message as data. The clinic matches it to the reminder by bot, chat and message id.
+pub(crate) fn report_status(context: &BotContext, messages: Vec<TrackedMessage>) {
+    if messages.is_empty() {
+        return;
+    }
+    let context = context.clone();
+    tokio::spawn(async move {
+        for message in messages {
+            let request = ApiRequest {
+                api_url: String::new(),
+                object: "messages".to_string(),
+                command: "status".to_string(),
+                data: json!(message)
+            };
+            if let Err(e) = context.call_backend(&request).await {
+                log::error!("[{}] Can't report the status of {}: {}", context.bot_name, message.id, e);
+            }
+        }
+    });
+}
+
+/// Tells the backend which user each imported contact links to, with the `clients` / `update`
+/// call a single `add_contact` makes. Numeric CRM ids are sent as numbers, like before.
+pub(crate) fn report_contacts(context: &BotContext, contacts: &[ContactImport]) {
+    let updates: Vec<_> = contacts.iter()
+        .filter(|contact| !contact.api_id.is_empty())
+        .filter(|contact| matches!(contact.status, ContactStatus::Imported | ContactStatus::Linked | ContactStatus::NotOnTelegram))
+        .map(|contact| json!({
+            "id": contact.api_id.parse::<i64>().map(Value::from).unwrap_or_else(|_| Value::from(contact.api_id.clone())),
+            "messenger_id": contact.chat_id,
+            "phone": contact.phone,
+            "status": contact.status
+        }))
+        .collect();
+    if updates.is_empty() {
+        return;
+    }
+    let context = context.clone();
+    tokio::spawn(async move {
+        for data in updates {
+            let request = ApiRequest {
+                api_url: String::new(),
+                object: "clients".to_string(),
+                command: "update".to_string(),
+                data
+            };
+            if let Err(e) = context.call_backend(&request).await {
+                log::error!("[{}] Can't report contact {}: {}", context.bot_name, request.data["id"], e);
+            }
+        }
+    });
+}
+
 #[async_trait]
 impl Clone for Box<dyn DocaBot> {
     fn clone(&self) -> Self {
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::RwLock;
+use std::time::SystemTime;
+use regex::RegexBuilder;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::structs::api::{ApiRequest, TelegramMessage};
+use crate::utils::JsonConfigs;
+
+/// Rules under this key apply to every bot that has no rules of its own.
+const ANY_BOT: &str = "*";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum RuleMatch {
+    Exact(String),
+    CaseInsensitive(String),
+    Regex(String),
+    Keywords(Vec<String>),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReplyRule {
+    #[serde(rename = "match")]
+    pub matcher: RuleMatch,
+    #[serde(default)]
+    pub request: Option<ApiRequest>,
+    #[serde(default)]
+    pub reply: Option<String>,
+}
+
+pub type ReplyRules = HashMap<String, Vec<ReplyRule>>;
+
+impl JsonConfigs for ReplyRules {}
+
+fn substitute(value: &Value, message: &TelegramMessage) -> Value {
+    match value {
+        Value::String(text) => Value::String(text
+            .replace("{user_id}", &message.user)
+            .replace("{text}", &message.text)
+            .replace("{message_id}", &message.id.to_string())),
+        Value::Array(items) => Value::Array(items.iter().map(|item| substitute(item, message)).collect()),
+        Value::Object(fields) => Value::Object(fields.iter()
+            .map(|(key, item)| (key.clone(), substitute(item, message)))
+            .collect()),
+        other => other.clone()
+    }
+}
+
+impl ReplyRule {
+    pub fn matches(&self, text: &str) -> bool {
+        let text = text.trim();
+        match &self.matcher {
+            RuleMatch::Exact(value) => text == value,
+            RuleMatch::CaseInsensitive(value) => text.to_lowercase() == value.to_lowercase(),
+            RuleMatch::Regex(pattern) => match RegexBuilder::new(pattern).case_insensitive(true).build() {
+                Ok(regex) => regex.is_match(text),
+                Err(e) => {
+                    log::error!("Invalid reply rule {}: {}", pattern, e);
+                    false
+                }
+            },
+            RuleMatch::Keywords(keywords) => {
+                let text = text.to_lowercase();
+                keywords.iter().any(|keyword| text.contains(&keyword.to_lowercase()))
+            }
+        }
+    }
+
+    /// The rule's request with `{user_id}`, `{text}` and `{message_id}` filled in.
+    pub fn render_request(&self, message: &TelegramMessage) -> Option<ApiRequest> {
+        let request = self.request.as_ref()?;
+        Some(ApiRequest {
+            data: substitute(&request.data, message),
+            ..request.clone()
+        })
+    }
+}
+
+/// Reply rules read from a JSON file keyed by bot name. The file is re-read whenever its
+/// modification time changes, so edits apply without restarting the process.
+pub struct RuleBook {
+    file_name: String,
+    state: RwLock<(Option<SystemTime>, ReplyRules)>
+}
+
+impl RuleBook {
+    pub fn load(file_name: &str) -> Self {
+        RuleBook {
+            file_name: file_name.to_string(),
+            state: RwLock::new((RuleBook::modified(file_name), ReplyRules::from_file(file_name)))
+        }
+    }
+
+    fn modified(file_name: &str) -> Option<SystemTime> {
+        fs::metadata(file_name).and_then(|metadata| metadata.modified()).ok()
+    }
+
+    fn reload_if_changed(&self) {
+        let modified = RuleBook::modified(&self.file_name);
+        if self.state.read().unwrap().0 == modified {
+            return;
+        }
+        log::info!("Reloading {}", self.file_name);
+        *self.state.write().unwrap() = (modified, ReplyRules::from_file(&self.file_name));
+    }
+
+    pub fn find(&self, bot_name: &str, text: &str) -> Option<ReplyRule> {
+        self.reload_if_changed();
+        let state = self.state.read().unwrap();
+        let rules = state.1.get(bot_name).or(state.1.get(ANY_BOT))?;
+        rules.iter().find(|rule| rule.matches(text)).cloned()
+    }
+}
@@ -1,23 +1,34 @@
+pub mod peers;
+pub mod session;
+
+use std::collections::HashMap;
 use std::default::Default;
-use std::io;
-use std::io::{BufRead, Write};
 use std::ops::ControlFlow;
-use std::time::Duration;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::time::{Duration, UNIX_EPOCH};
 use async_trait::async_trait;
-use grammers_client::{Client, Config, InitParams, InputMessage, SignInError, Update};
+use grammers_client::{button, reply_markup, Client, Config, InitParams, InputMessage, SignInError, Update};
+use grammers_client::types::{Attribute, Chat, InlineQuery, LoginToken, Media, Message, MessageRead, PasswordToken};
+use grammers_client::types::inline_query::Article;
 use grammers_mtsender::{InvocationError, ReconnectionPolicy};
-use grammers_session::{PackedChat, PackedType, Session};
+use grammers_session::{PackedChat, PackedType};
 use grammers_tl_types::enums::{InputContact};
 use grammers_tl_types::types::{InputPhoneContact};
 use serde::{Deserialize, Serialize};
-use serde_json::{json};
+use serde_json::{json, Value};
 use tokio::sync::mpsc::Sender;
-use crate::bot::{BotAuth, DocaBot, MessagesMap};
+use crate::bot::{build_handler, check_reachability, clear_reachability, peek_handler, send_handler, take_handler, report_contacts, report_status, unreachable, BotAuth, DocaBot, LastError};
+use crate::bot::webhook::{self, InboundMessage};
+use crate::structs::auth;
 use crate::structs::auth::AuthData;
-use crate::{SESSION_FOLDER, utils};
-use crate::structs::api::{AddContactRequest, SendMessageRequest, BotHandler, UserHandlers, TelegramMessage, UserData, BotContext};
-use crate::structs::wrapper::{ChannelData, ChannelTx};
+use crate::utils;
+use crate::structs::api::{AddContactRequest, ContactImport, ContactInfo, ContactStatus, SendMessageRequest, BotButtons, BotHandler, UserHandlers, TelegramMessage, UserData, BotContext, BotStatus, DeliveryError, DeliveryResult, LoginStatus, MessageMeta, Attachment, ParseMode};
+use crate::structs::wrapper::{AddedContact, ChannelData, ChannelTx, Dialog, DialogMessage, MessageStatus, PeerKind, TrackedMessage};
+use crate::wrapper::metrics::BotMetrics;
 use crate::utils::JsonConfigs;
+use peers::{pack_user, phone_digits, PeerCache};
+use session::{SessionStorage, StoredSession};
 
 #[derive(PartialEq, Clone, Default, Serialize, Deserialize)]
 pub struct TelegramAuth {
@@ -27,38 +38,50 @@ pub struct TelegramAuth {
 
 impl JsonConfigs for TelegramAuth {}
 
-struct MyPolicy;
+/// Longest pause between attempts of a failing update loop.
+const MAX_RECOVERY_DELAY: Duration = Duration::from_secs(60);
+/// Contacts per `contacts.importContacts` call.
+const IMPORT_BATCH: usize = 100;
+
+/// Counts the reconnection attempts of one bot for `/metrics`. The sender wants a `'static`
+/// policy, so every connection leaks its own instance, which is a few bytes per restart.
+struct MyPolicy {
+    metrics: Arc<BotMetrics>
+}
 
 impl ReconnectionPolicy for MyPolicy {
     fn should_retry(&self, attempts: usize) -> ControlFlow<(), Duration> {
+        self.metrics.reconnects.fetch_add(1, Ordering::Relaxed);
         let duration = u64::pow(2, attempts as _);
         ControlFlow::Continue(Duration::from_millis(duration))
     }
 }
 
-
+/// Where the account is in the login process, driven by the `/bots/{name}/login` endpoints.
+#[derive(Default)]
+pub struct LoginFlow {
+    pub status: LoginStatus,
+    pub auth: auth::TelegramAuth,
+    token: Option<LoginToken>,
+    password_token: Option<PasswordToken>
+}
 
 #[derive(Clone)]
 pub struct Telegram {
     pub client: Client,
-    pub bot_id: i64,
-    pub handlers: UserHandlers,
-    pub dialogs: MessagesMap,
-    pub context: BotContext
-}
-
-fn prompt(message: &str) -> utils::Result<String> {
-    let stdout = io::stdout();
-    let mut stdout = stdout.lock();
-    stdout.write_all(message.as_bytes())?;
-    stdout.flush()?;
-
-    let stdin = io::stdin();
-    let mut stdin = stdin.lock();
-
-    let mut line = String::new();
-    stdin.read_line(&mut line)?;
-    Ok(line)
+    /// Stays 0 until the account is authorized.
+    pub bot_id: Arc<AtomicI64>,
+    pub handlers: Arc<RwLock<UserHandlers>>,
+    pub context: BotContext,
+    pub login: Arc<tokio::sync::Mutex<LoginFlow>>,
+    /// Set for BotFather accounts, which can't import contacts or list dialogs.
+    pub is_bot: Arc<AtomicBool>,
+    pub connected: Arc<AtomicBool>,
+    /// Unix time of the last successful `get_updates`, 0 before the first one.
+    pub last_update: Arc<AtomicI64>,
+    pub peers: Arc<PeerCache>,
+    pub session: Arc<StoredSession>,
+    pub last_error: LastError
 }
 
 impl Telegram {
@@ -68,42 +91,543 @@ impl Telegram {
     //     Ok(contacts)
     // }
 
-
-    pub async fn new(bot_name: String, cfg: BotAuth, ctx: BotContext) -> Self {
-        println!("Connecting to Telegram...");
+    pub async fn new(bot_name: String, cfg: BotAuth, ctx: BotContext, sessions: Arc<dyn SessionStorage>) -> utils::Result<Self> {
+        log::info!("[{}] Connecting to Telegram", bot_name);
         let auth = match cfg {
             BotAuth::TelegramAuth(data) => data,
             _ => {
-                println!("No auth data provided!");
+                log::warn!("[{}] No auth data provided", bot_name);
                 TelegramAuth::default()
             }
         };
         let api_id = auth.app_id;
-        let session_file = format!("configs/{}/{}.session", SESSION_FOLDER, bot_name);
-        let session = Session::load_file_or_create(session_file).unwrap();
+        let (stored_session, session) = StoredSession::open(sessions, &bot_name)?;
         let client = Client::connect(Config {
             session,
             api_id,
             api_hash: auth.app_hash.clone(),
             params: InitParams {
-                reconnection_policy: &MyPolicy,
+                reconnection_policy: Box::leak(Box::new(MyPolicy { metrics: ctx.metrics.clone() })),
                 ..Default::default()
             },
-        }).await.unwrap();
-        let bot_id = client.get_me().await.unwrap().id();
-        let dialogs: MessagesMap = MessagesMap::default();
-        Telegram {
+        }).await?;
+        let bot_id = match client.is_authorized().await {
+            Ok(true) => client.get_me().await.map(|me| me.id()).unwrap_or_default(),
+            _ => 0
+        };
+        Ok(Telegram {
             client,
-            bot_id,
-            handlers: Default::default(),
-            dialogs: dialogs.clone(),
-            context: ctx
+            bot_id: Arc::new(AtomicI64::new(bot_id)),
+            handlers: Arc::new(RwLock::new(UserHandlers::default())),
+            context: ctx,
+            login: Arc::new(tokio::sync::Mutex::new(LoginFlow::default())),
+            is_bot: Arc::new(AtomicBool::new(false)),
+            connected: Arc::new(AtomicBool::new(true)),
+            last_update: Arc::new(AtomicI64::new(0)),
+            peers: Arc::new(PeerCache::default()),
+            session: Arc::new(stored_session),
+            last_error: LastError::default()
+        })
+    }
+
+    fn is_bot(&self) -> bool {
+        self.is_bot.load(Ordering::Relaxed)
+    }
+
+    fn not_for_bots(method: &str) -> utils::Error {
+        utils::Error::request(400, "BOT_METHOD_INVALID", format!("{} is not available to bot accounts", method))
+    }
+
+    /// The peer and id of a scheduled message from what its send returned.
+    fn scheduled_message(message: &DeliveryResult) -> utils::Result<(grammers_tl_types::enums::InputPeer, i32)> {
+        let (Some(chat_id), Some(id)) = (message.chat_id, message.message_id) else {
+            return Err(utils::Error::request(400, "MESSAGE_ID_INVALID", "the schedule has no Telegram message"));
+        };
+        let chat = PackedChat { ty: PackedType::User, id: chat_id, access_hash: message.access_hash };
+        Ok((chat.to_input_peer(), id))
+    }
+
+    /// Offers the auto-reply of the reply rule matching the query, if there is one.
+    async fn answer_inline_query(&self, query: InlineQuery) -> Result<(), InvocationError> {
+        let reply = self.context.rules
+            .find(&self.context.bot_name, query.text())
+            .and_then(|rule| rule.reply);
+        let results = reply
+            .map(|reply| Article::new(reply.clone(), InputMessage::text(reply)).into())
+            .into_iter();
+        query.answer(results).private().send().await
+    }
+
+    /// Keeps the peer in memory and in the session's peer table, which is saved with the
+    /// session and survives restarts.
+    fn remember_peer(&self, chat: PackedChat, username: Option<&str>, phone: Option<&str>) {
+        self.peers.insert(chat, username, phone);
+        self.client.session().insert_peer(chat, username, phone);
+    }
+
+    /// The chat as the dialog list shows it, before any message is added.
+    fn dialog_peer(chat: &Chat) -> Dialog {
+        let (kind, phone) = match chat {
+            Chat::User(user) => (PeerKind::User, user.phone().map(String::from)),
+            Chat::Group(_) => (PeerKind::Group, None),
+            Chat::Channel(_) => (PeerKind::Channel, None)
+        };
+        let packed = chat.pack();
+        Dialog {
+            chat_id: packed.id,
+            kind,
+            name: chat.name().to_string(),
+            username: chat.username().map(String::from),
+            phone,
+            access_hash: packed.access_hash,
+            unread_count: 0,
+            last_message: None
+        }
+    }
+
+    fn dialog_message(message: &Message) -> DialogMessage {
+        DialogMessage {
+            id: message.id(),
+            text: message.text().to_string(),
+            date: message.date().timestamp(),
+            outgoing: message.outgoing()
+        }
+    }
+
+    /// Sends the message, `send_message` decides beforehand whether the recipient can get it.
+    async fn deliver(&self, data: &SendMessageRequest) -> utils::Result<DeliveryResult> {
+        let recipient = self.resolve_recipient(&data.user, data.access_hash).await?;
+        let chat_id = recipient.id;
+        let message = self.build_message(data).await?;
+        let sent = self.client.send_message(recipient, message).await?;
+        if data.access_hash.is_some() {
+            self.remember_peer(recipient, None, None);
+        }
+        if let Some(handler) = data.handlers.as_ref() {
+            let buttons = data.buttons.clone().unwrap_or_default();
+            self.add_handler(
+                UserData { messenger_id: Some(chat_id.to_string()), ..data.user.clone() },
+                build_handler(handler, &buttons)
+            )?;
+        }
+        self.record_dialog(&sent);
+        let chat = sent.chat().pack();
+        // Scheduled messages get another id once Telegram sends them, only private chats have
+        // read receipts worth following.
+        if data.schedule_date.is_none() && chat.ty == PackedType::User {
+            let mut tracked = TrackedMessage::sent(&self.context.bot_name, format!("{}:{}", chat.id, sent.id()), sent.date().timestamp());
+            tracked.chat_id = Some(chat.id);
+            tracked.access_hash = chat.access_hash;
+            tracked.message_id = Some(sent.id());
+            self.context.receipts.track(tracked);
+        }
+        Ok(DeliveryResult {
+            message_id: Some(sent.id()),
+            chat_id: Some(chat.id),
+            access_hash: chat.access_hash,
+            ..Default::default()
+        })
+    }
+
+    /// Keeps the dialog list current between syncs.
+    fn record_dialog(&self, message: &Message) {
+        let mut dialog = Telegram::dialog_peer(&message.chat());
+        dialog.unread_count = match message.outgoing() {
+            true => 0,
+            false => 1
+        };
+        dialog.last_message = Some(Telegram::dialog_message(message));
+        self.context.dialogs.record(&self.context.bot_name, dialog);
+    }
+
+    /// Keeps the unread count of a chat read on another device or client.
+    fn read_dialog(&self, read: &MessageRead) {
+        let Some(unread_count) = read.still_unread_count() else { return };
+        let chat_id = match read.peer() {
+            grammers_tl_types::enums::Peer::User(peer) => peer.user_id,
+            grammers_tl_types::enums::Peer::Chat(peer) => peer.chat_id,
+            grammers_tl_types::enums::Peer::Channel(peer) => peer.channel_id
+        };
+        self.context.dialogs.mark_read(&self.context.bot_name, chat_id, unread_count);
+    }
+
+    /// Marks what the user read up to `max_id` and reports it.
+    fn read_receipt(&self, peer: &grammers_tl_types::enums::Peer, max_id: i32) {
+        let grammers_tl_types::enums::Peer::User(user) = peer else { return };
+        let now = chrono::Utc::now().timestamp();
+        let read = self.context.receipts.read_up_to(&self.context.bot_name, user.user_id, max_id, now);
+        report_status(&self.context, read);
+    }
+
+    /// Read receipts missed while offline, from the `read_outbox_max_id` of every chat that
+    /// still has unread messages.
+    async fn catch_up_receipts(&self) -> utils::Result<()> {
+        let chats = self.context.receipts.unread_chats(&self.context.bot_name);
+        for batch in chats.chunks(100) {
+            let Err(e) = self.read_dialogs(batch).await else { continue };
+            if e.code() != 400 {
+                return Err(e);
+            }
+            // A single peer Telegram doesn't accept fails the whole batch, so the chats are
+            // asked for one by one and the bad ones skipped.
+            for chat in batch {
+                match self.read_dialogs(std::slice::from_ref(chat)).await {
+                    Err(e) if e.code() == 400 => log::warn!("[{}] Skipping read receipts of chat {}: {}", self.context.bot_name, chat.0, e),
+                    result => result?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn read_dialogs(&self, chats: &[(i64, Option<i64>)]) -> utils::Result<()> {
+        let peers = chats.iter()
+            .map(|&(id, access_hash)| grammers_tl_types::types::InputDialogPeer {
+                peer: PackedChat { ty: PackedType::User, id, access_hash }.to_input_peer()
+            }.into())
+            .collect();
+        let grammers_tl_types::enums::messages::PeerDialogs::Dialogs(result) = self.client
+            .invoke(&grammers_tl_types::functions::messages::GetPeerDialogs { peers })
+            .await?;
+        for dialog in result.dialogs {
+            if let grammers_tl_types::enums::Dialog::Dialog(dialog) = dialog {
+                self.read_receipt(&dialog.peer, dialog.read_outbox_max_id);
+            }
+        }
+        Ok(())
+    }
+
+    fn save_session(&self) {
+        if let Err(e) = self.session.save(self.client.session()) {
+            log::error!("[{}] Failed to save the session: {}", self.context.bot_name, e);
         }
     }
 
+    async fn finish_login(&self, login: &mut LoginFlow) -> utils::Result<LoginStatus> {
+        let me = self.client.get_me().await?;
+        self.bot_id.store(me.id(), Ordering::Relaxed);
+        login.status = LoginStatus::Authorized;
+        login.token = None;
+        login.password_token = None;
+        log::info!("[{}] Signed in", self.context.bot_name);
+        self.save_session();
+        Ok(login.status.clone())
+    }
+
     async fn get_updates(&self) -> Result<Option<Update>, InvocationError> {
-        Ok(self.client.get_updates_m().await?)
+        self.client.get_updates_m().await
+    }
+
+    /// Finds who a request is addressed to, in order: the caller's id and access hash, the
+    /// peers seen so far, an "@username", and the phone number, imported as a contact if needed.
+    async fn resolve_recipient(&self, user: &UserData, access_hash: Option<i64>) -> utils::Result<PackedChat> {
+        let recipient = user.messenger_id.as_deref().map(str::trim).filter(|id| !id.is_empty());
+        let id = recipient.and_then(|id| id.parse::<i64>().ok());
+        if let Some(id) = id {
+            // Remembered only once a send went through, the hash may be another account's.
+            if access_hash.is_some() {
+                return Ok(PackedChat { ty: PackedType::User, id, access_hash });
+            }
+            if let Some(chat) = self.peers.by_id(id).or_else(|| self.client.known_chat(id)) {
+                return Ok(chat);
+            }
+        }
+        let username = [recipient, Some(user.phone.trim())].into_iter().flatten().find(|name| name.starts_with('@'));
+        if let Some(username) = username {
+            if let Some(chat) = self.peers.by_username(username).or_else(|| self.client.session().get_peer_by_username(username)) {
+                return Ok(chat);
+            }
+            let resolved = self.client.resolve_username(username.trim_start_matches('@')).await?;
+            let Some(chat) = resolved else {
+                return Err(utils::Error::request(400, "USERNAME_NOT_OCCUPIED", format!("{} is not taken by anyone", username)));
+            };
+            self.remember_peer(chat.pack(), Some(username), None);
+            return Ok(chat.pack());
+        }
+        let phone = phone_digits(&user.phone);
+        if !phone.is_empty() {
+            if let Some(chat) = self.peers.by_phone(&phone).or_else(|| self.client.session().get_peer_by_phone(&phone)) {
+                return Ok(chat);
+            }
+            if !self.is_bot() {
+                if let Some(chat) = self.resolve_phone(&phone).await? {
+                    return Ok(chat);
+                }
+            }
+        }
+        match id {
+            // Bots may write to users that contacted them without knowing the access hash.
+            Some(id) if self.is_bot() => Ok(PackedChat { ty: PackedType::User, id, access_hash: None }),
+            _ => Err(DeliveryError::new(400, "PEER_NOT_RESOLVED", "recipient is not known by id, username or phone".to_string()).into())
+        }
+    }
+
+    /// `contacts.resolvePhone` only finds numbers whose privacy settings allow it, otherwise
+    /// (`PHONE_NOT_OCCUPIED`) the number is imported as a contact.
+    async fn resolve_phone(&self, phone: &str) -> utils::Result<Option<PackedChat>> {
+        let resolved = self.client
+            .invoke(&grammers_tl_types::functions::contacts::ResolvePhone { phone: phone.to_string() })
+            .await;
+        let chat = match resolved {
+            Ok(grammers_tl_types::enums::contacts::ResolvedPeer::Peer(resolved)) => match resolved.peer {
+                grammers_tl_types::enums::Peer::User(peer) => pack_user(&resolved.users, peer.user_id),
+                _ => None
+            },
+            // Hidden by privacy settings or not on Telegram. Anything else, flood waits
+            // included, is passed up rather than spending a contact import on it.
+            Err(e) if e.is("PHONE_NOT_OCCUPIED") => None,
+            Err(e) => return Err(e.into())
+        };
+        let chat = match chat {
+            Some(chat) => Some(chat),
+            None => {
+                let contact = AddContactRequest { phone: phone.to_string(), first_name: phone.to_string(), ..Default::default() };
+                self.import_phones(&[contact]).await?.into_iter()
+                    .find(|contact| contact.status == ContactStatus::Imported)
+                    .and_then(|contact| Some(PackedChat { ty: PackedType::User, id: contact.chat_id?, access_hash: contact.access_hash }))
+            }
+        };
+        if let Some(chat) = chat {
+            self.remember_peer(chat, None, Some(phone));
+        }
+        Ok(chat)
     }
+
+    /// Imports the phones not linked yet, `IMPORT_BATCH` per call. Each distinct phone gets its
+    /// own `client_id`, which is how Telegram's answer finds its way back to every contact
+    /// that asked for the phone. Contacts not reached before an error stay `retry`.
+    async fn import_phones(&self, contacts: &[AddContactRequest]) -> utils::Result<Vec<ContactImport>> {
+        let mut results: Vec<ContactImport> = contacts.iter()
+            .map(|contact| ContactImport::new(contact, ContactStatus::Retry))
+            .collect();
+        let mut phones: Vec<(String, Vec<usize>)> = Vec::new();
+        let mut pending: Vec<InputContact> = Vec::new();
+        for (index, contact) in contacts.iter().enumerate() {
+            if let Some(chat) = self.peers.by_phone(&contact.phone) {
+                results[index].status = ContactStatus::Linked;
+                results[index].chat_id = Some(chat.id);
+                results[index].access_hash = chat.access_hash;
+                continue;
+            }
+            let digits = phone_digits(&contact.phone);
+            if digits.is_empty() {
+                results[index].status = ContactStatus::NotOnTelegram;
+                continue;
+            }
+            if let Some((_, indexes)) = phones.iter_mut().find(|(phone, _)| *phone == digits) {
+                indexes.push(index);
+                continue;
+            }
+            pending.push(InputContact::InputPhoneContact(InputPhoneContact {
+                client_id: phones.len() as i64,
+                phone: contact.phone.clone(),
+                first_name: contact.first_name.clone(),
+                last_name: contact.last_name.clone()
+            }));
+            phones.push((digits, vec![index]));
+        }
+        for (batch, chunk) in pending.chunks(IMPORT_BATCH).enumerate() {
+            let response = self.client
+                .invoke(&grammers_tl_types::functions::contacts::ImportContacts { contacts: chunk.to_vec() })
+                .await;
+            let grammers_tl_types::enums::contacts::ImportedContacts::Contacts(data) = match response {
+                Ok(data) => data,
+                Err(e) if batch == 0 => return Err(e.into()),
+                Err(e) => {
+                    log::error!("[{}] Contact import stopped: {}", self.context.bot_name, e);
+                    break;
+                }
+            };
+            let imported: HashMap<i64, i64> = data.imported.iter()
+                .map(|grammers_tl_types::enums::ImportedContact::Contact(contact)| (contact.client_id, contact.user_id))
+                .collect();
+            for InputContact::InputPhoneContact(contact) in chunk {
+                let (phone, indexes) = &phones[contact.client_id as usize];
+                let chat = imported.get(&contact.client_id).map(|user_id| pack_user(&data.users, *user_id)
+                    .unwrap_or(PackedChat { ty: PackedType::User, id: *user_id, access_hash: None }));
+                let status = match chat {
+                    Some(chat) => {
+                        self.remember_peer(chat, None, Some(phone));
+                        self.context.contacts.record(&self.context.bot_name, AddedContact {
+                            user_id: chat.id,
+                            phone: contact.phone.clone(),
+                            added_at: chrono::Utc::now().timestamp()
+                        });
+                        ContactStatus::Imported
+                    }
+                    None if data.retry_contacts.contains(&contact.client_id) => ContactStatus::Retry,
+                    None => ContactStatus::NotOnTelegram
+                };
+                for index in indexes {
+                    results[*index].status = status;
+                    results[*index].chat_id = chat.map(|chat| chat.id);
+                    results[*index].access_hash = chat.and_then(|chat| chat.access_hash);
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    async fn fetch_contacts(&self) -> utils::Result<Vec<ContactInfo>> {
+        let response = self.client
+            .invoke(&grammers_tl_types::functions::contacts::GetContacts { hash: 0 })
+            .await?;
+        // `contactsNotModified` only answers a known hash.
+        let grammers_tl_types::enums::contacts::Contacts::Contacts(list) = response else { return Ok(Vec::new()) };
+        let mutual: HashMap<i64, bool> = list.contacts.iter()
+            .map(|grammers_tl_types::enums::Contact::Contact(contact)| (contact.user_id, contact.mutual))
+            .collect();
+        Ok(list.users.iter()
+            .filter_map(|user| match user {
+                grammers_tl_types::enums::User::User(user) => Some(user),
+                grammers_tl_types::enums::User::Empty(_) => None
+            })
+            .map(|user| ContactInfo {
+                user_id: user.id,
+                access_hash: user.access_hash,
+                phone: user.phone.clone().unwrap_or_default(),
+                first_name: user.first_name.clone().unwrap_or_default(),
+                last_name: user.last_name.clone().unwrap_or_default(),
+                username: user.username.clone(),
+                mutual: mutual.get(&user.id).copied().unwrap_or(user.mutual_contact),
+                added_at: self.context.contacts.get(&self.context.bot_name, user.id).map(|contact| contact.added_at)
+            })
+            .collect())
+    }
+
+    /// Contacts without an access hash can't be named in `contacts.deleteContacts`, they are
+    /// left in place and not returned.
+    async fn remove_contacts(&self, contacts: Vec<ContactInfo>) -> utils::Result<Vec<ContactInfo>> {
+        let (contacts, skipped): (Vec<_>, Vec<_>) = contacts.into_iter().partition(|contact| contact.access_hash.is_some());
+        for contact in skipped {
+            log::warn!("[{}] Can't delete contact {} without its access hash", self.context.bot_name, contact.user_id);
+        }
+        for chunk in contacts.chunks(IMPORT_BATCH) {
+            let id = chunk.iter()
+                .filter_map(|contact| Some(grammers_tl_types::enums::InputUser::User(grammers_tl_types::types::InputUser {
+                    user_id: contact.user_id,
+                    access_hash: contact.access_hash?
+                })))
+                .collect();
+            self.client.invoke(&grammers_tl_types::functions::contacts::DeleteContacts { id }).await?;
+            let deleted: Vec<i64> = chunk.iter().map(|contact| contact.user_id).collect();
+            self.context.contacts.forget(&self.context.bot_name, &deleted);
+        }
+        Ok(contacts)
+    }
+
+    /// The buttons to attach as an inline keyboard. User accounts can't send one, their
+    /// recipients type the button titles, which the handlers match just the same.
+    pub fn check_buttons(is_bot: bool, data: &SendMessageRequest) -> Option<&[BotButtons]> {
+        data.buttons.as_deref().filter(|buttons| is_bot && !buttons.is_empty())
+    }
+
+    /// Maps the request onto an `InputMessage`: formatting, attachment and delivery options.
+    async fn build_message(&self, data: &SendMessageRequest) -> utils::Result<InputMessage> {
+        let message = match data.parse_mode {
+            ParseMode::Text => InputMessage::text(&data.message),
+            ParseMode::Markdown => InputMessage::markdown(&data.message),
+            ParseMode::Html => InputMessage::html(&data.message)
+        };
+        let message = match data.attachment.as_ref() {
+            Some(attachment) => self.attach(message, attachment).await?,
+            None => message
+        };
+        let message = message
+            .reply_to(data.reply_to)
+            .silent(data.silent)
+            .schedule_date(data.schedule_date.map(|date| UNIX_EPOCH + Duration::from_secs(date.max(0) as u64)));
+        let Some(buttons) = Telegram::check_buttons(self.is_bot(), data) else {
+            return Ok(message);
+        };
+        let row: Vec<button::Inline> = buttons
+            .iter()
+            .map(|btn| button::inline(btn.title.clone(), btn.reply.clone()))
+            .collect();
+        Ok(message.reply_markup(&reply_markup::inline(vec![row])))
+    }
+
+    /// Inline `data` is uploaded first, a `url` is left for Telegram to download.
+    async fn attach(&self, message: InputMessage, attachment: &Attachment) -> utils::Result<InputMessage> {
+        let source = match attachment {
+            Attachment::Location { latitude, longitude } => return Ok(message.location(*latitude, *longitude)),
+            Attachment::Photo(source) | Attachment::Document(source) | Attachment::Voice { source, .. } => source
+        };
+        let message = match source.mime_type.as_ref() {
+            Some(mime_type) => message.mime_type(mime_type),
+            None => message
+        };
+        let bytes = source.bytes()?;
+        let uploaded = match bytes {
+            Some(bytes) => {
+                let size = bytes.len();
+                let mut stream = std::io::Cursor::new(bytes);
+                Some(self.client.upload_stream(&mut stream, size, source.file_name.clone()).await?)
+            }
+            None => None
+        };
+        let message = match (attachment, uploaded, source.url.as_ref()) {
+            (Attachment::Photo(_), Some(file), _) => message.photo(file),
+            (Attachment::Photo(_), None, Some(url)) => message.photo_url(url),
+            (Attachment::Voice { duration, .. }, Some(file), _) => {
+                let message = if source.mime_type.is_none() { message.mime_type("audio/ogg") } else { message };
+                message.document(file).attribute(Attribute::Voice {
+                    duration: Duration::from_secs(*duration),
+                    waveform: None
+                })
+            }
+            (_, Some(file), _) => message.document(file),
+            (_, None, Some(url)) => message.document_url(url),
+            (_, None, None) => return Err(utils::Error::request(400, "MEDIA_EMPTY", "attachment needs a url or data"))
+        };
+        Ok(message)
+    }
+
+    /// What the webhook receives about an inbound message besides its plain text.
+    fn message_meta(message: &Message) -> MessageMeta {
+        let (username, phone) = match message.sender().or(Some(message.chat())) {
+            Some(Chat::User(user)) => (user.username().map(String::from), user.phone().map(String::from)),
+            Some(chat) => (chat.username().map(String::from), None),
+            None => (None, None)
+        };
+        MessageMeta {
+            username,
+            phone,
+            markdown: message.markdown_text(),
+            html: message.html_text(),
+            media: message.media().map(|media| Telegram::media_descriptor(&media)),
+            reply_to: message.reply_to_message_id(),
+            date: message.date().timestamp()
+        }
+    }
+
+    fn media_descriptor(media: &Media) -> Value {
+        match media {
+            Media::Photo(photo) => json!({ "type": "photo", "id": photo.id() }),
+            Media::Document(document) => json!({
+                "type": "document",
+                "id": document.id(),
+                "name": document.name(),
+                "mime_type": document.mime_type(),
+                "size": document.size()
+            }),
+            Media::Sticker(sticker) => json!({ "type": "sticker", "emoji": sticker.emoji() }),
+            Media::Contact(contact) => json!({
+                "type": "contact",
+                "phone": contact.phone_number(),
+                "first_name": contact.first_name(),
+                "last_name": contact.last_name()
+            }),
+            Media::Geo(geo) => json!({ "type": "geo", "latitude": geo.latitue(), "longitude": geo.longitude() }),
+            Media::GeoLive(geo) => json!({ "type": "geo_live", "period": geo.period() }),
+            Media::Venue(venue) => json!({ "type": "venue", "title": venue.title(), "address": venue.address() }),
+            Media::Poll(poll) => json!({ "type": "poll", "question": poll.question() }),
+            Media::Dice(dice) => json!({ "type": "dice", "emoji": dice.emoji(), "value": dice.value() }),
+            Media::WebPage(_) => json!({ "type": "web_page" }),
+            _ => json!({ "type": "unknown" })
+        }
+    }
+
 }
 
 #[async_trait]
@@ -112,207 +636,398 @@ impl DocaBot for Telegram {
         String::from("telegram")
     }
 
-    fn add_handler(&mut self, user: UserData, handler: BotHandler) -> utils::Result<()> {
-        if user.messenger_id.is_none() {
-            return Ok({});
-        }
-        self.handlers.insert(user.messenger_id.unwrap(), handler);
-        Ok({})
+    fn add_handler(&self, user: UserData, handler: BotHandler) -> utils::Result<()> {
+        let Some(user_id) = user.messenger_id else { return Ok(()) };
+        self.handlers.write().unwrap().insert(user_id, handler);
+        Ok(())
     }
 
+    /// Never blocks: an account without a valid session is left in [`LoginStatus::PendingLogin`]
+    /// until the login endpoints complete it.
     async fn sign_in(&mut self, bot_name: String, data: AuthData) -> utils::Result<()> {
-        if self.client.is_authorized().await? { return Ok(()); }
         let AuthData::Telegram(auth_data) = data else { return Ok(()) };
-        println!("Signing in...");
-        let token = self.client.request_login_code(&auth_data.username).await?;
-
-        let prompt_text = format!( "{} Code: ", bot_name );
-        let code = prompt( &prompt_text ).unwrap();
-        let signed_in = self.client.sign_in(&token, &code).await;
-        match signed_in {
-            Err(SignInError::PasswordRequired(password_token)) => {
-                self.client
-                    .check_password(password_token, auth_data.password.trim())
-                    .await?;
-            }
-            Ok(_) => (),
-            Err(e) => panic!("{}", e),
-        };
-        println!("Signed in!");
-        let session_file = format!("configs/{}/{}.session", SESSION_FOLDER, bot_name);
-        match self.client.session().save_to_file(session_file) {
-            Ok(_) => {}
-            Err(e) => {
-                println!(
-                    "NOTE: failed to save the session, will sign out when done: {}",
-                    e
-                );
-            }
+        let mut login = self.login.lock().await;
+        login.auth = auth_data;
+        self.is_bot.store(login.auth.is_bot(), Ordering::Relaxed);
+        if self.client.is_authorized().await? {
+            self.finish_login(&mut login).await?;
+        } else if let Some(token) = login.auth.bot_token.clone() {
+            self.client.bot_sign_in(&token).await?;
+            self.finish_login(&mut login).await?;
+        } else {
+            log::info!("[{}] Waiting for login", bot_name);
+            login.status = LoginStatus::PendingLogin;
         }
         Ok(())
     }
+
     async fn sign_out(&self) {
         drop(self.client.sign_out_disconnect().await);
+        // Parks the update loop, the disconnected client would only fail from now on.
+        self.bot_id.store(0, Ordering::Relaxed);
+        self.session.release();
     }
 
-    async fn send_message(&self, data: SendMessageRequest) -> utils::Result<()> {
-        let message = InputMessage::from(data.message.as_str());
-        if data.user.messenger_id.is_none() {
-            return Ok(());
+    async fn login_status(&self) -> LoginStatus {
+        self.login.lock().await.status.clone()
+    }
+
+    async fn status(&self) -> BotStatus {
+        let last_update = self.last_update.load(Ordering::Relaxed);
+        let mut status = BotStatus {
+            messenger: "telegram".to_string(),
+            connected: self.connected.load(Ordering::Relaxed),
+            authorized: self.bot_id.load(Ordering::Relaxed) != 0,
+            login: self.login_status().await,
+            last_update: (last_update > 0).then_some(last_update),
+            ..Default::default()
+        };
+        self.last_error.apply(&mut status);
+        status
+    }
+
+    async fn is_authorized(&self) -> bool {
+        self.bot_id.load(Ordering::Relaxed) != 0 && self.client.is_authorized().await.unwrap_or(false)
+    }
+
+    async fn request_login(&self, phone: Option<String>) -> utils::Result<LoginStatus> {
+        let mut login = self.login.lock().await;
+        if let Some(token) = login.auth.bot_token.clone() {
+            self.client.bot_sign_in(&token).await?;
+            return self.finish_login(&mut login).await;
         }
-        let chat_id = data.user.messenger_id.unwrap().parse::<i64>().unwrap();
-        self.client.send_message(PackedChat {
-            id: chat_id,
-            ty: PackedType::User,
-            access_hash: data.access_hash,
-        }, message).await?;
-        Ok(())
+        if let Some(phone) = phone {
+            login.auth.username = phone;
+        }
+        let token = self.client.request_login_code(&login.auth.username).await?;
+        login.token = Some(token);
+        login.password_token = None;
+        login.status = LoginStatus::CodeSent;
+        Ok(login.status.clone())
     }
 
-    async fn get_dialogs(&self) -> utils::Result<MessagesMap> {
-        let mut dialogs_list: MessagesMap = MessagesMap::default();
-        let mut dialogs_iter = self.client.iter_dialogs();
-        let mut counter = 0;
+    async fn submit_code(&self, code: String) -> utils::Result<LoginStatus> {
+        let mut login = self.login.lock().await;
+        let Some(token) = login.token.take() else {
+            return Err(utils::Error::request(400, "LOGIN_NOT_STARTED", "request a login code first"))
+        };
+        match self.client.sign_in(&token, code.trim()).await {
+            Ok(_) => self.finish_login(&mut login).await,
+            Err(SignInError::PasswordRequired(password_token)) => {
+                login.status = LoginStatus::PasswordRequired { hint: password_token.hint().map(String::from) };
+                login.password_token = Some(password_token);
+                Ok(login.status.clone())
+            }
+            Err(SignInError::InvalidCode) => {
+                login.token = Some(token);
+                Err(SignInError::InvalidCode.into())
+            }
+            Err(e) => Err(e.into())
+        }
+    }
 
-        while let Some(dialog) = dialogs_iter.next().await? {
-            if counter >= 5 {
-                break;
+    async fn submit_password(&self, password: Option<String>) -> utils::Result<LoginStatus> {
+        let mut login = self.login.lock().await;
+        let Some(password_token) = login.password_token.take() else {
+            return Err(utils::Error::request(400, "PASSWORD_NOT_REQUESTED", "the account did not ask for a password"))
+        };
+        let password = password.unwrap_or(login.auth.password.clone());
+        match self.client.check_password(password_token.clone(), password.trim()).await {
+            Ok(_) => self.finish_login(&mut login).await,
+            Err(SignInError::InvalidPassword) => {
+                login.password_token = Some(password_token);
+                Err(SignInError::InvalidPassword.into())
             }
-            dialogs_list.insert(
-                dialog.chat.id().to_string(),
-                TelegramMessage::from(TelegramMessage {
-                    id: dialog.dialog.top_message().clone(),
-                    ctx: dialog.chat.pack(),
-                    user: dialog.chat.id().to_string(),
-                    text: "".to_string()
-                })
-            );
-            counter += 1;
+            Err(e) => Err(e.into())
         }
-        Ok(dialogs_list)
     }
 
-    async fn add_contact(&self, new_contact: AddContactRequest) -> utils::Result<()> {
-        let mut test_import = Vec::new();
-        test_import.push(InputContact::InputPhoneContact(
-            InputPhoneContact {
-                last_name: new_contact.last_name,
-                first_name: new_contact.first_name,
-                client_id: 0,
-                phone: new_contact.phone,
+    async fn send_message(&self, data: SendMessageRequest) -> utils::Result<DeliveryResult> {
+        if let Some(status) = self.context.reachability.get(&self.context.bot_name, &data.user) {
+            return Err(unreachable(&status));
+        }
+        let mut result = self.deliver(&data).await;
+        // A caller's access hash only works for the account that handed it out. When it is
+        // rejected the peer is resolved again, and the recipient isn't marked invalid over it.
+        if data.access_hash.is_some() && matches!(&result, Err(e) if e.name() == "PEER_ID_INVALID") {
+            result = self.deliver(&SendMessageRequest { access_hash: None, ..data.clone() }).await;
+            if matches!(&result, Err(e) if e.name() == "PEER_ID_INVALID") {
+                return result;
             }
-        ));
-        match self.client.invoke(&grammers_tl_types::functions::contacts::ImportContacts {
-            contacts: test_import
-        }).await {
-            Ok(grammers_tl_types::enums::contacts::ImportedContacts::Contacts(data)) => {
-                if data.imported.first().is_none() {
-                    return Ok({})
-                }
-                let imported_contact = data.imported.first().unwrap();
-                match imported_contact {
-                    grammers_tl_types::enums::ImportedContact::Contact(data) => {
-                        let client_id = new_contact.api_id.parse::<i32>().unwrap();
-                        let request = json!({
-                            "object": "clients",
-                            "command": "update",
-                            "data": {
-                                "id": client_id,
-                                "messenger_id": data.user_id
-                            }
-                        });
-                        let _ = reqwest::Client::new()
-                            .post(&self.context.api_url)
-                            .body(serde_json::to_string(&request).unwrap())
-                            .send()
-                            .await;
-                        Ok({})
-                    }
-                }
+        }
+        if let Err(e) = &result {
+            check_reachability(&self.context, &data.user, e);
+        }
+        result
+    }
+
+    async fn sync_dialogs(&self) -> utils::Result<Vec<Dialog>> {
+        if self.is_bot() {
+            return Err(Telegram::not_for_bots("messages.getDialogs"));
+        }
+        let mut dialogs = Vec::new();
+        let mut dialogs_iter = self.client.iter_dialogs();
+        while let Some(dialog) = dialogs_iter.next().await? {
+            let mut entry = Telegram::dialog_peer(dialog.chat());
+            if let grammers_tl_types::enums::Dialog::Dialog(data) = &dialog.dialog {
+                entry.unread_count = data.unread_count;
             }
-            Err(_) => { Ok({}) }
+            entry.last_message = dialog.last_message.as_ref().map(Telegram::dialog_message);
+            dialogs.push(entry);
+        }
+        self.context.dialogs.replace(&self.context.bot_name, dialogs.clone());
+        Ok(dialogs)
+    }
+
+    async fn reschedule(&self, message: &DeliveryResult, send_at: i64) -> utils::Result<()> {
+        let (peer, id) = Telegram::scheduled_message(message)?;
+        self.client.invoke(&grammers_tl_types::functions::messages::EditMessage {
+            no_webpage: false,
+            invert_media: false,
+            peer,
+            id,
+            message: None,
+            media: None,
+            reply_markup: None,
+            entities: None,
+            schedule_date: Some(send_at as i32),
+            quick_reply_shortcut_id: None
+        }).await?;
+        Ok(())
+    }
+
+    async fn cancel_scheduled(&self, message: &DeliveryResult) -> utils::Result<()> {
+        let (peer, id) = Telegram::scheduled_message(message)?;
+        self.client.invoke(&grammers_tl_types::functions::messages::DeleteScheduledMessages {
+            peer,
+            id: vec![id]
+        }).await?;
+        Ok(())
+    }
+
+    async fn add_contact(&self, new_contact: AddContactRequest) -> utils::Result<DeliveryResult> {
+        let phone = new_contact.phone.clone();
+        let Some(contact) = self.import_contacts(vec![new_contact]).await?.pop() else {
+            return Ok(DeliveryResult::default())
+        };
+        match contact.status {
+            ContactStatus::Imported | ContactStatus::Linked => Ok(DeliveryResult {
+                chat_id: contact.chat_id,
+                access_hash: contact.access_hash,
+                ..Default::default()
+            }),
+            ContactStatus::NotOnTelegram => Err(utils::Error::request(404, "PHONE_NOT_ON_TELEGRAM", format!("{} has no Telegram account", phone))),
+            ContactStatus::Retry | ContactStatus::RateLimited => Err(utils::Error::request(429, "IMPORT_RETRY", format!("Telegram asked to import {} again later", phone)))
         }
     }
 
+    async fn import_contacts(&self, contacts: Vec<AddContactRequest>) -> utils::Result<Vec<ContactImport>> {
+        if self.is_bot() {
+            return Err(Telegram::not_for_bots("contacts.importContacts"));
+        }
+        let results = self.import_phones(&contacts).await?;
+        report_contacts(&self.context, &results);
+        Ok(results)
+    }
+
     async fn update_profile_status(&self) {
         tokio::time::sleep(Duration::from_millis(2000)).await;
         let _ = self.client.invoke(&grammers_tl_types::functions::updates::GetState{}).await;
     }
 
     async fn message_handler(&self, tx: Sender<ChannelTx>) {
+        let mut failures: u32 = 0;
+        let mut synced = false;
         loop {
-            self.client.sync_update_state();
-            let update = self.get_updates().await.unwrap();
-            if update.is_none() {
-                continue;
+            if self.bot_id.load(Ordering::Relaxed) == 0 {
+                synced = false;
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue
             }
-            match update.unwrap() {
-                Update::NewMessage(message) if !message.outgoing() => {
-                    match message.chat().pack().ty {
-                        PackedType::User => {
-                            let chat = Option::from(PackedChat::from(message.chat()));
-                            let user = chat.unwrap().id.to_string();
-                            if user == self.bot_id.to_string() {
-                                continue
-                            }
-                            let data = TelegramMessage{
-                                user: user.clone(),
-                                text: String::from(message.text()),
-                                ctx: message.chat().pack(),
-                                id: message.id().clone()
-                            };
-                            let _ = tx.send(ChannelTx{
-                                bot_name: self.context.bot_name.clone(),
-                                data: ChannelData::ReceiveMessage(data)
-                            }).await;
-                        }
-                        _ => {}
+            // After a login or a lost connection the list catches up with what happened meanwhile.
+            if !synced && !self.is_bot() {
+                synced = true;
+                if let Err(e) = self.sync_dialogs().await {
+                    log::error!("[{}] Dialog sync failed: {}", self.context.bot_name, e);
+                }
+                if let Err(e) = self.catch_up_receipts().await {
+                    log::error!("[{}] Read receipts could not be caught up: {}", self.context.bot_name, e);
+                }
+            }
+            self.client.sync_update_state();
+            // A lock error means another instance took the account over, both can't receive its
+            // updates. Like a dropped connection it is retried with a growing pause, so the bot
+            // comes back on its own once the account or the network is available again.
+            let update = match self.session.autosave(self.client.session()) {
+                Ok(()) => self.get_updates().await.map_err(utils::Error::from),
+                Err(e) => Err(e)
+            };
+            let update = match update {
+                Ok(update) => update,
+                Err(e) => {
+                    let delay = Duration::from_secs(1 << failures.min(6)).min(MAX_RECOVERY_DELAY);
+                    log::error!("[{}] Update handler failed: {}, retrying in {}s", self.context.bot_name, e, delay.as_secs());
+                    self.last_error.set(&e);
+                    self.connected.store(false, Ordering::Relaxed);
+                    self.save_session();
+                    synced = false;
+                    failures += 1;
+                    tokio::time::sleep(delay).await;
+                    continue;
+                }
+            };
+            failures = 0;
+            self.connected.store(true, Ordering::Relaxed);
+            self.last_update.store(chrono::Utc::now().timestamp(), Ordering::Relaxed);
+            let Some(update) = update else { continue };
+            match update {
+                Update::NewMessage(message) => {
+                    self.record_dialog(&message);
+                    if message.outgoing() || message.chat().pack().ty != PackedType::User {
+                        continue
+                    }
+                    let user = message.chat().id().to_string();
+                    if user == self.bot_id.load(Ordering::Relaxed).to_string() {
+                        continue
+                    }
+                    let data = TelegramMessage{
+                        user: user.clone(),
+                        text: String::from(message.text()),
+                        ctx: message.chat().pack(),
+                        id: message.id(),
+                        meta: Telegram::message_meta(&message)
+                    };
+                    // Whoever writes in can be written back to.
+                    clear_reachability(&self.context, Some(&user), data.meta.phone.as_deref().unwrap_or_default(), "MESSAGE_RECEIVED");
+                    self.remember_peer(data.ctx, data.meta.username.as_deref(), data.meta.phone.as_deref());
+                    let _ = tx.send(ChannelTx{
+                        bot_name: self.context.bot_name.clone(),
+                        data: ChannelData::ReceiveMessage(data),
+                        reply: None
+                    }).await;
+                }
+                Update::ReadHistoryInbox(read) => self.read_dialog(&read),
+                Update::ReadHistoryOutbox(read) => self.read_receipt(read.peer(), read.max_id()),
+                Update::BotStopped(update) => {
+                    let user = update.user_id().to_string();
+                    if update.stopped() {
+                        let recipient = UserData { messenger_id: Some(user), ..Default::default() };
+                        check_reachability(&self.context, &recipient, &utils::Error::request(403, "USER_IS_BLOCKED", "the user stopped the bot"));
+                    } else {
+                        clear_reachability(&self.context, Some(&user), "", "BOT_RESTARTED");
+                    }
+                }
+                Update::InlineQuery(query) => {
+                    if let Err(e) = self.answer_inline_query(query).await {
+                        log::error!("[{}] {}", self.context.bot_name, e);
                     }
                 }
+                Update::CallbackQuery(query) => {
+                    let _ = query.answer().send().await;
+                    let data = TelegramMessage{
+                        user: query.sender().id().to_string(),
+                        text: String::from_utf8_lossy(query.data()).to_string(),
+                        ctx: query.chat().pack(),
+                        id: 0,
+                        meta: MessageMeta {
+                            username: query.sender().username().map(String::from),
+                            date: chrono::Utc::now().timestamp(),
+                            ..Default::default()
+                        }
+                    };
+                    let _ = tx.send(ChannelTx{
+                        bot_name: self.context.bot_name.clone(),
+                        data: ChannelData::ReceiveMessage(data),
+                        reply: None
+                    }).await;
+                }
                 _ => {}
             }
         }
     }
 
-    async fn handle_message(&self, user: String, message: String) -> utils::Result<()> {
-        if message != "1" || message == "1." || message == "'1'" {
+    async fn handle_message(&self, message: TelegramMessage) -> utils::Result<()> {
+        let handler = peek_handler(&self.handlers, &message.user);
+        webhook::forward(self.context.webhook.as_ref(), InboundMessage::new(&self.context.bot_name, &message, handler));
+        if let Some(request) = take_handler(&self.handlers, &message.user, &message.text) {
+            send_handler(request, &message.user, &self.context).await?;
             return Ok(());
         }
-        let request = json!({
-            "object": "visits",
-            "command": "bot_verify",
-            "data": {
-                "context": {
-                    "bot": true,
-                    "user_id": &user,
-                }
-            }
-        });
-        reqwest::Client::new()
-            .post(&self.context.api_url)
-            .body(serde_json::to_string(&request).unwrap())
-            .send()
-            .await.unwrap();
+        let Some(rule) = self.context.rules.find(&self.context.bot_name, &message.text) else { return Ok(()) };
+        if let Some(request) = rule.render_request(&message) {
+            self.context.call_backend(&request).await?;
+        }
+        if let Some(reply) = rule.reply {
+            self.client.send_message(message.ctx, InputMessage::text(reply)).await?;
+        }
         Ok(())
     }
 
-    async fn delete_contacts(&self) {
-        let response = self.client.invoke(&grammers_tl_types::functions::contacts::GetContacts{
-            hash: 0
-        }).await.unwrap();
-        let mut delete_users: Vec<grammers_tl_types::enums::InputUser> = Vec::new();
-        let grammers_tl_types::enums::contacts::Contacts::Contacts(contacts) = response else { return; };
-        for user_data in contacts.users.iter() {
-            let grammers_tl_types::enums::User::User(user) = user_data else { continue };
-            let user_to_delete = grammers_tl_types::types::InputUser {
-                user_id: user.id,
-                access_hash: user.access_hash.unwrap(),
-            };
-            delete_users.push(grammers_tl_types::enums::InputUser::User(user_to_delete));
+    async fn list_contacts(&self) -> utils::Result<Vec<ContactInfo>> {
+        if self.is_bot() {
+            return Err(Telegram::not_for_bots("contacts.getContacts"));
         }
-        self.client.invoke(&grammers_tl_types::functions::contacts::DeleteContacts{
-            id: delete_users
-        }).await.unwrap();
+        self.fetch_contacts().await
+    }
+
+    async fn delete_contacts(&self, contacts: &[String]) -> utils::Result<Vec<ContactInfo>> {
+        if self.is_bot() {
+            return Err(Telegram::not_for_bots("contacts.deleteContacts"));
+        }
+        let phones: Vec<String> = contacts.iter().map(|contact| phone_digits(contact)).filter(|phone| !phone.is_empty()).collect();
+        let matching = self.fetch_contacts().await?.into_iter()
+            .filter(|contact| contacts.contains(&contact.user_id.to_string()) || phones.contains(&phone_digits(&contact.phone)))
+            .collect();
+        self.remove_contacts(matching).await
+    }
+
+    async fn purge_contacts(&self, before: i64) -> utils::Result<Vec<ContactInfo>> {
+        if self.is_bot() {
+            return Err(Telegram::not_for_bots("contacts.deleteContacts"));
+        }
+        let expired: Vec<i64> = self.context.contacts.added_before(&self.context.bot_name, before).iter()
+            .map(|contact| contact.user_id)
+            .collect();
+        if expired.is_empty() {
+            return Ok(Vec::new());
+        }
+        let matching: Vec<ContactInfo> = self.fetch_contacts().await?.into_iter()
+            .filter(|contact| expired.contains(&contact.user_id))
+            .collect();
+        // Contacts deleted some other way only need to be forgotten.
+        let gone: Vec<i64> = expired.into_iter().filter(|id| !matching.iter().any(|contact| contact.user_id == *id)).collect();
+        self.context.contacts.forget(&self.context.bot_name, &gone);
+        self.remove_contacts(matching).await
+    }
+
+    fn report_error(&self, error: &utils::Error) {
+        self.last_error.set(error);
+    }
+
+    fn has_dialog(&self, user: &UserData) -> bool {
+        let recipient = user.messenger_id.as_deref().map(str::trim).filter(|id| !id.is_empty());
+        let chat = match recipient.and_then(|id| id.parse::<i64>().ok()) {
+            Some(id) => Some(id),
+            None => [recipient, Some(user.phone.trim())].into_iter().flatten()
+                .find(|name| name.starts_with('@'))
+                .and_then(|username| self.peers.by_username(username))
+                .or_else(|| self.peers.by_phone(&user.phone))
+                .map(|chat| chat.id)
+        };
+        chat.is_some_and(|id| self.context.dialogs.contains(&self.context.bot_name, id))
+    }
+
+    fn update_status(&self, id: &str, status: MessageStatus, at: i64) {
+        let changed = self.context.receipts.advance(&self.context.bot_name, id, status, at);
+        report_status(&self.context, changed.into_iter().collect());
+    }
+
+    fn clear_unreachable(&self, recipient: &str) -> bool {
+        clear_reachability(&self.context, Some(recipient), recipient, "CLEARED")
+    }
+
+    fn is_linked(&self, phone: &str) -> bool {
+        self.peers.by_phone(phone).is_some()
     }
 
     fn clone_boxed(&self) -> Box<dyn DocaBot + 'static> {
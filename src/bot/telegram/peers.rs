@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use grammers_session::{PackedChat, PackedType};
+
+/// Recipients resolved so far, reachable by id, "@username" or phone number, so that later
+/// sends don't need the caller to know Telegram ids or access hashes.
+#[derive(Default)]
+pub struct PeerCache {
+    peers: RwLock<HashMap<String, PackedChat>>
+}
+
+/// Keeps only the digits, so "+7 (900) 000-00-00" and "79000000000" are the same number.
+pub fn phone_digits(phone: &str) -> String {
+    phone.chars().filter(|c| c.is_ascii_digit()).collect()
+}
+
+fn username_key(username: &str) -> String {
+    format!("@{}", username.trim().trim_start_matches('@').to_lowercase())
+}
+
+impl PeerCache {
+    pub fn by_id(&self, id: i64) -> Option<PackedChat> {
+        self.peers.read().unwrap().get(&format!("id:{}", id)).copied()
+    }
+
+    pub fn by_username(&self, username: &str) -> Option<PackedChat> {
+        self.peers.read().unwrap().get(&username_key(username)).copied()
+    }
+
+    pub fn by_phone(&self, phone: &str) -> Option<PackedChat> {
+        self.peers.read().unwrap().get(&format!("phone:{}", phone_digits(phone))).copied()
+    }
+
+    /// A chat without access hash never replaces one that has it.
+    pub fn insert(&self, chat: PackedChat, username: Option<&str>, phone: Option<&str>) {
+        let chat = match self.by_id(chat.id) {
+            Some(known) if chat.access_hash.is_none() => known,
+            _ => chat
+        };
+        let mut peers = self.peers.write().unwrap();
+        peers.insert(format!("id:{}", chat.id), chat);
+        if let Some(username) = username.filter(|username| !username.is_empty()) {
+            peers.insert(username_key(username), chat);
+        }
+        if let Some(phone) = phone.map(phone_digits).filter(|phone| !phone.is_empty()) {
+            peers.insert(format!("phone:{}", phone), chat);
+        }
+    }
+}
+
+/// Packs the user with this id out of a `users` vector returned by Telegram.
+pub fn pack_user(users: &[grammers_tl_types::enums::User], id: i64) -> Option<PackedChat> {
+    users.iter().find_map(|user| match user {
+        grammers_tl_types::enums::User::User(user) if user.id == id => Some(PackedChat {
+            ty: if user.bot { PackedType::Bot } else { PackedType::User },
+            id: user.id,
+            access_hash: user.access_hash
+        }),
+        _ => None
+    })
+}
@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use grammers_session::Session;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use crate::utils;
+use crate::utils::JsonConfigs;
+
+/// A lock without a heartbeat for this long belongs to a process that is gone.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(300);
+/// How often a running bot refreshes its lock.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+/// Changes are written at most this often.
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Identifies this process in session locks, so a restarted bot reclaims its own lock.
+pub fn instance_id() -> &'static str {
+    static INSTANCE: OnceLock<String> = OnceLock::new();
+    INSTANCE.get_or_init(|| format!("{}-{}", std::process::id(), chrono::Utc::now().timestamp_millis()))
+}
+
+fn session_locked(name: &str, owner: &str) -> utils::Error {
+    utils::Error::request(409, "SESSION_LOCKED", format!("session {} is used by instance {}", name, owner))
+}
+
+/// Where the serialized `grammers_session::Session` of every account lives.
+pub trait SessionStorage: Send + Sync {
+    fn load(&self, name: &str) -> utils::Result<Option<Vec<u8>>>;
+    fn save(&self, name: &str, data: &[u8]) -> utils::Result<()>;
+    /// Claims the account for `owner`, or refreshes its claim. Fails with `SESSION_LOCKED`
+    /// while another instance holds a lock that hasn't timed out.
+    fn lock(&self, name: &str, owner: &str) -> utils::Result<()>;
+    fn unlock(&self, name: &str, owner: &str);
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SessionBackend {
+    File { path: String },
+    Sqlite { path: String },
+    Memory
+}
+
+impl Default for SessionBackend {
+    fn default() -> Self {
+        SessionBackend::File { path: format!("configs/{}", crate::SESSION_FOLDER) }
+    }
+}
+
+impl JsonConfigs for SessionBackend {}
+
+impl SessionBackend {
+    pub fn open(&self) -> utils::Result<Arc<dyn SessionStorage>> {
+        Ok(match self {
+            SessionBackend::File { path } => Arc::new(FileSessionStorage::new(path)?),
+            SessionBackend::Sqlite { path } => Arc::new(SqliteSessionStorage::open(path)?),
+            SessionBackend::Memory => Arc::new(MemorySessionStorage::default())
+        })
+    }
+}
+
+/// One `{name}.session` file per account, next to a `{name}.lock` file holding the owner.
+pub struct FileSessionStorage {
+    folder: PathBuf
+}
+
+impl FileSessionStorage {
+    pub fn new(folder: &str) -> utils::Result<Self> {
+        std::fs::create_dir_all(folder)?;
+        Ok(FileSessionStorage { folder: PathBuf::from(folder) })
+    }
+
+    fn path(&self, name: &str, extension: &str) -> PathBuf {
+        self.folder.join(format!("{}.{}", name, extension))
+    }
+}
+
+impl SessionStorage for FileSessionStorage {
+    fn load(&self, name: &str) -> utils::Result<Option<Vec<u8>>> {
+        match std::fs::read(self.path(name, "session")) {
+            Ok(data) if data.is_empty() => Ok(None),
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into())
+        }
+    }
+
+    fn save(&self, name: &str, data: &[u8]) -> utils::Result<()> {
+        let path = self.path(name, "session");
+        let tmp = self.path(name, "session.tmp");
+        std::fs::write(&tmp, data)?;
+        std::fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    fn lock(&self, name: &str, owner: &str) -> utils::Result<()> {
+        let path = self.path(name, "lock");
+        // `create_new` makes the first claim atomic, so two instances starting together
+        // can't both take a free lock.
+        if let Ok(mut file) = std::fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            std::io::Write::write_all(&mut file, owner.as_bytes())?;
+            return Ok(());
+        }
+        if let Ok(metadata) = std::fs::metadata(&path) {
+            let holder = std::fs::read_to_string(&path).unwrap_or_default();
+            let fresh = metadata.modified()?.elapsed().map(|age| age < LOCK_TIMEOUT).unwrap_or(true);
+            if holder.trim() != owner && fresh {
+                return Err(session_locked(name, holder.trim()));
+            }
+        }
+        std::fs::write(path, owner)?;
+        Ok(())
+    }
+
+    fn unlock(&self, name: &str, owner: &str) {
+        let path = self.path(name, "lock");
+        if std::fs::read_to_string(&path).is_ok_and(|holder| holder.trim() == owner) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// All accounts in one database, which several instances can share.
+pub struct SqliteSessionStorage {
+    connection: Mutex<Connection>
+}
+
+impl SqliteSessionStorage {
+    pub fn open(path: &str) -> utils::Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.busy_timeout(Duration::from_secs(5))?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS sessions (name TEXT PRIMARY KEY, data BLOB NOT NULL, updated_at INTEGER NOT NULL);
+             CREATE TABLE IF NOT EXISTS session_locks (name TEXT PRIMARY KEY, owner TEXT NOT NULL, heartbeat INTEGER NOT NULL);"
+        )?;
+        Ok(SqliteSessionStorage { connection: Mutex::new(connection) })
+    }
+}
+
+impl SessionStorage for SqliteSessionStorage {
+    fn load(&self, name: &str) -> utils::Result<Option<Vec<u8>>> {
+        let connection = self.connection.lock().unwrap();
+        let data = connection
+            .query_row("SELECT data FROM sessions WHERE name = ?1", params![name], |row| row.get(0))
+            .optional()?;
+        Ok(data)
+    }
+
+    fn save(&self, name: &str, data: &[u8]) -> utils::Result<()> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "INSERT INTO sessions (name, data, updated_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT(name) DO UPDATE SET data = excluded.data, updated_at = excluded.updated_at",
+            params![name, data, chrono::Utc::now().timestamp()]
+        )?;
+        Ok(())
+    }
+
+    fn lock(&self, name: &str, owner: &str) -> utils::Result<()> {
+        let now = chrono::Utc::now().timestamp();
+        let connection = self.connection.lock().unwrap();
+        // Takes the lock when it is free, ours or expired, in a single statement so two
+        // instances can't both win.
+        let claimed = connection.execute(
+            "INSERT INTO session_locks (name, owner, heartbeat) VALUES (?1, ?2, ?3)
+             ON CONFLICT(name) DO UPDATE SET owner = excluded.owner, heartbeat = excluded.heartbeat
+             WHERE session_locks.owner = excluded.owner OR session_locks.heartbeat < ?4",
+            params![name, owner, now, now - LOCK_TIMEOUT.as_secs() as i64]
+        )?;
+        if claimed == 0 {
+            let holder: String = connection
+                .query_row("SELECT owner FROM session_locks WHERE name = ?1", params![name], |row| row.get(0))
+                .unwrap_or_default();
+            return Err(session_locked(name, &holder));
+        }
+        Ok(())
+    }
+
+    fn unlock(&self, name: &str, owner: &str) {
+        let connection = self.connection.lock().unwrap();
+        let _ = connection.execute("DELETE FROM session_locks WHERE name = ?1 AND owner = ?2", params![name, owner]);
+    }
+}
+
+/// Keeps sessions for the lifetime of the process only, for tests and throwaway bots.
+#[derive(Default)]
+pub struct MemorySessionStorage {
+    sessions: Mutex<HashMap<String, Vec<u8>>>,
+    locks: Mutex<HashMap<String, (String, Instant)>>
+}
+
+impl SessionStorage for MemorySessionStorage {
+    fn load(&self, name: &str) -> utils::Result<Option<Vec<u8>>> {
+        Ok(self.sessions.lock().unwrap().get(name).cloned())
+    }
+
+    fn save(&self, name: &str, data: &[u8]) -> utils::Result<()> {
+        self.sessions.lock().unwrap().insert(name.to_string(), data.to_vec());
+        Ok(())
+    }
+
+    fn lock(&self, name: &str, owner: &str) -> utils::Result<()> {
+        let mut locks = self.locks.lock().unwrap();
+        if let Some((holder, heartbeat)) = locks.get(name) {
+            if holder != owner && heartbeat.elapsed() < LOCK_TIMEOUT {
+                return Err(session_locked(name, holder));
+            }
+        }
+        locks.insert(name.to_string(), (owner.to_string(), Instant::now()));
+        Ok(())
+    }
+
+    fn unlock(&self, name: &str, owner: &str) {
+        let mut locks = self.locks.lock().unwrap();
+        if locks.get(name).is_some_and(|(holder, _)| holder == owner) {
+            locks.remove(name);
+        }
+    }
+}
+
+struct SaveState {
+    data: Vec<u8>,
+    checked_at: Instant,
+    locked_at: Instant
+}
+
+/// The session of one account bound to its storage. Writes happen only when the serialized
+/// session changed, or to keep the lock alive.
+pub struct StoredSession {
+    storage: Arc<dyn SessionStorage>,
+    name: String,
+    last_save: Mutex<SaveState>
+}
+
+impl StoredSession {
+    /// Locks the account and loads its session, or starts a new one.
+    pub fn open(storage: Arc<dyn SessionStorage>, name: &str) -> utils::Result<(Self, Session)> {
+        storage.lock(name, instance_id())?;
+        let data = storage.load(name)?;
+        let session = match data.as_deref() {
+            Some(data) => Session::load(data)?,
+            None => Session::new()
+        };
+        let stored = StoredSession {
+            storage,
+            name: name.to_string(),
+            last_save: Mutex::new(SaveState {
+                data: data.unwrap_or_default(),
+                checked_at: Instant::now(),
+                locked_at: Instant::now()
+            })
+        };
+        Ok((stored, session))
+    }
+
+    /// Writes the session if it changed since the last write.
+    pub fn save(&self, session: &Session) -> utils::Result<()> {
+        self.write(session, true)
+    }
+
+    /// Called from the update loop: writes changes at most every few seconds and refreshes
+    /// the lock once a minute.
+    pub fn autosave(&self, session: &Session) -> utils::Result<()> {
+        let (checked_at, locked_at) = {
+            let last_save = self.last_save.lock().unwrap();
+            (last_save.checked_at, last_save.locked_at)
+        };
+        if checked_at.elapsed() < AUTOSAVE_INTERVAL {
+            return Ok(());
+        }
+        self.write(session, locked_at.elapsed() >= HEARTBEAT_INTERVAL)
+    }
+
+    fn write(&self, session: &Session, heartbeat: bool) -> utils::Result<()> {
+        let data = session.save();
+        let mut last_save = self.last_save.lock().unwrap();
+        last_save.checked_at = Instant::now();
+        if heartbeat {
+            self.storage.lock(&self.name, instance_id())?;
+            last_save.locked_at = Instant::now();
+        }
+        if data != last_save.data {
+            self.storage.save(&self.name, &data)?;
+            last_save.data = data;
+        }
+        Ok(())
+    }
+
+    pub fn release(&self) {
+        self.storage.unlock(&self.name, instance_id());
+    }
+}
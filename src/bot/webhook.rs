@@ -0,0 +1,88 @@
+use std::time::Duration;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use crate::structs::api::{BotHandler, MessageMeta, TelegramMessage};
+
+const MAX_ATTEMPTS: u32 = 5;
+pub const SIGNATURE_HEADER: &str = "X-Doca-Signature";
+
+/// Where a bot forwards its inbound messages. With a `secret` every delivery is signed.
+#[derive(Default, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(default)]
+    pub secret: String
+}
+
+/// Body of a webhook delivery: the message with its metadata and the handlers that were
+/// waiting for this user when it arrived.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InboundMessage {
+    pub bot: String,
+    pub message_id: i32,
+    pub sender_id: String,
+    pub text: String,
+    #[serde(flatten)]
+    pub meta: MessageMeta,
+    pub handler: Option<BotHandler>
+}
+
+impl InboundMessage {
+    pub fn new(bot: &str, message: &TelegramMessage, handler: Option<BotHandler>) -> Self {
+        InboundMessage {
+            bot: bot.to_string(),
+            message_id: message.id,
+            sender_id: message.user.clone(),
+            text: message.text.clone(),
+            meta: message.meta.clone(),
+            handler
+        }
+    }
+}
+
+/// Hex encoded HMAC-SHA256 of the body, sent as `sha256=<hex>`.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    let digest: String = mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256={}", digest)
+}
+
+/// Posts the message to the webhook, retrying network errors, 429 and 5xx responses with
+/// an exponential backoff. Returns whether the webhook accepted it.
+pub async fn deliver(config: &WebhookConfig, message: &InboundMessage) -> bool {
+    let Ok(body) = serde_json::to_vec(message) else { return false };
+    let client = reqwest::Client::new();
+    for attempt in 0..MAX_ATTEMPTS {
+        if attempt > 0 {
+            tokio::time::sleep(Duration::from_secs(1 << (attempt - 1))).await;
+        }
+        let mut request = client
+            .post(&config.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.clone());
+        if !config.secret.is_empty() {
+            request = request.header(SIGNATURE_HEADER, sign(&config.secret, &body));
+        }
+        match request.send().await {
+            Ok(response) if response.status().is_success() => return true,
+            Ok(response) if response.status().as_u16() != 429 && !response.status().is_server_error() => {
+                log::error!("[{}] Webhook rejected message {}: {}", message.bot, message.message_id, response.status());
+                return false;
+            }
+            Ok(response) => log::warn!("[{}] Webhook answered {}, retrying", message.bot, response.status()),
+            Err(e) => log::warn!("[{}] Webhook unreachable: {}, retrying", message.bot, e)
+        }
+    }
+    log::error!("[{}] Webhook gave up on message {}", message.bot, message.message_id);
+    false
+}
+
+/// Delivers in the background so a slow webhook never holds up the update loop.
+pub fn forward(config: Option<&WebhookConfig>, message: InboundMessage) {
+    let Some(config) = config.filter(|config| !config.url.is_empty()).cloned() else { return };
+    tokio::spawn(async move {
+        deliver(&config, &message).await;
+    });
+}
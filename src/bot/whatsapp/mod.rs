@@ -1,30 +1,336 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicI64, Ordering};
 use async_trait::async_trait;
+use grammers_session::{PackedChat, PackedType};
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use tokio::sync::mpsc::Sender;
-use crate::bot::{DocaBot, MessagesMap};
-use crate::structs::api::{AddContactRequest, BotHandler, SendMessageRequest, UserData};
-use crate::structs::wrapper::{ChannelTx};
-use crate::structs::auth::AuthData;
+use crate::bot::{build_handler, clear_reachability, peek_handler, send_handler, take_handler, report_status, BotAuth, DocaBot, LastError};
+use crate::api::auth::constant_time_eq;
+use crate::bot::webhook::{self, InboundMessage};
+use crate::structs::api::{AddContactRequest, Attachment, BotContext, BotHandler, BotStatus, ContactImport, ContactInfo, DeliveryResult, LoginStatus, MessageMeta, SendMessageRequest, TelegramMessage, UserData, UserHandlers};
+use crate::structs::wrapper::{ChannelTx, Dialog, MessageStatus, TrackedMessage};
+use crate::structs::auth::{AuthData, WhatsAppAuth};
 use crate::utils;
 use crate::utils::JsonConfigs;
 
-#[derive(PartialEq, Default, Serialize, Deserialize)]
+const GRAPH_URL: &str = "https://graph.facebook.com/v19.0";
+/// Seconds to pause a bot the Graph API rate-limited without a `Retry-After`.
+const RATE_LIMIT_WAIT: u32 = 60;
+
+fn default_graph_url() -> String {
+    GRAPH_URL.to_string()
+}
+
+/// Settings shared by every WhatsApp account, the per-number credentials live in
+/// [`WhatsAppAuth`].
+#[derive(PartialEq, Clone, Default, Serialize, Deserialize)]
 pub struct WhatsappAuth {
-    phone_id: String,
-    account_id: String,
+    #[serde(default = "default_graph_url")]
+    pub graph_url: String,
+    pub verify_token: String,
+    /// The Meta app secret that signs webhook notifications. Without it every notification
+    /// is turned away.
+    #[serde(default)]
+    pub app_secret: String
+}
+
+/// Carries the HMAC-SHA256 of the raw notification body, keyed with the app secret.
+pub const SIGNATURE_HEADER: &str = "X-Hub-Signature-256";
+
+/// Whether the notification was signed with `app_secret`, as `sha256=<hex digest>`.
+pub fn verify_signature(app_secret: &str, body: &[u8], signature: Option<&str>) -> bool {
+    let Some(signature) = signature.filter(|_| !app_secret.is_empty()) else { return false };
+    constant_time_eq(webhook::sign(app_secret, body).as_bytes(), signature.trim().as_bytes())
 }
 
 impl JsonConfigs for WhatsappAuth {}
 
+/// A message, media message or button reply taken from a Cloud API webhook notification.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WhatsAppInbound {
+    pub phone_id: String,
+    pub from: String,
+    pub id: String,
+    pub text: String,
+    pub meta: MessageMeta
+}
+
+impl From<WhatsAppInbound> for TelegramMessage {
+    fn from(message: WhatsAppInbound) -> Self {
+        TelegramMessage {
+            id: 0,
+            ctx: PackedChat {
+                ty: PackedType::User,
+                id: message.from.parse().unwrap_or_default(),
+                access_hash: None
+            },
+            user: message.from,
+            text: message.text,
+            meta: message.meta
+        }
+    }
+}
+
+/// Media messages carry their type and Graph media id in `meta.media`, the caption as text.
+const MEDIA_TYPES: [&str; 5] = ["image", "video", "audio", "document", "sticker"];
+
+fn media_descriptor(kind: &str, media: &Value) -> Value {
+    json!({
+        "type": kind,
+        "id": media["id"],
+        "mime_type": media["mime_type"],
+        "name": media["filename"]
+    })
+}
+
+/// Collects the text of every inbound message in the notification. Button replies resolve to
+/// their payload (the button `reply`), so they match handlers the same way Telegram callbacks do.
+pub fn parse_webhook(payload: &Value) -> Vec<WhatsAppInbound> {
+    let mut result = Vec::new();
+    let entries = payload["entry"].as_array().cloned().unwrap_or_default();
+    for change in entries.iter().flat_map(|entry| entry["changes"].as_array().cloned().unwrap_or_default()) {
+        let value = &change["value"];
+        let phone_id = value["metadata"]["phone_number_id"].as_str().unwrap_or_default();
+        let contacts: HashMap<&str, String> = value["contacts"].as_array().into_iter().flatten()
+            .filter_map(|contact| Some((contact["wa_id"].as_str()?, contact["profile"]["name"].as_str()?.to_string())))
+            .collect();
+        for message in value["messages"].as_array().cloned().unwrap_or_default() {
+            let kind = message["type"].as_str().unwrap_or_default();
+            let mut media = None;
+            let text = match kind {
+                "text" => message["text"]["body"].as_str(),
+                "button" => message["button"]["payload"].as_str().or(message["button"]["text"].as_str()),
+                "interactive" => message["interactive"]["button_reply"]["id"].as_str()
+                    .or(message["interactive"]["list_reply"]["id"].as_str()),
+                kind if MEDIA_TYPES.contains(&kind) => {
+                    media = Some(media_descriptor(kind, &message[kind]));
+                    Some(message[kind]["caption"].as_str().unwrap_or_default())
+                }
+                _ => None
+            };
+            let Some(text) = text else {
+                log::info!("[whatsapp] Skipping a {} message from {}", kind, phone_id);
+                continue
+            };
+            let from = message["from"].as_str().unwrap_or_default();
+            result.push(WhatsAppInbound {
+                phone_id: phone_id.to_string(),
+                from: from.to_string(),
+                id: message["id"].as_str().unwrap_or_default().to_string(),
+                text: text.to_string(),
+                meta: MessageMeta {
+                    username: contacts.get(from).cloned(),
+                    phone: Some(from.to_string()),
+                    markdown: text.to_string(),
+                    html: text.to_string(),
+                    media,
+                    reply_to: None,
+                    date: message["timestamp"].as_str().and_then(|date| date.parse().ok()).unwrap_or_default()
+                }
+            });
+        }
+    }
+    result
+}
+
+/// A delivery receipt from a Cloud API webhook notification.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WhatsAppStatus {
+    pub phone_id: String,
+    /// The `wamid` of the message the receipt is for.
+    pub id: String,
+    pub status: MessageStatus,
+    pub timestamp: i64
+}
+
+/// Collects the `sent`, `delivered`, `read` and `failed` receipts in the notification.
+pub fn parse_statuses(payload: &Value) -> Vec<WhatsAppStatus> {
+    let entries = payload["entry"].as_array().cloned().unwrap_or_default();
+    entries.iter()
+        .flat_map(|entry| entry["changes"].as_array().cloned().unwrap_or_default())
+        .flat_map(|change| {
+            let value = &change["value"];
+            let phone_id = value["metadata"]["phone_number_id"].as_str().unwrap_or_default().to_string();
+            value["statuses"].as_array().cloned().unwrap_or_default().into_iter().filter_map(move |receipt| {
+                let status = match receipt["status"].as_str()? {
+                    "sent" => MessageStatus::Sent,
+                    "delivered" => MessageStatus::Delivered,
+                    "read" => MessageStatus::Read,
+                    "failed" => MessageStatus::Failed,
+                    _ => return None
+                };
+                Some(WhatsAppStatus {
+                    phone_id: phone_id.clone(),
+                    id: receipt["id"].as_str()?.to_string(),
+                    status,
+                    timestamp: receipt["timestamp"].as_str().and_then(|date| date.parse().ok()).unwrap_or_default()
+                })
+            })
+        })
+        .collect()
+}
+
 #[derive(Clone)]
 pub struct WhatsApp {
-    pub token: String,
+    pub client: reqwest::Client,
+    pub graph_url: String,
+    pub auth: WhatsAppAuth,
+    pub handlers: Arc<RwLock<UserHandlers>>,
+    pub context: BotContext,
+    pub last_update: Arc<AtomicI64>,
+    pub last_error: LastError
 }
 
 impl WhatsApp {
-    // pub async fn new(_: BotAuth) -> Self {
-    //     todo!()
-    // }
+    pub fn new(cfg: BotAuth, ctx: BotContext) -> Self {
+        let graph_url = match cfg {
+            BotAuth::WhatsappAuth(data) => data.graph_url,
+            _ => GRAPH_URL.to_string()
+        };
+        WhatsApp {
+            client: reqwest::Client::new(),
+            graph_url,
+            auth: WhatsAppAuth::default(),
+            handlers: Arc::new(RwLock::new(UserHandlers::default())),
+            context: ctx,
+            last_update: Arc::new(AtomicI64::new(0)),
+            last_error: LastError::default()
+        }
+    }
+
+    /// WhatsApp ids are the phone number in international format without the plus sign.
+    fn recipient(user: &UserData) -> String {
+        let recipient = user.messenger_id.clone().unwrap_or(user.phone.clone());
+        recipient.chars().filter(|c| c.is_ascii_digit()).collect()
+    }
+
+    /// `media` is the Graph API media object of the attachment, `{"link": ..}` or `{"id": ..}`
+    /// once uploaded.
+    pub fn build_payload(to: &str, data: &SendMessageRequest, media: Option<Value>) -> Value {
+        if let Some(attachment) = data.attachment.as_ref() {
+            let (kind, body) = match (attachment, media) {
+                (Attachment::Location { latitude, longitude }, _) => ("location", json!({
+                    "latitude": latitude,
+                    "longitude": longitude,
+                    "name": data.message
+                })),
+                (Attachment::Photo(_), Some(mut media)) => {
+                    media["caption"] = json!(data.message);
+                    ("image", media)
+                }
+                (Attachment::Document(source), Some(mut media)) => {
+                    media["caption"] = json!(data.message);
+                    media["filename"] = json!(source.file_name);
+                    ("document", media)
+                }
+                (_, media) => ("audio", media.unwrap_or_default())
+            };
+            return json!({
+                "messaging_product": "whatsapp",
+                "to": to,
+                "type": kind,
+                kind: body
+            });
+        }
+        if let Some(template) = data.template.as_ref() {
+            return json!({
+                "messaging_product": "whatsapp",
+                "to": to,
+                "type": "template",
+                "template": {
+                    "name": template.name,
+                    "language": { "code": template.language },
+                    "components": template.components
+                }
+            });
+        }
+        match data.buttons.as_ref().filter(|buttons| !buttons.is_empty()) {
+            Some(buttons) => json!({
+                "messaging_product": "whatsapp",
+                "to": to,
+                "type": "interactive",
+                "interactive": {
+                    "type": "button",
+                    "body": { "text": data.message },
+                    "action": {
+                        "buttons": buttons.iter().map(|btn| json!({
+                            "type": "reply",
+                            "reply": { "id": btn.reply, "title": btn.title }
+                        })).collect::<Vec<Value>>()
+                    }
+                }
+            }),
+            None => json!({
+                "messaging_product": "whatsapp",
+                "to": to,
+                "type": "text",
+                "text": { "body": data.message }
+            })
+        }
+    }
+
+    /// Inline attachments go through the media endpoint first, links are passed as they are.
+    async fn media_object(&self, attachment: Option<&Attachment>) -> utils::Result<Option<Value>> {
+        let source = match attachment {
+            Some(Attachment::Photo(source) | Attachment::Document(source) | Attachment::Voice { source, .. }) => source,
+            _ => return Ok(None)
+        };
+        let Some(bytes) = source.bytes()? else {
+            return match source.url.as_ref() {
+                Some(url) => Ok(Some(json!({ "link": url }))),
+                None => Err(utils::Error::request(400, "MEDIA_EMPTY", "attachment needs a url or data"))
+            };
+        };
+        let mime_type = source.mime_type.clone()
+            .or(mime_guess::from_path(&source.file_name).first().map(|mime| mime.essence_str().to_string()))
+            .unwrap_or("application/octet-stream".to_string());
+        let file = reqwest::multipart::Part::bytes(bytes)
+            .file_name(source.file_name.clone())
+            .mime_str(&mime_type)?;
+        let form = reqwest::multipart::Form::new()
+            .text("messaging_product", "whatsapp")
+            .text("type", mime_type)
+            .part("file", file);
+        let response = self.client
+            .post(format!("{}/{}/media", self.graph_url, self.auth.phone_id))
+            .bearer_auth(&self.auth.token)
+            .multipart(form)
+            .send()
+            .await?;
+        let status = response.status();
+        let retry_after = WhatsApp::retry_after(&response);
+        let body: Value = response.json().await.unwrap_or_default();
+        if !status.is_success() {
+            return Err(WhatsApp::graph_error(status.as_u16(), retry_after, &body));
+        }
+        Ok(Some(json!({ "id": body["id"] })))
+    }
+
+    fn retry_after(response: &reqwest::Response) -> Option<u32> {
+        response.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?.trim().parse().ok()
+    }
+
+    /// A 429 becomes a `FLOOD_WAIT` with the seconds to wait, so the limiter pauses the bot the
+    /// same way it does for Telegram. The Graph API rarely says how long, then a minute is used.
+    fn graph_error(status: u16, retry_after: Option<u32>, body: &Value) -> utils::Error {
+        let error = &body["error"];
+        let message = error["message"].as_str().unwrap_or_default().to_string();
+        if status == 429 {
+            return utils::Error::Rpc {
+                code: status as i32,
+                name: "FLOOD_WAIT".to_string(),
+                value: Some(retry_after.unwrap_or(RATE_LIMIT_WAIT)),
+                message
+            };
+        }
+        utils::Error::Api {
+            code: status as i32,
+            name: format!("WHATSAPP_{}", error["code"].as_i64().unwrap_or_default()),
+            message
+        }
+    }
 }
 
 #[async_trait]
@@ -33,55 +339,185 @@ impl DocaBot for WhatsApp {
         String::from("whatsapp")
     }
 
-    fn add_handler(&mut self, _: UserData, _: BotHandler) -> utils::Result<()> {
-        todo!()
+    fn add_handler(&self, user: UserData, handler: BotHandler) -> utils::Result<()> {
+        self.handlers.write().unwrap().insert(WhatsApp::recipient(&user), handler);
+        Ok(())
+    }
+
+    async fn sign_in(&mut self, _: String, data: AuthData) -> utils::Result<()> {
+        let AuthData::WhatsApp(auth_data) = data else { return Ok(()) };
+        // Webhook deliveries find their bot by phone id.
+        if auth_data.phone_id.trim().is_empty() {
+            return Err(utils::Error::request(400, "INVALID_AUTH", "a WhatsApp account needs its phone_id"));
+        }
+        self.auth = auth_data;
+        Ok(())
+    }
+
+    async fn sign_out(&self) {}
+
+    async fn login_status(&self) -> LoginStatus {
+        LoginStatus::Authorized
+    }
+
+    async fn status(&self) -> BotStatus {
+        let last_update = self.last_update.load(Ordering::Relaxed);
+        let mut status = BotStatus {
+            messenger: "whatsapp".to_string(),
+            connected: true,
+            authorized: !self.auth.token.is_empty(),
+            login: LoginStatus::Authorized,
+            last_update: (last_update > 0).then_some(last_update),
+            ..Default::default()
+        };
+        self.last_error.apply(&mut status);
+        status
+    }
+
+    async fn is_authorized(&self) -> bool {
+        !self.auth.token.is_empty()
+    }
+
+    async fn request_login(&self, _: Option<String>) -> utils::Result<LoginStatus> {
+        Ok(LoginStatus::Authorized)
+    }
+
+    async fn submit_code(&self, _: String) -> utils::Result<LoginStatus> {
+        Ok(LoginStatus::Authorized)
+    }
+
+    async fn submit_password(&self, _: Option<String>) -> utils::Result<LoginStatus> {
+        Ok(LoginStatus::Authorized)
+    }
+
+    async fn send_message(&self, data: SendMessageRequest) -> utils::Result<DeliveryResult> {
+        if data.schedule_date.is_some() {
+            // The Cloud API would send it right away, use a local schedule instead.
+            return Err(utils::Error::request(400, "NOT_SUPPORTED", "WhatsApp can't hold scheduled messages"));
+        }
+        let to = WhatsApp::recipient(&data.user);
+        let media = self.media_object(data.attachment.as_ref()).await?;
+        let response = self.client
+            .post(format!("{}/{}/messages", self.graph_url, self.auth.phone_id))
+            .bearer_auth(&self.auth.token)
+            .json(&WhatsApp::build_payload(&to, &data, media))
+            .send()
+            .await?;
+        let status = response.status();
+        let retry_after = WhatsApp::retry_after(&response);
+        let body: Value = response.json().await.unwrap_or_default();
+        if !status.is_success() {
+            return Err(WhatsApp::graph_error(status.as_u16(), retry_after, &body));
+        }
+        if let Some(handler) = data.handlers.as_ref() {
+            let buttons = data.buttons.clone().unwrap_or_default();
+            self.add_handler(data.user.clone(), build_handler(handler, &buttons))?;
+        }
+        let remote_id = body["messages"][0]["id"].as_str().map(String::from);
+        if let Some(id) = remote_id.clone() {
+            let mut tracked = TrackedMessage::sent(&self.context.bot_name, id, chrono::Utc::now().timestamp());
+            tracked.chat_id = to.parse().ok();
+            self.context.receipts.track(tracked);
+        }
+        Ok(DeliveryResult {
+            chat_id: to.parse().ok(),
+            remote_id,
+            ..Default::default()
+        })
     }
 
-    async fn sign_in(&mut self, _: String, _: AuthData) -> utils::Result<()> {
-        todo!()
+    async fn add_contact(&self, _: AddContactRequest) -> utils::Result<DeliveryResult> {
+        Err(utils::Error::request(400, "NOT_SUPPORTED", "WhatsApp has no contact list"))
     }
 
-    async fn sign_out(&self) {
-        todo!()
+    async fn import_contacts(&self, _: Vec<AddContactRequest>) -> utils::Result<Vec<ContactImport>> {
+        Err(utils::Error::request(400, "NOT_SUPPORTED", "WhatsApp has no contact list"))
     }
 
-    async fn send_message(&self, _: SendMessageRequest) -> utils::Result<()> {
-        todo!()
+    async fn reschedule(&self, _: &DeliveryResult, _: i64) -> utils::Result<()> {
+        Err(utils::Error::request(400, "NOT_SUPPORTED", "WhatsApp can't hold scheduled messages"))
     }
 
-    async fn add_contact(&self, _: AddContactRequest) -> utils::Result<()> {
-        todo!()
+    async fn cancel_scheduled(&self, _: &DeliveryResult) -> utils::Result<()> {
+        Err(utils::Error::request(400, "NOT_SUPPORTED", "WhatsApp can't hold scheduled messages"))
     }
 
-    async fn get_dialogs(&self) -> utils::Result<MessagesMap> {
-        todo!()
+    async fn sync_dialogs(&self) -> utils::Result<Vec<Dialog>> {
+        Err(utils::Error::request(400, "NOT_SUPPORTED", "the Cloud API has no dialog list"))
     }
 
     // async fn custom_handler(&mut self, bot_ctx: BotContext, tx: Sender<ChannelData>) {
     //     todo!()
     // }
 
-    async fn update_profile_status(&self) {
-        todo!()
+    async fn update_profile_status(&self) {}
+
+    /// Inbound messages arrive through the webhook endpoint, there is nothing to poll.
+    async fn message_handler(&self, _: Sender<ChannelTx>) {}
+
+    async fn handle_message(&self, message: TelegramMessage) -> utils::Result<()> {
+        self.last_update.store(chrono::Utc::now().timestamp(), Ordering::Relaxed);
+        let handler = peek_handler(&self.handlers, &message.user);
+        webhook::forward(self.context.webhook.as_ref(), InboundMessage::new(&self.context.bot_name, &message, handler));
+        if let Some(request) = take_handler(&self.handlers, &message.user, &message.text) {
+            send_handler(request, &message.user, &self.context).await?;
+            return Ok(());
+        }
+        let Some(rule) = self.context.rules.find(&self.context.bot_name, &message.text) else { return Ok(()) };
+        if let Some(request) = rule.render_request(&message) {
+            self.context.call_backend(&request).await?;
+        }
+        if let Some(reply) = rule.reply {
+            self.send_message(SendMessageRequest {
+                user: UserData { phone: message.user.clone(), messenger_id: Some(message.user.clone()) },
+                message: reply,
+                ..Default::default()
+            }).await?;
+        }
+        Ok(())
+    }
+
+    async fn list_contacts(&self) -> utils::Result<Vec<ContactInfo>> {
+        Err(utils::Error::request(400, "NOT_SUPPORTED", "WhatsApp has no contact list"))
+    }
+
+    async fn delete_contacts(&self, _: &[String]) -> utils::Result<Vec<ContactInfo>> {
+        Err(utils::Error::request(400, "NOT_SUPPORTED", "WhatsApp has no contact list"))
+    }
+
+    async fn purge_contacts(&self, _: i64) -> utils::Result<Vec<ContactInfo>> {
+        Err(utils::Error::request(400, "NOT_SUPPORTED", "WhatsApp has no contact list"))
+    }
+
+    fn report_error(&self, error: &utils::Error) {
+        self.last_error.set(error);
+    }
+
+    fn has_dialog(&self, _: &UserData) -> bool {
+        false
     }
 
-    async fn message_handler(&self, _: Sender<ChannelTx>) {
-        todo!()
+    fn update_status(&self, id: &str, status: MessageStatus, at: i64) {
+        let changed = self.context.receipts.advance(&self.context.bot_name, id, status, at);
+        report_status(&self.context, changed.into_iter().collect());
     }
 
-    async fn handle_message(&self, _: String, _: String) -> utils::Result<()> {
-        todo!()
+    fn start_handle(self, tx: Sender<ChannelTx>) {
+        actix_rt::spawn(async move {
+            self.message_handler(tx).await;
+            0
+        });
     }
 
-    async fn delete_contacts(&self) {
-        todo!()
+    fn clear_unreachable(&self, recipient: &str) -> bool {
+        clear_reachability(&self.context, Some(recipient), recipient, "CLEARED")
     }
 
-    fn start_handle(self, _: Sender<ChannelTx>) {
-        todo!()
+    fn is_linked(&self, _: &str) -> bool {
+        false
     }
 
     fn clone_boxed(&self) -> Box<dyn DocaBot + 'static> {
         Box::new(self.clone())
     }
-}
\ No newline at end of file
+}
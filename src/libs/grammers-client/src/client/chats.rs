@@ -124,6 +124,7 @@ impl ParticipantIter {
 
                 // Don't actually care for the chats, just the users.
                 let mut chats = ChatMap::new(full.users, Vec::new());
+                client.remember_chats(&chats);
                 let chats = Arc::get_mut(&mut chats).unwrap();
 
                 buffer.extend(
@@ -165,6 +166,7 @@ impl ParticipantIter {
 
                 // Don't actually care for the chats, just the users.
                 let mut chats = ChatMap::new(users, Vec::new());
+                iter.client.remember_chats(&chats);
                 let chats = Arc::get_mut(&mut chats).unwrap();
 
                 iter.buffer.extend(
@@ -342,6 +344,42 @@ impl ProfilePhotoIter {
 
 /// Method implementations related to dealing with chats or other users.
 impl Client {
+    /// Returns the packed chat for an id the client has already seen, be it in a dialog,
+    /// an update or a previous resolve, without contacting Telegram. Chats remembered in the
+    /// session's peer table are found even after a restart.
+    pub fn known_chat(&self, id: i64) -> Option<PackedChat> {
+        let cached = self.0.state.read().unwrap().chat_hashes.get(id);
+        cached.or_else(|| self.session().get_peer(id))
+    }
+
+    /// Fills in a missing access hash from the chats seen so far.
+    pub(crate) fn complete_chat(&self, chat: PackedChat) -> PackedChat {
+        match chat.access_hash {
+            Some(_) => chat,
+            None => self
+                .known_chat(chat.id)
+                .filter(|known| known.access_hash.is_some())
+                .unwrap_or(chat),
+        }
+    }
+
+    /// Stores every chat of the map in the session's peer table, along with the username and
+    /// phone used to look it up later.
+    pub(crate) fn remember_chats(&self, chats: &ChatMap) {
+        let session = self.session();
+        for chat in chats.iter() {
+            let mut packed = chat.pack();
+            if chat.is_min() {
+                packed.access_hash = None;
+            }
+            let phone = match chat {
+                Chat::User(user) => user.phone(),
+                _ => None,
+            };
+            session.insert_peer(packed, chat.username(), phone);
+        }
+    }
+
     /// Resolves a username into the chat that owns it, if any.
     ///
     /// Note that this method is expensive to call, and can quickly cause long flood waits.
@@ -623,6 +661,7 @@ impl Client {
     /// # }
     /// ```
     pub async fn unpack_chat(&self, packed_chat: PackedChat) -> Result<Chat, InvocationError> {
+        let packed_chat = self.complete_chat(packed_chat);
         Ok(match packed_chat.ty {
             PackedType::User | PackedType::Bot => {
                 let mut res = self
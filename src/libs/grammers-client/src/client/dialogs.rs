@@ -89,6 +89,7 @@ impl DialogIter {
         }
 
         let chats = ChatMap::new(users, chats);
+        self.client.remember_chats(&chats);
         let mut messages = messages
             .into_iter()
             .flat_map(|m| Message::new(&self.client, m, &chats))
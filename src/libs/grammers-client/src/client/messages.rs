@@ -39,6 +39,7 @@ fn map_random_ids_to_messages(
             seq: _,
         }) => {
             let chats = ChatMap::new(users, chats);
+            client.remember_chats(&chats);
 
             let rnd_to_id = updates
                 .iter()
@@ -171,6 +172,7 @@ impl<R: tl::RemoteCall<Return = tl::enums::messages::Messages>> IterBuffer<R, Me
         }
 
         let chats = ChatMap::new(users, chats);
+        self.client.remember_chats(&chats);
 
         let client = self.client.clone();
         self.buffer.extend(
@@ -474,7 +476,7 @@ impl Client {
         chat: C,
         message: M,
     ) -> Result<Message, InvocationError> {
-        let chat = chat.into();
+        let chat = self.complete_chat(chat.into());
         let message = message.into();
         let random_id = generate_random_id();
         let entities = parse_mention_entities(self, message.entities.clone());
@@ -771,6 +773,7 @@ impl Client {
         };
 
         let chats = ChatMap::new(users, chats);
+        self.remember_chats(&chats);
         Ok(messages
             .into_iter()
             .flat_map(|m| Message::new(self, m, &chats))
@@ -888,6 +891,7 @@ impl Client {
         };
 
         let chats = ChatMap::new(users, chats);
+        self.remember_chats(&chats);
         let mut map = messages
             .into_iter()
             .flat_map(|m| Message::new(self, m, &chats))
@@ -938,6 +942,7 @@ impl Client {
         };
 
         let chats = ChatMap::new(users, chats);
+        self.remember_chats(&chats);
         Ok(messages
             .into_iter()
             .flat_map(|m| Message::new(self, m, &chats))
@@ -283,6 +283,7 @@ impl Client {
     }
 
     fn extend_update_queue(&self, mut updates: Vec<tl::enums::Update>, chat_map: Arc<ChatMap>) {
+        self.remember_chats(&chat_map);
         let mut state = self.0.state.write().unwrap();
 
         if let Some(limit) = self.0.config.params.update_queue_limit {
@@ -0,0 +1,64 @@
+// Copyright 2020 - developers of the `grammers` project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+use super::{Chat, ChatMap};
+use crate::utils;
+use chrono::{DateTime, Utc};
+use grammers_tl_types as tl;
+use std::fmt;
+use std::sync::Arc;
+
+/// Occurs when a user blocks the bot, or unblocks it again. Bots only.
+#[derive(Clone)]
+pub struct BotStopped {
+    pub(crate) update: tl::types::UpdateBotStopped,
+    pub(crate) chats: Arc<ChatMap>,
+}
+
+impl BotStopped {
+    pub(crate) fn new(update: tl::types::UpdateBotStopped, chats: &Arc<ChatMap>) -> Self {
+        Self {
+            update,
+            chats: chats.clone(),
+        }
+    }
+
+    /// The ID of the user who blocked or unblocked the bot.
+    pub fn user_id(&self) -> i64 {
+        self.update.user_id
+    }
+
+    /// The user who blocked or unblocked the bot, if Telegram sent it along with the update.
+    pub fn user(&self) -> Option<&Chat> {
+        self.chats.get(
+            &tl::types::PeerUser {
+                user_id: self.update.user_id,
+            }
+            .into(),
+        )
+    }
+
+    /// `true` when the user blocked the bot, `false` when they unblocked it.
+    pub fn stopped(&self) -> bool {
+        self.update.stopped
+    }
+
+    /// When it happened.
+    pub fn date(&self) -> DateTime<Utc> {
+        utils::date(self.update.date)
+    }
+}
+
+impl fmt::Debug for BotStopped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BotStopped")
+            .field("user_id", &self.user_id())
+            .field("stopped", &self.stopped())
+            .field("user", &self.user())
+            .finish()
+    }
+}
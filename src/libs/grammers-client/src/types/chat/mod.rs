@@ -178,6 +178,15 @@ impl Chat {
         }
     }
 
+    // Whether the `access_hash` is a `min` one, which can't be used to address the chat.
+    pub(crate) fn is_min(&self) -> bool {
+        match self {
+            Self::User(user) => user.0.min,
+            Self::Group(_group) => false,
+            Self::Channel(channel) => channel.0.min,
+        }
+    }
+
     // get an chat photo downloadable
     pub fn photo_downloadable(&self, big: bool) -> Option<crate::types::Downloadable> {
         let peer = self.pack().to_input_peer();
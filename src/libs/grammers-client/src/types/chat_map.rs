@@ -75,6 +75,10 @@ impl ChatMap {
         self.map.remove(&peer.into())
     }
 
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Chat> {
+        self.map.values()
+    }
+
     pub(crate) fn remove_user(&mut self, user_id: i64) -> Option<User> {
         self.map
             .remove(&Peer::User(user_id))
@@ -220,6 +220,24 @@ impl InputMessage {
         self
     }
 
+    /// Include a location pin in the message.
+    ///
+    /// The text will be ignored, as Telegram does not allow captions on geo points.
+    pub fn location(mut self, latitude: f64, longitude: f64) -> Self {
+        self.media = Some(
+            (tl::types::InputMediaGeoPoint {
+                geo_point: (tl::types::InputGeoPoint {
+                    lat: latitude,
+                    long: longitude,
+                    accuracy_radius: None,
+                })
+                .into(),
+            })
+            .into(),
+        );
+        self
+    }
+
     /// Add additional attributes to the message.
     ///
     /// This must be called *after* setting a file.
@@ -0,0 +1,62 @@
+// Copyright 2020 - developers of the `grammers` project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+use super::{Chat, ChatMap};
+use grammers_tl_types as tl;
+use std::fmt;
+use std::sync::Arc;
+
+/// Occurs when the reactions to a message change.
+#[derive(Clone)]
+pub struct MessageReactions {
+    pub(crate) update: tl::types::UpdateMessageReactions,
+    pub(crate) chats: Arc<ChatMap>,
+}
+
+impl MessageReactions {
+    pub(crate) fn new(update: tl::types::UpdateMessageReactions, chats: &Arc<ChatMap>) -> Self {
+        Self {
+            update,
+            chats: chats.clone(),
+        }
+    }
+
+    /// The chat of the message.
+    pub fn chat(&self) -> Option<&Chat> {
+        self.chats.get(&self.update.peer)
+    }
+
+    /// The peer of the chat of the message.
+    pub fn peer(&self) -> &tl::enums::Peer {
+        &self.update.peer
+    }
+
+    /// The ID of the message that was reacted to.
+    pub fn message_id(&self) -> i32 {
+        self.update.msg_id
+    }
+
+    /// The thread the message is in, for forum topics.
+    pub fn top_message_id(&self) -> Option<i32> {
+        self.update.top_msg_id
+    }
+
+    /// The reactions the message has now.
+    pub fn reactions(&self) -> &tl::enums::MessageReactions {
+        &self.update.reactions
+    }
+}
+
+impl fmt::Debug for MessageReactions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MessageReactions")
+            .field("peer", &self.peer())
+            .field("message_id", &self.message_id())
+            .field("reactions", &self.reactions())
+            .finish()
+    }
+}
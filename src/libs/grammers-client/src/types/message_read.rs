@@ -0,0 +1,69 @@
+// Copyright 2020 - developers of the `grammers` project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+use super::{Chat, ChatMap};
+use grammers_tl_types as tl;
+use std::fmt;
+use std::sync::Arc;
+
+/// Occurs when messages in a chat are read, either by us on any of our devices (the inbox) or
+/// by the other side (the outbox, a read receipt).
+///
+/// Every message with an ID up to and including [`MessageRead::max_id`] counts as read.
+#[derive(Clone)]
+pub struct MessageRead {
+    pub(crate) peer: tl::enums::Peer,
+    pub(crate) max_id: i32,
+    pub(crate) still_unread_count: Option<i32>,
+    pub(crate) chats: Arc<ChatMap>,
+}
+
+impl MessageRead {
+    pub(crate) fn new(
+        peer: tl::enums::Peer,
+        max_id: i32,
+        still_unread_count: Option<i32>,
+        chats: &Arc<ChatMap>,
+    ) -> Self {
+        Self {
+            peer,
+            max_id,
+            still_unread_count,
+            chats: chats.clone(),
+        }
+    }
+
+    /// The chat whose messages were read.
+    pub fn chat(&self) -> Option<&Chat> {
+        self.chats.get(&self.peer)
+    }
+
+    /// The peer of the chat whose messages were read.
+    pub fn peer(&self) -> &tl::enums::Peer {
+        &self.peer
+    }
+
+    /// The ID of the newest message read.
+    pub fn max_id(&self) -> i32 {
+        self.max_id
+    }
+
+    /// How many messages are left unread, only known for the inbox.
+    pub fn still_unread_count(&self) -> Option<i32> {
+        self.still_unread_count
+    }
+}
+
+impl fmt::Debug for MessageRead {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MessageRead")
+            .field("peer", &self.peer())
+            .field("max_id", &self.max_id())
+            .field("still_unread_count", &self.still_unread_count())
+            .finish()
+    }
+}
@@ -8,6 +8,7 @@
 
 //! Custom types extending those provided by Telegram.
 pub mod attributes;
+pub mod bot_stopped;
 pub mod button;
 pub mod callback_query;
 pub mod chat;
@@ -22,15 +23,21 @@ pub mod login_token;
 pub mod media;
 pub mod message;
 pub mod message_deletion;
+pub mod message_reactions;
+pub mod message_read;
 pub mod participant;
+pub mod participant_update;
 pub mod password_token;
 pub mod permissions;
 pub mod photo_sizes;
 pub mod reply_markup;
 pub mod terms_of_service;
 pub mod update;
+pub mod user_status;
+pub mod user_typing;
 
 pub use attributes::Attribute;
+pub use bot_stopped::BotStopped;
 pub use callback_query::CallbackQuery;
 pub use chat::{Channel, Chat, Group, PackedChat, Platform, RestrictionReason, User};
 pub use chat_map::ChatMap;
@@ -46,9 +53,14 @@ pub(crate) use media::Uploaded;
 pub use media::{Media, Photo};
 pub use message::Message;
 pub use message_deletion::MessageDeletion;
+pub use message_reactions::MessageReactions;
+pub use message_read::MessageRead;
 pub use participant::{Participant, Role};
+pub use participant_update::{ParticipantList, ParticipantUpdate};
 pub use password_token::PasswordToken;
 pub use permissions::{Permissions, Restrictions};
 pub(crate) use reply_markup::ReplyMarkup;
 pub use terms_of_service::TermsOfService;
 pub use update::Update;
+pub use user_status::UserStatus;
+pub use user_typing::UserTyping;
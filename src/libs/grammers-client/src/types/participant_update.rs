@@ -0,0 +1,284 @@
+// Copyright 2020 - developers of the `grammers` project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+use super::{Chat, ChatMap};
+use crate::utils;
+use chrono::{DateTime, Utc};
+use grammers_tl_types as tl;
+use std::fmt;
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+pub(crate) enum RawParticipantUpdate {
+    Chat(tl::types::UpdateChatParticipant),
+    Channel(tl::types::UpdateChannelParticipant),
+}
+
+/// Occurs when someone joins or leaves a group or channel, or their role in it changes.
+#[derive(Clone)]
+pub struct ParticipantUpdate {
+    pub(crate) update: RawParticipantUpdate,
+    pub(crate) chats: Arc<ChatMap>,
+}
+
+impl ParticipantUpdate {
+    pub(crate) fn new(update: RawParticipantUpdate, chats: &Arc<ChatMap>) -> Self {
+        Self {
+            update,
+            chats: chats.clone(),
+        }
+    }
+
+    /// The peer of the group or channel.
+    pub fn peer(&self) -> tl::enums::Peer {
+        match &self.update {
+            RawParticipantUpdate::Chat(update) => tl::types::PeerChat {
+                chat_id: update.chat_id,
+            }
+            .into(),
+            RawParticipantUpdate::Channel(update) => tl::types::PeerChannel {
+                channel_id: update.channel_id,
+            }
+            .into(),
+        }
+    }
+
+    /// The group or channel.
+    pub fn chat(&self) -> Option<&Chat> {
+        self.chats.get(&self.peer())
+    }
+
+    /// The ID of the user whose membership changed.
+    pub fn user_id(&self) -> i64 {
+        match &self.update {
+            RawParticipantUpdate::Chat(update) => update.user_id,
+            RawParticipantUpdate::Channel(update) => update.user_id,
+        }
+    }
+
+    /// The user whose membership changed.
+    pub fn user(&self) -> Option<&Chat> {
+        self.chats.get(
+            &tl::types::PeerUser {
+                user_id: self.user_id(),
+            }
+            .into(),
+        )
+    }
+
+    /// The ID of the user who made the change, the same as [`ParticipantUpdate::user_id`]
+    /// when someone joined or left on their own.
+    pub fn actor_id(&self) -> i64 {
+        match &self.update {
+            RawParticipantUpdate::Chat(update) => update.actor_id,
+            RawParticipantUpdate::Channel(update) => update.actor_id,
+        }
+    }
+
+    /// When it happened.
+    pub fn date(&self) -> DateTime<Utc> {
+        match &self.update {
+            RawParticipantUpdate::Chat(update) => utils::date(update.date),
+            RawParticipantUpdate::Channel(update) => utils::date(update.date),
+        }
+    }
+
+    /// Whether the user was a member before the change.
+    pub fn was_member(&self) -> bool {
+        match &self.update {
+            RawParticipantUpdate::Chat(update) => update.prev_participant.is_some(),
+            RawParticipantUpdate::Channel(update) => update
+                .prev_participant
+                .as_ref()
+                .is_some_and(is_channel_member),
+        }
+    }
+
+    /// Whether the user is a member after the change.
+    pub fn is_member(&self) -> bool {
+        match &self.update {
+            RawParticipantUpdate::Chat(update) => update.new_participant.is_some(),
+            RawParticipantUpdate::Channel(update) => update
+                .new_participant
+                .as_ref()
+                .is_some_and(is_channel_member),
+        }
+    }
+
+    /// Whether the user joined.
+    pub fn joined(&self) -> bool {
+        !self.was_member() && self.is_member()
+    }
+
+    /// Whether the user left or was removed.
+    pub fn left(&self) -> bool {
+        self.was_member() && !self.is_member()
+    }
+}
+
+fn is_channel_member(participant: &tl::enums::ChannelParticipant) -> bool {
+    !matches!(
+        participant,
+        tl::enums::ChannelParticipant::Banned(_) | tl::enums::ChannelParticipant::Left(_)
+    )
+}
+
+/// Occurs when the member list of a small group changes, with the whole new list.
+#[derive(Clone)]
+pub struct ParticipantList {
+    pub(crate) participants: tl::enums::ChatParticipants,
+    pub(crate) chats: Arc<ChatMap>,
+}
+
+impl ParticipantList {
+    pub(crate) fn new(participants: tl::enums::ChatParticipants, chats: &Arc<ChatMap>) -> Self {
+        Self {
+            participants,
+            chats: chats.clone(),
+        }
+    }
+
+    /// The ID of the group.
+    pub fn chat_id(&self) -> i64 {
+        match &self.participants {
+            tl::enums::ChatParticipants::Forbidden(list) => list.chat_id,
+            tl::enums::ChatParticipants::Participants(list) => list.chat_id,
+        }
+    }
+
+    /// The group.
+    pub fn chat(&self) -> Option<&Chat> {
+        self.chats.get(
+            &tl::types::PeerChat {
+                chat_id: self.chat_id(),
+            }
+            .into(),
+        )
+    }
+
+    /// The IDs of the members. When we may not see the list, only we are in it.
+    pub fn user_ids(&self) -> Vec<i64> {
+        let participants = match &self.participants {
+            tl::enums::ChatParticipants::Forbidden(list) => {
+                list.self_participant.iter().collect::<Vec<_>>()
+            }
+            tl::enums::ChatParticipants::Participants(list) => list.participants.iter().collect(),
+        };
+        participants
+            .into_iter()
+            .map(|participant| match participant {
+                tl::enums::ChatParticipant::Participant(p) => p.user_id,
+                tl::enums::ChatParticipant::Creator(p) => p.user_id,
+                tl::enums::ChatParticipant::Admin(p) => p.user_id,
+            })
+            .collect()
+    }
+
+    /// The raw member list.
+    pub fn raw(&self) -> &tl::enums::ChatParticipants {
+        &self.participants
+    }
+}
+
+impl fmt::Debug for ParticipantUpdate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParticipantUpdate")
+            .field("peer", &self.peer())
+            .field("user_id", &self.user_id())
+            .field("actor_id", &self.actor_id())
+            .field("was_member", &self.was_member())
+            .field("is_member", &self.is_member())
+            .finish()
+    }
+}
+
+impl fmt::Debug for ParticipantList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParticipantList")
+            .field("chat_id", &self.chat_id())
+            .field("user_ids", &self.user_ids())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel_update(
+        prev: Option<tl::enums::ChannelParticipant>,
+        new: Option<tl::enums::ChannelParticipant>,
+    ) -> ParticipantUpdate {
+        ParticipantUpdate::new(
+            RawParticipantUpdate::Channel(tl::types::UpdateChannelParticipant {
+                via_chatlist: false,
+                channel_id: 1,
+                date: 0,
+                actor_id: 2,
+                user_id: 2,
+                prev_participant: prev,
+                new_participant: new,
+                invite: None,
+                qts: 0,
+            }),
+            &ChatMap::empty(),
+        )
+    }
+
+    #[test]
+    fn channel_members_join_and_leave() {
+        let member = || {
+            Some(tl::enums::ChannelParticipant::Participant(
+                tl::types::ChannelParticipant {
+                    user_id: 2,
+                    date: 0,
+                },
+            ))
+        };
+        let left = || {
+            Some(tl::enums::ChannelParticipant::Left(
+                tl::types::ChannelParticipantLeft {
+                    peer: tl::types::PeerUser { user_id: 2 }.into(),
+                },
+            ))
+        };
+
+        assert!(channel_update(None, member()).joined());
+        assert!(channel_update(left(), member()).joined());
+        assert!(channel_update(member(), left()).left());
+        assert!(channel_update(member(), None).left());
+        let promoted = channel_update(member(), member());
+        assert!(!promoted.joined() && !promoted.left());
+        assert_eq!(
+            promoted.peer(),
+            tl::types::PeerChannel { channel_id: 1 }.into()
+        );
+    }
+
+    #[test]
+    fn participant_list_reads_user_ids() {
+        let list = ParticipantList::new(
+            tl::enums::ChatParticipants::Participants(tl::types::ChatParticipants {
+                chat_id: 5,
+                participants: vec![
+                    tl::enums::ChatParticipant::Creator(tl::types::ChatParticipantCreator {
+                        user_id: 7,
+                    }),
+                    tl::enums::ChatParticipant::Participant(tl::types::ChatParticipant {
+                        user_id: 8,
+                        inviter_id: 7,
+                        date: 0,
+                    }),
+                ],
+                version: 1,
+            }),
+            &ChatMap::empty(),
+        );
+        assert_eq!(list.chat_id(), 5);
+        assert_eq!(list.user_ids(), vec![7, 8]);
+    }
+}
@@ -9,7 +9,11 @@ use std::sync::Arc;
 
 use grammers_tl_types as tl;
 
-use super::{CallbackQuery, ChatMap, InlineQuery, Message};
+use super::participant_update::RawParticipantUpdate;
+use super::{
+    BotStopped, CallbackQuery, ChatMap, InlineQuery, Message, MessageReactions, MessageRead,
+    ParticipantList, ParticipantUpdate, UserStatus, UserTyping,
+};
 use crate::{types::MessageDeletion, Client};
 
 #[non_exhaustive]
@@ -27,6 +31,24 @@ pub enum Update {
     /// Occurs whenever you sign in as a bot and a user sends an inline query
     /// such as `@bot query`.
     InlineQuery(InlineQuery),
+    /// Occurs when a user goes online or offline.
+    UserStatus(UserStatus),
+    /// Occurs when someone is typing or sending media in a chat.
+    UserTyping(UserTyping),
+    /// Occurs when we read incoming messages, on this or any other of our devices.
+    ReadHistoryInbox(MessageRead),
+    /// Occurs when the other side reads messages we sent.
+    ReadHistoryOutbox(MessageRead),
+    /// Occurs when the reactions to a message change.
+    MessageReactions(MessageReactions),
+    /// Occurs when someone joins or leaves a group or channel, or their role changes.
+    ChatParticipant(ParticipantUpdate),
+    /// Occurs when the member list of a small group changes.
+    ChatParticipants(ParticipantList),
+    /// Occurs when a user blocks or unblocks the bot.
+    BotStopped(BotStopped),
+    /// Occurs when a message is scheduled, to be sent by Telegram later.
+    NewScheduledMessage(Message),
     /// Raw events are not actual events.
     /// Instead, they are the raw Update object that Telegram sends. You
     /// normally shouldn’t need these.
@@ -62,6 +84,28 @@ impl Update {
                 ..
             }) => Message::new(client, message, chats).map(Self::MessageEdited),
 
+            // CallbackQuery
+            tl::enums::Update::BotCallbackQuery(query) => Some(Self::CallbackQuery(
+                CallbackQuery::new(client, query, chats),
+            )),
+
+            // InlineQuery
+            tl::enums::Update::BotInlineQuery(query) => {
+                Some(Self::InlineQuery(InlineQuery::new(client, query, chats)))
+            }
+
+            // NewScheduledMessage
+            tl::enums::Update::NewScheduledMessage(tl::types::UpdateNewScheduledMessage {
+                message,
+            }) => Message::new(client, message, chats).map(Self::NewScheduledMessage),
+
+            update => Self::from_raw(update, chats),
+        }
+    }
+
+    /// The updates that carry no message and don't need the client to wrap.
+    pub(crate) fn from_raw(update: tl::enums::Update, chats: &Arc<ChatMap>) -> Option<Self> {
+        match update {
             // MessageDeleted
             tl::enums::Update::DeleteMessages(tl::types::UpdateDeleteMessages {
                 messages, ..
@@ -74,14 +118,84 @@ impl Update {
                 messages, channel_id,
             ))),
 
-            // CallbackQuery
-            tl::enums::Update::BotCallbackQuery(query) => Some(Self::CallbackQuery(
-                CallbackQuery::new(client, query, chats),
+            // UserStatus
+            tl::enums::Update::UserStatus(update) => {
+                Some(Self::UserStatus(UserStatus::new(update, chats)))
+            }
+
+            // UserTyping
+            update @ (tl::enums::Update::UserTyping(_)
+            | tl::enums::Update::ChatUserTyping(_)
+            | tl::enums::Update::ChannelUserTyping(_)) => {
+                UserTyping::new(update, chats).map(Self::UserTyping)
+            }
+
+            // ReadHistoryInbox
+            tl::enums::Update::ReadHistoryInbox(tl::types::UpdateReadHistoryInbox {
+                peer,
+                max_id,
+                still_unread_count,
+                ..
+            }) => Some(Self::ReadHistoryInbox(MessageRead::new(
+                peer,
+                max_id,
+                Some(still_unread_count),
+                chats,
+            ))),
+            tl::enums::Update::ReadChannelInbox(tl::types::UpdateReadChannelInbox {
+                channel_id,
+                max_id,
+                still_unread_count,
+                ..
+            }) => Some(Self::ReadHistoryInbox(MessageRead::new(
+                tl::types::PeerChannel { channel_id }.into(),
+                max_id,
+                Some(still_unread_count),
+                chats,
+            ))),
+
+            // ReadHistoryOutbox
+            tl::enums::Update::ReadHistoryOutbox(tl::types::UpdateReadHistoryOutbox {
+                peer,
+                max_id,
+                ..
+            }) => Some(Self::ReadHistoryOutbox(MessageRead::new(
+                peer, max_id, None, chats,
+            ))),
+            tl::enums::Update::ReadChannelOutbox(tl::types::UpdateReadChannelOutbox {
+                channel_id,
+                max_id,
+            }) => Some(Self::ReadHistoryOutbox(MessageRead::new(
+                tl::types::PeerChannel { channel_id }.into(),
+                max_id,
+                None,
+                chats,
+            ))),
+
+            // MessageReactions
+            tl::enums::Update::MessageReactions(update) => {
+                Some(Self::MessageReactions(MessageReactions::new(update, chats)))
+            }
+
+            // ChatParticipant
+            tl::enums::Update::ChatParticipant(update) => Some(Self::ChatParticipant(
+                ParticipantUpdate::new(RawParticipantUpdate::Chat(update), chats),
+            )),
+            tl::enums::Update::ChannelParticipant(update) => Some(Self::ChatParticipant(
+                ParticipantUpdate::new(RawParticipantUpdate::Channel(update), chats),
             )),
 
-            // InlineQuery
-            tl::enums::Update::BotInlineQuery(query) => {
-                Some(Self::InlineQuery(InlineQuery::new(client, query, chats)))
+            // ChatParticipants
+            tl::enums::Update::ChatParticipants(tl::types::UpdateChatParticipants {
+                participants,
+            }) => Some(Self::ChatParticipants(ParticipantList::new(
+                participants,
+                chats,
+            ))),
+
+            // BotStopped
+            tl::enums::Update::BotStopped(update) => {
+                Some(Self::BotStopped(BotStopped::new(update, chats)))
             }
 
             // Raw
@@ -89,3 +203,163 @@ impl Update {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(update: impl Into<tl::enums::Update>) -> Update {
+        Update::from_raw(update.into(), &ChatMap::empty()).unwrap()
+    }
+
+    fn user(user_id: i64) -> tl::enums::Peer {
+        tl::types::PeerUser { user_id }.into()
+    }
+
+    #[test]
+    fn read_history_updates() {
+        let inbox = typed(tl::types::UpdateReadHistoryInbox {
+            folder_id: None,
+            peer: user(2),
+            max_id: 10,
+            still_unread_count: 3,
+            pts: 0,
+            pts_count: 0,
+        });
+        let Update::ReadHistoryInbox(read) = inbox else {
+            panic!("expected ReadHistoryInbox, got {:?}", inbox)
+        };
+        assert_eq!(read.peer(), &user(2));
+        assert_eq!(read.max_id(), 10);
+        assert_eq!(read.still_unread_count(), Some(3));
+
+        let channel = typed(tl::types::UpdateReadChannelInbox {
+            folder_id: None,
+            channel_id: 5,
+            max_id: 20,
+            still_unread_count: 0,
+            pts: 0,
+        });
+        let Update::ReadHistoryInbox(read) = channel else {
+            panic!("expected ReadHistoryInbox, got {:?}", channel)
+        };
+        assert_eq!(
+            read.peer(),
+            &tl::types::PeerChannel { channel_id: 5 }.into()
+        );
+        assert_eq!(read.still_unread_count(), Some(0));
+
+        let outbox = typed(tl::types::UpdateReadHistoryOutbox {
+            peer: user(2),
+            max_id: 11,
+            pts: 0,
+            pts_count: 0,
+        });
+        let Update::ReadHistoryOutbox(read) = outbox else {
+            panic!("expected ReadHistoryOutbox, got {:?}", outbox)
+        };
+        assert_eq!(read.peer(), &user(2));
+        assert_eq!(read.max_id(), 11);
+        assert_eq!(read.still_unread_count(), None);
+
+        let channel = typed(tl::types::UpdateReadChannelOutbox {
+            channel_id: 5,
+            max_id: 21,
+        });
+        let Update::ReadHistoryOutbox(read) = channel else {
+            panic!("expected ReadHistoryOutbox, got {:?}", channel)
+        };
+        assert_eq!(
+            read.peer(),
+            &tl::types::PeerChannel { channel_id: 5 }.into()
+        );
+        assert_eq!(read.max_id(), 21);
+    }
+
+    #[test]
+    fn reaction_updates() {
+        let reactions: tl::enums::MessageReactions = tl::types::MessageReactions {
+            min: false,
+            can_see_list: false,
+            reactions_as_tags: false,
+            results: Vec::new(),
+            recent_reactions: None,
+        }
+        .into();
+        let update = typed(tl::types::UpdateMessageReactions {
+            peer: user(2),
+            msg_id: 7,
+            top_msg_id: Some(6),
+            reactions: reactions.clone(),
+        });
+        let Update::MessageReactions(update) = update else {
+            panic!("expected MessageReactions, got {:?}", update)
+        };
+        assert_eq!(update.peer(), &user(2));
+        assert_eq!(update.message_id(), 7);
+        assert_eq!(update.top_message_id(), Some(6));
+        assert_eq!(update.reactions(), &reactions);
+    }
+
+    #[test]
+    fn participant_updates() {
+        let member = |user_id| {
+            Some(tl::enums::ChatParticipant::Participant(
+                tl::types::ChatParticipant {
+                    user_id,
+                    inviter_id: 1,
+                    date: 0,
+                },
+            ))
+        };
+        let update = typed(tl::types::UpdateChatParticipant {
+            chat_id: 5,
+            date: 0,
+            actor_id: 1,
+            user_id: 2,
+            prev_participant: None,
+            new_participant: member(2),
+            invite: None,
+            qts: 0,
+        });
+        let Update::ChatParticipant(update) = update else {
+            panic!("expected ChatParticipant, got {:?}", update)
+        };
+        assert_eq!(update.peer(), tl::types::PeerChat { chat_id: 5 }.into());
+        assert_eq!(update.user_id(), 2);
+        assert_eq!(update.actor_id(), 1);
+        assert!(update.joined());
+
+        let list = typed(tl::types::UpdateChatParticipants {
+            participants: tl::types::ChatParticipants {
+                chat_id: 5,
+                participants: vec![member(2).unwrap(), member(3).unwrap()],
+                version: 2,
+            }
+            .into(),
+        });
+        let Update::ChatParticipants(list) = list else {
+            panic!("expected ChatParticipants, got {:?}", list)
+        };
+        assert_eq!(list.chat_id(), 5);
+        assert_eq!(list.user_ids(), vec![2, 3]);
+    }
+
+    #[test]
+    fn bot_stopped_and_raw_updates() {
+        let update = typed(tl::types::UpdateBotStopped {
+            user_id: 2,
+            date: 0,
+            stopped: true,
+            qts: 0,
+        });
+        let Update::BotStopped(update) = update else {
+            panic!("expected BotStopped, got {:?}", update)
+        };
+        assert_eq!(update.user_id(), 2);
+        assert!(update.stopped());
+
+        let update = typed(tl::types::UpdateConfig {});
+        assert!(matches!(update, Update::Raw(tl::enums::Update::Config)));
+    }
+}
@@ -0,0 +1,73 @@
+// Copyright 2020 - developers of the `grammers` project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+use super::{Chat, ChatMap};
+use crate::utils;
+use chrono::{DateTime, Utc};
+use grammers_tl_types as tl;
+use std::fmt;
+use std::sync::Arc;
+
+/// Occurs when a user goes online or offline, or changes who may see when they were last seen.
+#[derive(Clone)]
+pub struct UserStatus {
+    pub(crate) update: tl::types::UpdateUserStatus,
+    pub(crate) chats: Arc<ChatMap>,
+}
+
+impl UserStatus {
+    pub(crate) fn new(update: tl::types::UpdateUserStatus, chats: &Arc<ChatMap>) -> Self {
+        Self {
+            update,
+            chats: chats.clone(),
+        }
+    }
+
+    /// The ID of the user whose status changed.
+    pub fn user_id(&self) -> i64 {
+        self.update.user_id
+    }
+
+    /// The user whose status changed, if Telegram sent it along with the update.
+    pub fn user(&self) -> Option<&Chat> {
+        self.chats.get(
+            &tl::types::PeerUser {
+                user_id: self.update.user_id,
+            }
+            .into(),
+        )
+    }
+
+    /// The raw status, for the approximate "recently" or "last week" variants.
+    pub fn status(&self) -> &tl::enums::UserStatus {
+        &self.update.status
+    }
+
+    /// Whether the user is online right now.
+    pub fn is_online(&self) -> bool {
+        matches!(self.update.status, tl::enums::UserStatus::Online(_))
+    }
+
+    /// When the user was last online, if they let others see it.
+    pub fn was_online(&self) -> Option<DateTime<Utc>> {
+        match &self.update.status {
+            tl::enums::UserStatus::Online(status) => Some(utils::date(status.expires)),
+            tl::enums::UserStatus::Offline(status) => Some(utils::date(status.was_online)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for UserStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserStatus")
+            .field("user_id", &self.user_id())
+            .field("status", &self.status())
+            .field("user", &self.user())
+            .finish()
+    }
+}
@@ -0,0 +1,107 @@
+// Copyright 2020 - developers of the `grammers` project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+use super::{Chat, ChatMap};
+use grammers_tl_types as tl;
+use std::fmt;
+use std::sync::Arc;
+
+/// Occurs when someone starts or stops typing, recording, or uploading in a chat.
+///
+/// Telegram repeats the action every few seconds while it lasts, and sends
+/// [`tl::enums::SendMessageAction::SendMessageCancelAction`] once it stops.
+#[derive(Clone)]
+pub struct UserTyping {
+    pub(crate) chat: tl::enums::Peer,
+    pub(crate) sender: tl::enums::Peer,
+    pub(crate) top_msg_id: Option<i32>,
+    pub(crate) action: tl::enums::SendMessageAction,
+    pub(crate) chats: Arc<ChatMap>,
+}
+
+impl UserTyping {
+    pub(crate) fn new(update: tl::enums::Update, chats: &Arc<ChatMap>) -> Option<Self> {
+        let (chat, sender, top_msg_id, action) = match update {
+            tl::enums::Update::UserTyping(update) => {
+                let peer: tl::enums::Peer = tl::types::PeerUser {
+                    user_id: update.user_id,
+                }
+                .into();
+                (peer.clone(), peer, None, update.action)
+            }
+            tl::enums::Update::ChatUserTyping(update) => (
+                tl::types::PeerChat {
+                    chat_id: update.chat_id,
+                }
+                .into(),
+                update.from_id,
+                None,
+                update.action,
+            ),
+            tl::enums::Update::ChannelUserTyping(update) => (
+                tl::types::PeerChannel {
+                    channel_id: update.channel_id,
+                }
+                .into(),
+                update.from_id,
+                update.top_msg_id,
+                update.action,
+            ),
+            _ => return None,
+        };
+        Some(Self {
+            chat,
+            sender,
+            top_msg_id,
+            action,
+            chats: chats.clone(),
+        })
+    }
+
+    /// The chat where the action happens. For private chats this is the typing user.
+    pub fn chat(&self) -> Option<&Chat> {
+        self.chats.get(&self.chat)
+    }
+
+    /// The peer of the chat where the action happens.
+    pub fn peer(&self) -> &tl::enums::Peer {
+        &self.chat
+    }
+
+    /// Who is typing.
+    pub fn sender(&self) -> Option<&Chat> {
+        self.chats.get(&self.sender)
+    }
+
+    /// The thread the action happens in, for forum topics.
+    pub fn top_message_id(&self) -> Option<i32> {
+        self.top_msg_id
+    }
+
+    /// What the user is doing.
+    pub fn action(&self) -> &tl::enums::SendMessageAction {
+        &self.action
+    }
+
+    /// Whether the user stopped whatever they were doing.
+    pub fn is_cancelled(&self) -> bool {
+        matches!(
+            self.action,
+            tl::enums::SendMessageAction::SendMessageCancelAction
+        )
+    }
+}
+
+impl fmt::Debug for UserTyping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserTyping")
+            .field("chat", &self.chat())
+            .field("sender", &self.sender())
+            .field("action", &self.action())
+            .finish()
+    }
+}
@@ -12,7 +12,7 @@ use std::fs::File;
 use std::io::{BufWriter, Write};
 use std::path::Path;
 
-const CURRENT_VERSION: i32 = 2;
+const CURRENT_VERSION: i32 = 3;
 
 fn main() -> std::io::Result<()> {
     let mut file = BufWriter::new(File::create(
@@ -20,13 +20,16 @@ fn main() -> std::io::Result<()> {
     )?);
 
     // Using boxed variants in the definitions so that deserialization fails if any constructor ID changes.
+    // `sessionV2` keeps the layout from before the peer table so older session files still load.
     let definitions = parse_tl_file(
         r#"
         dataCenter flags:# id:int ipv4:flags.0?int ipv6:flags.1?int128 port:int auth:flags.2?bytes = DataCenter;
         user id:long dc:int bot:Bool = User;
         channelState channel_id:long pts:int = ChannelState;
         updateState pts:int qts:int date:int seq:int channels:Vector<ChannelState> = UpdateState;
-        session flags:# dcs:Vector<DataCenter> user:flags.0?User state:flags.1?UpdateState = Session;
+        peer flags:# id:long ty:int access_hash:flags.0?long username:flags.1?string phone:flags.2?string = Peer;
+        sessionV2#a73eb8ce flags:# dcs:Vector<DataCenter> user:flags.0?User state:flags.1?UpdateState = Session;
+        session flags:# dcs:Vector<DataCenter> user:flags.0?User state:flags.1?UpdateState peers:flags.2?Vector<Peer> = Session;
         "#,
     )
     .map(Result::unwrap)
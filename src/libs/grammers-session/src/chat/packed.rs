@@ -26,6 +26,22 @@ pub enum PackedType {
     Gigagroup = 0b0011_1000,
 }
 
+impl TryFrom<u8> for PackedType {
+    type Error = ();
+
+    fn try_from(ty: u8) -> Result<Self, ()> {
+        Ok(match ty {
+            0b0000_0010 => PackedType::User,
+            0b0000_0011 => PackedType::Bot,
+            0b0000_0100 => PackedType::Chat,
+            0b0010_1000 => PackedType::Megagroup,
+            0b0011_0000 => PackedType::Broadcast,
+            0b0011_1000 => PackedType::Gigagroup,
+            _ => return Err(()),
+        })
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 /// A packed chat
 pub struct PackedChat {
@@ -62,15 +78,7 @@ impl PackedChat {
             return Err(());
         }
         let has_hash = (buf[0] & 0b0100_0000) != 0;
-        let ty = match buf[0] & 0b0011_1111 {
-            0b0000_0010 => PackedType::User,
-            0b0000_0011 => PackedType::Bot,
-            0b0000_0100 => PackedType::Chat,
-            0b0010_1000 => PackedType::Megagroup,
-            0b0011_0000 => PackedType::Broadcast,
-            0b0011_1000 => PackedType::Gigagroup,
-            _ => return Err(()),
-        };
+        let ty = PackedType::try_from(buf[0] & 0b0011_1111)?;
         let id = i64::from_le_bytes([
             buf[1], buf[2], buf[3], buf[4], buf[5], buf[6], buf[7], buf[8],
         ]);
@@ -6,10 +6,14 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 mod chat;
+// The accessors generated for the legacy `Session` constructor are not needed.
+#[allow(dead_code)]
 mod generated;
 mod message_box;
+mod peers;
 
 pub use chat::{ChatHashCache, PackedChat, PackedType};
+pub use generated::types::Peer;
 pub use generated::types::UpdateState;
 pub use generated::types::User;
 pub use generated::LAYER as VERSION;
@@ -22,6 +26,7 @@ use std::fs::{File, OpenOptions};
 use std::io::{self, Read, Seek, Write};
 use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6};
 use std::path::Path;
+use peers::PeerTable;
 use std::sync::Mutex;
 
 // Needed for auto-generated definitions.
@@ -29,6 +34,7 @@ use grammers_tl_types::{deserialize, Deserializable, Identifiable, Serializable}
 
 pub struct Session {
     session: Mutex<types::Session>,
+    peers: Mutex<PeerTable>,
 }
 
 #[allow(clippy::new_without_default)]
@@ -39,7 +45,9 @@ impl Session {
                 dcs: Vec::new(),
                 user: None,
                 state: None,
+                peers: None,
             }),
+            peers: Mutex::new(PeerTable::default()),
         }
     }
 
@@ -66,15 +74,23 @@ impl Session {
     }
 
     pub fn load(data: &[u8]) -> Result<Self, Error> {
+        let mut session = match enums::Session::from_bytes(data).map_err(|e| match e {
+            DeserializeError::UnexpectedEof => Error::MalformedData,
+            DeserializeError::UnexpectedConstructor { .. } => Error::UnsupportedVersion,
+        })? {
+            enums::Session::Session(session) => session,
+            enums::Session::V2(types::SessionV2 { dcs, user, state }) => types::Session {
+                dcs,
+                user,
+                state,
+                peers: None,
+            },
+        };
+        // The peers live in their own table while the session is loaded.
+        let peers = PeerTable::new(session.peers.take());
         Ok(Self {
-            session: Mutex::new(
-                enums::Session::from_bytes(data)
-                    .map_err(|e| match e {
-                        DeserializeError::UnexpectedEof => Error::MalformedData,
-                        DeserializeError::UnexpectedConstructor { .. } => Error::UnsupportedVersion,
-                    })?
-                    .into(),
-            ),
+            session: Mutex::new(session),
+            peers: Mutex::new(peers),
         })
     }
 
@@ -165,9 +181,35 @@ impl Session {
             .collect()
     }
 
+    /// Remembers a chat in the peer table. An existing access hash, username or phone is kept
+    /// when the new information lacks it.
+    pub fn insert_peer(&self, chat: PackedChat, username: Option<&str>, phone: Option<&str>) {
+        self.peers.lock().unwrap().remember(chat, username, phone);
+    }
+
+    pub fn get_peer(&self, id: i64) -> Option<PackedChat> {
+        self.peers.lock().unwrap().get(id)
+    }
+
+    /// Looks a peer up by username, ignoring case and a leading `@`.
+    pub fn get_peer_by_username(&self, username: &str) -> Option<PackedChat> {
+        self.peers.lock().unwrap().by_username(username)
+    }
+
+    /// Looks a peer up by phone number, comparing only the digits.
+    pub fn get_peer_by_phone(&self, phone: &str) -> Option<PackedChat> {
+        self.peers.lock().unwrap().by_phone(phone)
+    }
+
+    pub fn get_peers(&self) -> Vec<Peer> {
+        self.peers.lock().unwrap().values().cloned().collect()
+    }
+
     #[must_use]
     pub fn save(&self) -> Vec<u8> {
-        enums::Session::Session(self.session.lock().unwrap().clone()).to_bytes()
+        let mut session = self.session.lock().unwrap().clone();
+        session.peers = self.peers.lock().unwrap().to_vec();
+        enums::Session::Session(session).to_bytes()
     }
 
     /// Saves the session to a file.
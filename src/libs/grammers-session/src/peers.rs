@@ -0,0 +1,117 @@
+// Copyright 2020 - developers of the `grammers` project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+use crate::generated::{enums, types};
+use crate::{PackedChat, PackedType};
+use std::collections::HashMap;
+
+/// The known peers by id, with indexes to find them by username or phone number.
+#[derive(Default)]
+pub(crate) struct PeerTable {
+    peers: HashMap<i64, types::Peer>,
+    usernames: HashMap<String, i64>,
+    phones: HashMap<String, i64>,
+}
+
+/// Usernames are compared ignoring case and a leading `@`.
+fn username_key(username: &str) -> String {
+    username.trim_start_matches('@').to_ascii_lowercase()
+}
+
+/// Phone numbers are compared by their digits only.
+fn phone_key(phone: &str) -> String {
+    phone.chars().filter(char::is_ascii_digit).collect()
+}
+
+impl PeerTable {
+    pub(crate) fn new(peers: Option<Vec<enums::Peer>>) -> Self {
+        let mut table = Self::default();
+        for enums::Peer::Peer(peer) in peers.into_iter().flatten() {
+            table.insert(peer);
+        }
+        table
+    }
+
+    /// Remembers a chat. An existing access hash, username or phone is kept when the new
+    /// information lacks it.
+    pub(crate) fn remember(&mut self, chat: PackedChat, username: Option<&str>, phone: Option<&str>) {
+        let previous = self.peers.get(&chat.id);
+        let peer = types::Peer {
+            id: chat.id,
+            ty: chat.ty as i32,
+            access_hash: chat
+                .access_hash
+                .or(previous.and_then(|peer| peer.access_hash)),
+            username: username
+                .map(|username| username.trim_start_matches('@').to_string())
+                .or(previous.and_then(|peer| peer.username.clone())),
+            phone: phone
+                .map(String::from)
+                .or(previous.and_then(|peer| peer.phone.clone())),
+        };
+        self.insert(peer);
+    }
+
+    fn insert(&mut self, peer: types::Peer) {
+        if let Some(previous) = self.peers.remove(&peer.id) {
+            // Usernames and numbers change hands, only drop the entries still pointing here.
+            if let Some(username) = previous.username.as_deref() {
+                let key = username_key(username);
+                if self.usernames.get(&key) == Some(&peer.id) {
+                    self.usernames.remove(&key);
+                }
+            }
+            if let Some(phone) = previous.phone.as_deref() {
+                let key = phone_key(phone);
+                if self.phones.get(&key) == Some(&peer.id) {
+                    self.phones.remove(&key);
+                }
+            }
+        }
+        if let Some(username) = peer.username.as_deref() {
+            self.usernames.insert(username_key(username), peer.id);
+        }
+        if let Some(phone) = peer.phone.as_deref().map(phone_key) {
+            if !phone.is_empty() {
+                self.phones.insert(phone, peer.id);
+            }
+        }
+        self.peers.insert(peer.id, peer);
+    }
+
+    pub(crate) fn get(&self, id: i64) -> Option<PackedChat> {
+        let peer = self.peers.get(&id)?;
+        Some(PackedChat {
+            ty: PackedType::try_from(peer.ty as u8).ok()?,
+            id: peer.id,
+            access_hash: peer.access_hash,
+        })
+    }
+
+    pub(crate) fn by_username(&self, username: &str) -> Option<PackedChat> {
+        self.get(*self.usernames.get(&username_key(username))?)
+    }
+
+    pub(crate) fn by_phone(&self, phone: &str) -> Option<PackedChat> {
+        self.get(*self.phones.get(&phone_key(phone))?)
+    }
+
+    pub(crate) fn values(&self) -> impl Iterator<Item = &types::Peer> {
+        self.peers.values()
+    }
+
+    /// The table in the form the session file stores it, ordered by id so saving the same
+    /// peers gives the same bytes.
+    pub(crate) fn to_vec(&self) -> Option<Vec<enums::Peer>> {
+        if self.peers.is_empty() {
+            return None;
+        }
+        let mut peers: Vec<types::Peer> = self.peers.values().cloned().collect();
+        peers.sort_by_key(|peer| peer.id);
+        Some(peers.into_iter().map(enums::Peer::Peer).collect())
+    }
+}
@@ -1,15 +1,27 @@
-use std::collections::HashMap;
 use std::fs;
 use std::sync::Arc;
 use actix_web::{App, HttpServer, web};
 use simple_logger::SimpleLogger;
-use crate::bot::{BotAuth, DocaBot};
-use crate::bot::telegram::{Telegram, TelegramAuth};
-use crate::structs::api::{AppData, BotContext};
-use crate::structs::auth::{AuthData, AuthList};
+use crate::api::auth::{ApiAuth, ServerConfig};
+use crate::bot::rules::RuleBook;
+use crate::bot::telegram::TelegramAuth;
+use crate::bot::telegram::session::SessionBackend;
+use crate::bot::whatsapp::WhatsappAuth;
+use crate::structs::api::AppData;
+use crate::structs::auth::{AuthData, AuthList, WhatsAppAuthList};
 use crate::structs::wrapper::ChannelTx;
 use crate::utils::JsonConfigs;
-use crate::wrapper::wrapper::{BotStorage, Wrapper};
+use crate::wrapper::dialogs::DialogStore;
+use crate::wrapper::metrics::Metrics;
+use crate::wrapper::persist::{self, Flush};
+use crate::wrapper::pools::Pools;
+use crate::wrapper::contacts::ContactStore;
+use crate::wrapper::reachability::ReachabilityStore;
+use crate::wrapper::receipts::ReceiptStore;
+use crate::wrapper::queue::MessageQueue;
+use crate::wrapper::scheduler::Scheduler;
+use crate::wrapper::storage::{BotFactory, BotStorage};
+use crate::wrapper::wrapper::Wrapper;
 
 pub mod structs;
 
@@ -22,6 +34,18 @@ mod wrapper;
 
 // const SESSION_FILE: &str = "community_telegram.session";
 const SESSION_FOLDER: &str = "sessions";
+const QUEUE_FILE: &str = "configs/queue.json";
+const SCHEDULE_FILE: &str = "configs/schedule.json";
+const RULES_FILE: &str = "configs/reply_rules.json";
+const AUTH_FILE: &str = "configs/auth_data.json";
+const WHATSAPP_AUTH_FILE: &str = "configs/whatsapp_auth.json";
+const SESSION_STORAGE_FILE: &str = "configs/session_storage.json";
+const SERVER_FILE: &str = "configs/server.json";
+const POOLS_FILE: &str = "configs/pools.json";
+const DIALOGS_FILE: &str = "configs/dialogs.json";
+const RECEIPTS_FILE: &str = "configs/receipts.json";
+const REACHABILITY_FILE: &str = "configs/reachability.json";
+const CONTACTS_FILE: &str = "configs/contacts.json";
 
 
 
@@ -30,8 +54,11 @@ fn get_configs(file_name: &str) -> AuthList {
         println!("[!] {} not found", file_name);
         return AuthList::default();
     }
-    let file_contents: String = fs::read_to_string(file_name).unwrap();
-    serde_json::from_str::<AuthList>(&file_contents.clone()).unwrap_or_else(|e| {
+    let file_contents = fs::read_to_string(file_name).unwrap_or_else(|e| {
+        println!("[!] Can't read {}: {}", file_name, e);
+        String::new()
+    });
+    serde_json::from_str::<AuthList>(&file_contents).unwrap_or_else(|e| {
         println!("[!] Config file is corrupted: {:?}", e);
         AuthList::default()
     })
@@ -41,59 +68,124 @@ fn get_configs(file_name: &str) -> AuthList {
 async fn async_main() -> std::io::Result<()> {
 
     if fs::metadata(format!("configs/{}", SESSION_FOLDER)).is_err() {
-        fs::create_dir_all(format!("configs/{}", SESSION_FOLDER)).unwrap();
+        fs::create_dir_all(format!("configs/{}", SESSION_FOLDER))?;
     }
 
-    let mut bot_list: BotStorage = HashMap::new();
-    let app_data  = TelegramAuth::from_file("configs/telegram.json");
-
-    for ( bot_name, auth_data ) in get_configs("configs/auth_data.json").iter() {
-        let mut bot = Telegram::new(
-            bot_name.clone(),
-            BotAuth::TelegramAuth(app_data.clone()),
-            BotContext{
-                bot_name: bot_name.clone(),
-                api_url: auth_data.api_url.clone()
-        }).await;
-        bot.sign_in(bot_name.clone(), AuthData::Telegram(auth_data.clone())).await.unwrap();
-        bot.dialogs = bot.get_dialogs().await.unwrap();
-        bot_list.insert(bot_name.clone(), Box::new(bot.clone()));
+    let server_config = Arc::new(ServerConfig::from_file(SERVER_FILE));
+    if server_config.api_keys.is_empty() {
+        println!("[!] No API keys in {}, the API is open to anyone who can reach {}", SERVER_FILE, server_config.bind);
+    }
+    let tls = server_config.rustls()
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+
+    let rules = Arc::new(RuleBook::load(RULES_FILE));
+    let whatsapp_data = WhatsappAuth::from_file("configs/whatsapp.json");
+    if whatsapp_data.app_secret.is_empty() {
+        println!("[!] No app_secret in configs/whatsapp.json, WhatsApp webhook notifications will be rejected");
+    }
+    let sessions = SessionBackend::from_file(SESSION_STORAGE_FILE).open()
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+    let metrics = Arc::new(Metrics::default());
+    let dialogs = Arc::new(DialogStore::load(DIALOGS_FILE));
+    let receipts = Arc::new(ReceiptStore::load(RECEIPTS_FILE));
+    let reachability = Arc::new(ReachabilityStore::load(REACHABILITY_FILE));
+    let contacts = Arc::new(ContactStore::load(CONTACTS_FILE));
+    let factory = BotFactory {
+        telegram: TelegramAuth::from_file("configs/telegram.json"),
+        sessions,
+        whatsapp: whatsapp_data.clone(),
+        rules,
+        metrics: metrics.clone(),
+        dialogs: dialogs.clone(),
+        receipts: receipts.clone(),
+        reachability: reachability.clone(),
+        contacts: contacts.clone()
     };
 
     let (bot_tx, bot_rx) = tokio::sync::mpsc::channel::<ChannelTx>(4096);
-    let bot_list: Arc<BotStorage> = Arc::new(bot_list);
-
-    for (_, bot_instance) in bot_list.iter() {
-        let bot_clone: Arc<Box<dyn DocaBot>> = Arc::new(bot_instance.clone());
-        let tx_clone = bot_tx.clone();
-        actix_rt::spawn(async move {
-            bot_clone.message_handler(tx_clone).await;
-            0
-        });
-    }
+    let bot_list = Arc::new(BotStorage::new(factory, bot_tx.clone()).with_files(AUTH_FILE, WHATSAPP_AUTH_FILE));
+    let queue = Arc::new(MessageQueue::load(QUEUE_FILE));
+    let scheduler = Arc::new(Scheduler::load(SCHEDULE_FILE));
+    let stores: Vec<Arc<dyn Flush>> = vec![dialogs.clone(), receipts.clone(), reachability.clone(), contacts.clone()];
+    persist::flush_periodically(stores.clone());
+
+    let accounts = get_configs(AUTH_FILE).into_iter()
+        .map(|(bot_name, auth_data)| (bot_name, AuthData::Telegram(auth_data)))
+        .chain(WhatsAppAuthList::from_file(WHATSAPP_AUTH_FILE).into_iter()
+            .map(|(bot_name, auth_data)| (bot_name, AuthData::WhatsApp(auth_data))));
+    for ( bot_name, auth_data ) in accounts {
+        if let Err(e) = bot_list.start(&bot_name, auth_data).await {
+            println!("[!] {} failed to start: {}, retrying in the background", bot_name, e);
+        }
+    };
+    bot_list.supervise();
 
-    let wrapper = Wrapper::new(bot_list, bot_rx);
+    let pools = Arc::new(Pools::load(POOLS_FILE));
+    let wrapper = Wrapper::new(bot_list.clone(), bot_rx, queue.clone(), scheduler.clone(), metrics.clone(), pools);
     Wrapper::exec(Arc::<Wrapper>::new(wrapper));
 
-    HttpServer::new(move || {
+    let address = (server_config.bind.clone(), server_config.port);
+    let server = HttpServer::new(move || {
         let app_data = AppData {
-            tx: bot_tx.clone()
+            tx: bot_tx.clone(),
+            bots: bot_list.clone(),
+            queue: queue.clone(),
+            scheduler: scheduler.clone(),
+            whatsapp: whatsapp_data.clone(),
+            metrics: metrics.clone(),
+            dialogs: dialogs.clone(),
+            receipts: receipts.clone(),
+            reachability: reachability.clone()
         };
         App::new()
+            .wrap(ApiAuth::new(server_config.clone()))
             .app_data(web::Data::new(app_data))
             .service(api::send_message)
+            .service(api::send_message_upload)
             .service(api::add_contact)
-    })
-        .bind(("127.0.0.1", 1052))?
-        .run()
-        .await
+            .service(api::add_contacts)
+            .service(api::get_queue)
+            .service(api::replay_message)
+            .service(api::discard_message)
+            .service(api::whatsapp_verify)
+            .service(api::whatsapp_webhook)
+            .service(api::login_status)
+            .service(api::login_start)
+            .service(api::login_code)
+            .service(api::login_password)
+            .service(api::list_bots)
+            .service(api::add_bot)
+            .service(api::remove_bot)
+            .service(api::restart_bot)
+            .service(api::list_dialogs)
+            .service(api::sync_dialogs)
+            .service(api::message_status)
+            .service(api::list_contacts)
+            .service(api::delete_contacts)
+            .service(api::purge_contacts)
+            .service(api::list_unreachable)
+            .service(api::clear_unreachable)
+            .service(api::schedule_message)
+            .service(api::list_scheduled)
+            .service(api::reschedule_message)
+            .service(api::cancel_scheduled)
+            .service(api::healthz)
+            .service(api::readyz)
+            .service(api::metrics)
+    });
+    let result = match tls {
+        Some(tls) => server.bind_rustls_0_22(address, tls)?.run().await,
+        None => server.bind(address)?.run().await
+    };
+    // Whatever changed since the last periodic flush.
+    stores.iter().for_each(|store| store.flush());
+    result
 }
 
-fn main() {
-    SimpleLogger::new()
-        .with_level(log::LevelFilter::Debug)
-        .init()
-        .unwrap();
+fn main() -> std::io::Result<()> {
+    if let Err(e) = SimpleLogger::new().with_level(log::LevelFilter::Debug).init() {
+        println!("[!] Logger is not available: {}", e);
+    }
 
     actix_rt::System::with_tokio_rt( || {
         tokio::runtime::Builder::new_multi_thread()
@@ -101,6 +193,6 @@ fn main() {
             .worker_threads(8)
             .thread_name( "actix" )
             .build()
-            .unwrap()
-    } ).block_on(async_main()).unwrap();
+            .expect("the tokio runtime can't start")
+    } ).block_on(async_main())
 }
@@ -2,7 +2,21 @@ use std::collections::HashMap;
 use grammers_session::PackedChat;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use crate::structs::wrapper::ChannelTx;
+use base64::Engine;
+use crate::structs::wrapper::{ChannelTx, ScheduleMode};
+use crate::bot::rules::RuleBook;
+use crate::structs::auth::AuthData;
+use crate::bot::webhook::WebhookConfig;
+use crate::bot::whatsapp::WhatsappAuth;
+use crate::wrapper::dialogs::DialogStore;
+use crate::wrapper::contacts::ContactStore;
+use crate::wrapper::reachability::ReachabilityStore;
+use crate::wrapper::receipts::ReceiptStore;
+use crate::wrapper::metrics::{BotMetrics, Metrics};
+use crate::wrapper::queue::MessageQueue;
+use crate::wrapper::scheduler::Scheduler;
+use crate::wrapper::storage::BotStorage;
+use crate::utils;
 #[cfg(test)]
 use crate::utils::JsonConfigs;
 
@@ -21,6 +35,14 @@ pub struct UserHandler {
 
 pub struct AppData {
     pub tx: tokio::sync::mpsc::Sender<ChannelTx>,
+    pub bots: std::sync::Arc<BotStorage>,
+    pub queue: std::sync::Arc<MessageQueue>,
+    pub scheduler: std::sync::Arc<Scheduler>,
+    pub whatsapp: WhatsappAuth,
+    pub metrics: std::sync::Arc<Metrics>,
+    pub dialogs: std::sync::Arc<DialogStore>,
+    pub receipts: std::sync::Arc<ReceiptStore>,
+    pub reachability: std::sync::Arc<ReachabilityStore>
 }
 
 #[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -29,10 +51,39 @@ pub struct BotButtons {
     pub reply: String
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone)]
 pub struct BotContext {
     pub bot_name: String,
-    pub api_url: String
+    pub api_url: String,
+    pub rules: std::sync::Arc<RuleBook>,
+    pub webhook: Option<WebhookConfig>,
+    pub metrics: std::sync::Arc<BotMetrics>,
+    pub dialogs: std::sync::Arc<DialogStore>,
+    pub receipts: std::sync::Arc<ReceiptStore>,
+    pub reachability: std::sync::Arc<ReachabilityStore>,
+    pub contacts: std::sync::Arc<ContactStore>
+}
+
+impl BotContext {
+    /// Sends the request to the backend, recording how long it took.
+    pub async fn call_backend(&self, request: &ApiRequest) -> reqwest::Result<reqwest::Response> {
+        let started = std::time::Instant::now();
+        let response = request.send(&self.api_url).await;
+        self.metrics.observe_backend(started.elapsed());
+        response
+    }
+}
+
+/// Everything known about an inbound message besides its plain text.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MessageMeta {
+    pub username: Option<String>,
+    pub phone: Option<String>,
+    pub markdown: String,
+    pub html: String,
+    pub media: Option<Value>,
+    pub reply_to: Option<i32>,
+    pub date: i64
 }
 
 #[derive(Clone, PartialEq)]
@@ -40,18 +91,216 @@ pub struct TelegramMessage {
     pub id: i32,
     pub ctx: PackedChat,
     pub user: String,
-    pub text: String
+    pub text: String,
+    pub meta: MessageMeta
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ApiRequest {
-    #[serde(skip_serializing)]
+    #[serde(default, skip_serializing)]
     pub api_url: String,
     pub object: String,
     pub command: String,
     pub data: Value
 }
 
+impl ApiRequest {
+    /// Posts the request to its own `api_url`, falling back to `default_url` when it is empty.
+    /// An error status from the backend is an error too.
+    pub async fn send(&self, default_url: &str) -> reqwest::Result<reqwest::Response> {
+        let url = if self.api_url.is_empty() { default_url } else { &self.api_url };
+        reqwest::Client::new()
+            .post(url)
+            .json(self)
+            .send()
+            .await?
+            .error_for_status()
+    }
+}
+
+/// What the bot learned about a recipient while delivering a request.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeliveryResult {
+    pub message_id: Option<i32>,
+    pub chat_id: Option<i64>,
+    pub access_hash: Option<i64>,
+    /// Message id on backends that don't use numeric ids, such as a WhatsApp `wamid`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_id: Option<String>,
+    /// The account that sent the message when it was addressed to a pool.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    /// Per-contact outcome of a batch import.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub contacts: Vec<ContactImport>,
+    /// Set when the send was accepted but held back by the account's rate limits, it stays
+    /// in the outbound queue under this id.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queue_id: Option<String>,
+    /// When a held back send is tried next.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub send_at: Option<i64>
+}
+
+impl DeliveryResult {
+    pub fn is_queued(&self) -> bool {
+        self.queue_id.is_some()
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeliveryError {
+    pub code: i32,
+    pub name: String,
+    pub value: Option<u32>,
+    pub message: String,
+    /// Set when the message stays in the outbound queue and will be retried under this id.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queue_id: Option<String>
+}
+
+pub type DeliveryReply = Result<DeliveryResult, DeliveryError>;
+
+impl DeliveryError {
+    pub fn new(code: i32, name: &str, message: String) -> Self {
+        DeliveryError { code, name: name.to_string(), value: None, message, queue_id: None }
+    }
+
+    pub fn unknown_bot(bot_name: &str) -> Self {
+        DeliveryError::new(404, "UNKNOWN_BOT", format!("bot {} is not registered", bot_name))
+    }
+
+    /// Flood waits, Telegram-side internal errors and connection problems go away on their own;
+    /// anything else will fail the same way on every retry.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self.name.as_str(), "FLOOD_WAIT" | "TRANSPORT") || self.code >= 500 || self.code == -503
+    }
+}
+
+impl std::fmt::Display for DeliveryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}): {}", self.name, self.code, self.message)
+    }
+}
+
+impl std::error::Error for DeliveryError {}
+
+/// Keeps the RPC error name and value (e.g. `FLOOD_WAIT` and its seconds) when the bot
+/// failed on a Telegram call, so the caller can react to it.
+impl From<&utils::Error> for DeliveryError {
+    fn from(error: &utils::Error) -> Self {
+        let value = match error {
+            utils::Error::Rpc { value, .. } => *value,
+            _ => None
+        };
+        DeliveryError {
+            code: error.code(),
+            name: error.name().to_string(),
+            value,
+            message: error.message().to_string(),
+            queue_id: None
+        }
+    }
+}
+
+impl From<utils::Error> for DeliveryError {
+    fn from(error: utils::Error) -> Self {
+        DeliveryError::from(&error)
+    }
+}
+
+impl From<DeliveryError> for utils::Error {
+    fn from(error: DeliveryError) -> Self {
+        utils::Error::Request { code: error.code, name: error.name, message: error.message }
+    }
+}
+
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum LoginStatus {
+    Authorized,
+    #[default]
+    PendingLogin,
+    CodeSent,
+    PasswordRequired { hint: Option<String> }
+}
+
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BotStatus {
+    pub name: String,
+    pub messenger: String,
+    pub connected: bool,
+    pub authorized: bool,
+    pub login: LoginStatus,
+    /// Unix time of the last update received from the messenger.
+    pub last_update: Option<i64>,
+    pub queue_depth: usize,
+    /// The most recent failure, which the bot recovers from instead of stopping.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<DeliveryError>,
+    /// Unix time of `last_error`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error_at: Option<i64>
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AddBotRequest {
+    pub name: String,
+    pub auth: AuthData
+}
+
+/// `unread=true` keeps only the chats with messages nobody has read yet.
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DialogsQuery {
+    #[serde(default)]
+    pub unread: bool
+}
+
+/// A page of the account's contact list, `limit` defaults to 100.
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ContactsQuery {
+    #[serde(default)]
+    pub offset: usize,
+    pub limit: Option<usize>
+}
+
+/// Contacts to delete, each a Telegram user id or a phone number.
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DeleteContactsRequest {
+    pub contacts: Vec<String>
+}
+
+/// Deletes the contacts the bot imported more than `older_than_days` ago.
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PurgeContactsRequest {
+    pub older_than_days: u32
+}
+
+/// An entry of the account's contact list. `added_at` is only known for contacts the bot
+/// imported itself.
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ContactInfo {
+    pub user_id: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_hash: Option<i64>,
+    pub phone: String,
+    pub first_name: String,
+    pub last_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    pub mutual: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub added_at: Option<i64>
+}
+
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LoginRequest {
+    pub phone: Option<String>,
+    pub code: Option<String>,
+    pub password: Option<String>
+}
+
+#[allow(clippy::large_enum_variant)]
 #[derive(PartialEq)]
 pub enum BotRequestType {
     RequestContact(AddContactRequest),
@@ -60,6 +309,8 @@ pub enum BotRequestType {
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AddContactRequest {
+    /// Only needed on its own, contacts of a batch go through the batch's account.
+    #[serde(default)]
     pub messenger: String,
     pub api_id: String,
     pub first_name: String,
@@ -67,12 +318,71 @@ pub struct AddContactRequest {
     pub phone: String
 }
 
+/// Contacts imported together, in as few `contacts.importContacts` calls as possible.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AddContactsRequest {
+    pub messenger: String,
+    pub contacts: Vec<AddContactRequest>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContactStatus {
+    /// Imported now and linked to a Telegram user.
+    Imported,
+    /// The phone was linked to a user earlier in this run, it wasn't imported again.
+    Linked,
+    /// No Telegram account uses the phone.
+    NotOnTelegram,
+    /// Telegram asked to import the phone again later.
+    Retry,
+    /// The account used up its contact imports, see `retry_after`.
+    RateLimited
+}
+
+/// How one contact of an import went, matched back to the CRM by `api_id`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ContactImport {
+    pub api_id: String,
+    pub phone: String,
+    pub status: ContactStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chat_id: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_hash: Option<i64>,
+    /// Seconds until the account may import contacts again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<i64>
+}
+
+impl ContactImport {
+    pub fn new(contact: &AddContactRequest, status: ContactStatus) -> Self {
+        ContactImport {
+            api_id: contact.api_id.clone(),
+            phone: contact.phone.clone(),
+            status,
+            chat_id: None,
+            access_hash: None,
+            retry_after: None
+        }
+    }
+}
+
 #[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct UserData {
     pub phone: String,
     pub messenger_id: Option<String>
 }
 
+/// A pre-approved WhatsApp message template, see the Cloud API `template` object.
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MessageTemplate {
+    pub name: String,
+    pub language: String,
+    #[serde(default)]
+    pub components: Vec<Value>
+}
+
 #[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct SendMessageRequest {
     pub messenger: String,
@@ -80,7 +390,83 @@ pub struct SendMessageRequest {
     pub message: String,
     pub access_hash: Option<i64>,
     pub buttons: Option<Vec<BotButtons>>,
-    pub handlers: Option<BotHandler>
+    pub handlers: Option<BotHandler>,
+    #[serde(default)]
+    pub template: Option<MessageTemplate>,
+    /// When set, `message` becomes the caption of the attachment.
+    #[serde(default)]
+    pub attachment: Option<Attachment>,
+    #[serde(default)]
+    pub parse_mode: ParseMode,
+    #[serde(default)]
+    pub reply_to: Option<i32>,
+    #[serde(default)]
+    pub silent: bool,
+    /// Unix timestamp, the messenger holds the message back until then.
+    #[serde(default)]
+    pub schedule_date: Option<i64>
+}
+
+/// Body of `POST /schedule`: the usual send request and when it should go out.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScheduleRequest {
+    pub send_at: i64,
+    #[serde(default)]
+    pub mode: ScheduleMode,
+    pub request: SendMessageRequest
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RescheduleRequest {
+    pub send_at: i64
+}
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParseMode {
+    #[default]
+    Text,
+    Markdown,
+    Html
+}
+
+/// A file given either by `url`, for the messenger to download itself, or inline as a base64
+/// `data` body.
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MediaSource {
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub data: Option<String>,
+    #[serde(default)]
+    pub file_name: String,
+    #[serde(default)]
+    pub mime_type: Option<String>
+}
+
+impl MediaSource {
+    pub fn bytes(&self) -> utils::Result<Option<Vec<u8>>> {
+        let Some(data) = self.data.as_ref() else { return Ok(None) };
+        Ok(Some(base64::engine::general_purpose::STANDARD.decode(data)?))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Attachment {
+    Photo(MediaSource),
+    Document(MediaSource),
+    Voice {
+        #[serde(flatten)]
+        source: MediaSource,
+        /// Length in seconds.
+        #[serde(default)]
+        duration: u64
+    },
+    Location {
+        latitude: f64,
+        longitude: f64
+    }
 }
 
 #[cfg(test)]
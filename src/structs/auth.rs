@@ -1,18 +1,44 @@
 use serde::{Deserialize, Serialize};
+use crate::bot::webhook::WebhookConfig;
 use crate::utils::JsonConfigs;
+use crate::wrapper::limiter::RateLimits;
 use std::collections::HashMap;
 
+/// A user account signs in with `username` (the phone number) and `password`, a bot account
+/// with the `bot_token` issued by BotFather.
 #[derive(Default, PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub struct TelegramAuth {
+    #[serde(default)]
     pub(crate) username: String,
+    #[serde(default)]
     pub(crate) password: String,
-    pub(crate) api_url: String
+    #[serde(default)]
+    pub(crate) bot_token: Option<String>,
+    pub(crate) api_url: String,
+    #[serde(default)]
+    pub(crate) webhook: Option<WebhookConfig>,
+    /// Left out, user accounts get [`RateLimits::user_account`] and bot accounts no limits.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) rate_limits: Option<RateLimits>
+}
+
+impl TelegramAuth {
+    pub fn is_bot(&self) -> bool {
+        self.bot_token.is_some()
+    }
 }
 
 
 #[derive(Default, PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub struct WhatsAppAuth {
-    pub(crate) phone_id: String
+    pub(crate) phone_id: String,
+    pub(crate) account_id: String,
+    pub(crate) token: String,
+    pub(crate) api_url: String,
+    #[serde(default)]
+    pub(crate) webhook: Option<WebhookConfig>,
+    #[serde(default)]
+    pub(crate) rate_limits: RateLimits
 }
 
 
@@ -22,9 +48,25 @@ pub enum AuthData {
     WhatsApp(WhatsAppAuth)
 }
 
+impl AuthData {
+    pub fn rate_limits(&self) -> RateLimits {
+        match self {
+            AuthData::Telegram(auth) => match (&auth.rate_limits, auth.is_bot()) {
+                (Some(limits), _) => limits.clone(),
+                (None, true) => RateLimits::default(),
+                (None, false) => RateLimits::user_account()
+            },
+            AuthData::WhatsApp(auth) => auth.rate_limits.clone()
+        }
+    }
+}
+
 
 pub type AuthList = HashMap<String, TelegramAuth>;
+pub type WhatsAppAuthList = HashMap<String, WhatsAppAuth>;
 
 
 impl JsonConfigs for TelegramAuth{}
-impl JsonConfigs for WhatsAppAuth{}
\ No newline at end of file
+impl JsonConfigs for AuthList{}
+impl JsonConfigs for WhatsAppAuth{}
+impl JsonConfigs for WhatsAppAuthList{}
\ No newline at end of file
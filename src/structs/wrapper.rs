@@ -1,15 +1,267 @@
-use crate::structs::api::{AddContactRequest, SendMessageRequest, TelegramMessage};
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use crate::structs::api::{AddContactRequest, AddContactsRequest, DeliveryError, DeliveryReply, DeliveryResult, SendMessageRequest, TelegramMessage};
+use crate::utils::JsonConfigs;
 
 #[derive(PartialEq, Clone)]
 pub enum ChannelData {
     ReceiveMessage(TelegramMessage),
     SendMessage(SendMessageRequest),
     // Handler(UserHandler),
-    AddContact(AddContactRequest)
+    AddContact(AddContactRequest),
+    AddContacts(AddContactsRequest)
 }
 
-#[derive(PartialEq, Clone)]
 pub struct  ChannelTx {
     pub data: ChannelData,
-    pub bot_name: String
-}
\ No newline at end of file
+    pub bot_name: String,
+    pub reply: Option<oneshot::Sender<DeliveryReply>>
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QueuedMessage {
+    pub id: String,
+    pub bot_name: String,
+    pub request: SendMessageRequest,
+    pub attempts: u32,
+    pub next_attempt: i64,
+    pub last_error: Option<DeliveryError>
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct QueueData {
+    pub pending: Vec<QueuedMessage>,
+    pub dead: Vec<QueuedMessage>
+}
+
+impl JsonConfigs for QueueData {}
+
+/// `local` messages wait in the scheduler file and are queued when their time comes,
+/// `telegram` ones are handed to Telegram right away as scheduled messages.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleMode {
+    #[default]
+    Local,
+    Telegram
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScheduledMessage {
+    pub id: String,
+    pub bot_name: String,
+    pub mode: ScheduleMode,
+    /// Unix timestamp of the send.
+    pub send_at: i64,
+    pub request: SendMessageRequest,
+    /// Where Telegram keeps a `telegram` schedule, needed to move or cancel it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote: Option<DeliveryResult>
+}
+
+impl ScheduledMessage {
+    /// The bot that holds a `telegram` schedule, which is a pool member when it was sent to a pool.
+    pub fn account(&self) -> &str {
+        self.remote.as_ref().and_then(|remote| remote.account.as_deref()).unwrap_or(&self.bot_name)
+    }
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct ScheduleData {
+    pub scheduled: Vec<ScheduledMessage>
+}
+
+impl JsonConfigs for ScheduleData {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerKind {
+    User,
+    Group,
+    Channel
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DialogMessage {
+    pub id: i32,
+    pub text: String,
+    /// Unix time the message was sent.
+    pub date: i64,
+    pub outgoing: bool
+}
+
+/// A chat in an account's dialog list, with enough of the peer to write back to it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Dialog {
+    pub chat_id: i64,
+    pub kind: PeerKind,
+    pub name: String,
+    pub username: Option<String>,
+    pub phone: Option<String>,
+    pub access_hash: Option<i64>,
+    pub unread_count: i32,
+    pub last_message: Option<DialogMessage>
+}
+
+/// Dialogs of every bot, keyed by bot name and then by chat id.
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct DialogData {
+    pub bots: BTreeMap<String, BTreeMap<String, Dialog>>
+}
+
+impl JsonConfigs for DialogData {}
+
+/// Where an outbound message is on its way to the recipient. It only moves forward; Telegram
+/// has no delivery receipts, so its messages go from `sent` straight to `read`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageStatus {
+    Sent,
+    Delivered,
+    Read,
+    Failed
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TrackedMessage {
+    pub bot_name: String,
+    /// `<chat_id>:<message_id>` on Telegram, the `wamid` on WhatsApp.
+    pub id: String,
+    pub chat_id: Option<i64>,
+    pub access_hash: Option<i64>,
+    pub message_id: Option<i32>,
+    pub status: MessageStatus,
+    /// Unix times of each step, set once reached.
+    pub sent_at: i64,
+    pub delivered_at: Option<i64>,
+    pub read_at: Option<i64>,
+    pub failed_at: Option<i64>
+}
+
+impl TrackedMessage {
+    pub fn sent(bot_name: &str, id: String, at: i64) -> Self {
+        TrackedMessage {
+            bot_name: bot_name.to_string(),
+            id,
+            chat_id: None,
+            access_hash: None,
+            message_id: None,
+            status: MessageStatus::Sent,
+            sent_at: at,
+            delivered_at: None,
+            read_at: None,
+            failed_at: None
+        }
+    }
+
+    /// Moves the message to `status`, returns false when it is there or past it already.
+    /// A read message was delivered as well.
+    pub fn advance(&mut self, status: MessageStatus, at: i64) -> bool {
+        if status <= self.status {
+            return false;
+        }
+        self.status = status;
+        match status {
+            MessageStatus::Sent => {}
+            MessageStatus::Delivered => self.delivered_at = Some(at),
+            MessageStatus::Read => {
+                self.delivered_at.get_or_insert(at);
+                self.read_at = Some(at);
+            }
+            MessageStatus::Failed => self.failed_at = Some(at)
+        }
+        true
+    }
+}
+
+/// Tracked messages of every bot, keyed by bot name and then by message id.
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct ReceiptData {
+    pub bots: BTreeMap<String, BTreeMap<String, TrackedMessage>>
+}
+
+impl JsonConfigs for ReceiptData {}
+
+/// Whether a recipient can get messages from a bot. Anything but `reachable` stops further
+/// sends to them until the status is cleared.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reachability {
+    #[default]
+    Reachable,
+    /// The user blocked the account.
+    Blocked,
+    /// The account was deleted.
+    Deleted,
+    /// The user's privacy settings don't let the account write to them.
+    Restricted,
+    /// Telegram doesn't know the recipient.
+    Invalid
+}
+
+impl Reachability {
+    /// The status a failed send says the recipient has, `None` for errors that don't depend
+    /// on the recipient.
+    pub fn classify(error_name: &str) -> Option<Self> {
+        match error_name {
+            "USER_IS_BLOCKED" | "YOU_BLOCKED_USER" => Some(Reachability::Blocked),
+            "INPUT_USER_DEACTIVATED" => Some(Reachability::Deleted),
+            "USER_PRIVACY_RESTRICTED" | "PRIVACY_PREMIUM_REQUIRED" => Some(Reachability::Restricted),
+            "PEER_ID_INVALID" | "USER_ID_INVALID" => Some(Reachability::Invalid),
+            _ => None
+        }
+    }
+}
+
+impl std::fmt::Display for Reachability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Reachability::Reachable => "reachable",
+            Reachability::Blocked => "blocked",
+            Reachability::Deleted => "deleted",
+            Reachability::Restricted => "restricted",
+            Reachability::Invalid => "invalid"
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecipientStatus {
+    pub bot_name: String,
+    /// The recipient's messenger id, or the digits of their phone when that isn't known.
+    pub recipient: String,
+    pub messenger_id: Option<String>,
+    pub phone: String,
+    pub reachability: Reachability,
+    /// The error that set the status.
+    pub reason: String,
+    /// Unix time the status was set.
+    pub since: i64
+}
+
+/// Recipients that can't be reached, keyed by bot name and then by recipient.
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct ReachabilityData {
+    pub bots: BTreeMap<String, BTreeMap<String, RecipientStatus>>
+}
+
+impl JsonConfigs for ReachabilityData {}
+
+/// A contact the bot imported itself, kept so it can be deleted again once it served its
+/// purpose.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AddedContact {
+    pub user_id: i64,
+    pub phone: String,
+    pub added_at: i64
+}
+
+/// Contacts imported by every bot, keyed by bot name and then by user id.
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct ContactData {
+    pub bots: BTreeMap<String, BTreeMap<i64, AddedContact>>
+}
+
+impl JsonConfigs for ContactData {}
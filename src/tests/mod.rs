@@ -1,35 +1,982 @@
 mod sense_data;
 
+use std::collections::HashMap;
+use serde_json::json;
 use crate::bot;
 use crate::structs::*;
+use crate::structs::api::{ApiRequest, BotButtons, BotHandler};
+use crate::utils;
 use crate::utils::JsonConfigs;
+use crate::wrapper::persist::Flush;
+use crate::wrapper::queue::MessageQueue;
+use crate::bot::rules::RuleBook;
 use crate::tests::sense_data::{APP_HASH, APP_ID, PASSWORD, USERNAME};
 
+/// A bot context with in-memory stores and no rules.
+fn test_context(bot_name: &str, api_url: &str) -> api::BotContext {
+    api::BotContext {
+        bot_name: bot_name.to_string(),
+        api_url: api_url.to_string(),
+        rules: std::sync::Arc::new(RuleBook::load("")),
+        webhook: None,
+        metrics: Default::default(),
+        dialogs: Default::default(),
+        receipts: Default::default(),
+        reachability: Default::default(),
+        contacts: Default::default()
+    }
+}
+
+/// A bot factory with in-memory sessions and stores and no rules.
+fn test_factory() -> crate::wrapper::storage::BotFactory {
+    crate::wrapper::storage::BotFactory {
+        telegram: Default::default(),
+        sessions: std::sync::Arc::new(bot::telegram::session::MemorySessionStorage::default()),
+        whatsapp: Default::default(),
+        rules: std::sync::Arc::new(RuleBook::load("")),
+        metrics: Default::default(),
+        dialogs: Default::default(),
+        receipts: Default::default(),
+        reachability: Default::default(),
+        contacts: Default::default()
+    }
+}
+
+/// Waits up to a second for the mock backend to get `count` requests, the bots report in the
+/// background. Returns what arrived either way.
+async fn wait_for_requests(server: &wiremock::MockServer, count: usize) -> Vec<wiremock::Request> {
+    let mut received = Vec::new();
+    for _ in 0..50 {
+        received = server.received_requests().await.unwrap_or_default();
+        if received.len() >= count {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    received
+}
+
+fn temp_config<T: JsonConfigs>(name: &str, data: &T) -> String {
+    let file_name = std::env::temp_dir().join(format!("doca_tg_{}", name)).to_string_lossy().to_string();
+    std::fs::write(&file_name, serde_json::to_string(data).unwrap()).unwrap();
+    file_name
+}
 
 #[test]
 fn auth_data_write() {
-    let mut user_data: auth::AuthData = auth::AuthData::default();
-    user_data.username = USERNAME;
-    user_data.password = PASSWORD;
-    user_data.verify_code = "".to_string();
-    user_data.into_file("configs/auth_data.json").unwrap();
+    let user_data = auth::TelegramAuth {
+        username: USERNAME.to_string(),
+        password: PASSWORD.to_string(),
+        ..Default::default()
+    };
+    let file_name = temp_config("auth_data.json", &user_data);
+    assert_eq!(auth::TelegramAuth::from_file(&file_name), user_data);
 }
 
 #[test]
 fn bot_data_write() {
+    let bot_data = bot::telegram::TelegramAuth {
+        app_id: APP_ID,
+        app_hash: APP_HASH.to_string(),
+    };
+    let file_name = temp_config("telegram.json", &bot_data);
+    assert!(bot::telegram::TelegramAuth::from_file(&file_name) == bot_data);
+}
 
-    let mut bot_data: bot::BotAuth = bot::BotAuth::default();
-    bot_data.app_id = APP_ID;
-    bot_data.app_hash = APP_HASH;
-    bot_data.into_file("configs/telegram.json").unwrap()
+#[test]
+fn test_api_request() {
+    let request = api::SendMessageRequest::from_file("configs/api_request.json");
+    assert_eq!(request.buttons.unwrap().len(), 2);
+    assert!(request.handlers.unwrap().contains_key("yes"));
+}
 
+#[test]
+fn inline_buttons_are_left_out_for_user_accounts() {
+    use crate::bot::telegram::Telegram;
+    let request = api::SendMessageRequest::from_file("configs/api_request.json");
+    assert_eq!(Telegram::check_buttons(true, &request).map(<[_]>::len), Some(2));
+    // The text still goes out and the handlers still match the typed titles.
+    assert!(Telegram::check_buttons(false, &request).is_none());
+    let plain = api::SendMessageRequest { buttons: Some(vec![]), ..request };
+    assert!(Telegram::check_buttons(true, &plain).is_none());
+}
 
+#[tokio::test]
+async fn backend_errors_are_api_errors() {
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+    use wiremock::matchers::{method, path};
+
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/backend"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&server)
+        .await;
+    let request = ApiRequest { object: "visits".to_string(), command: "update".to_string(), ..Default::default() };
+    let context = test_context("clinic", &format!("{}/backend", server.uri()));
+    let error = bot::send_handler(request, "42", &context).await.unwrap_err();
+    assert!(matches!(error, utils::Error::Api { code: 500, .. }));
 }
 
 #[test]
-fn test_api_request() {
+fn handler_resolves_button_titles() {
+    let request = ApiRequest {
+        object: "visits".to_string(),
+        command: "update".to_string(),
+        data: json!({ "comment": "Придёт" }),
+        ..Default::default()
+    };
+    let handler: BotHandler = HashMap::from([("yes".to_string(), request.clone())]);
+    let buttons = vec![
+        BotButtons { title: "Да".to_string(), reply: "Yes".to_string() },
+        BotButtons { title: "Нет".to_string(), reply: "No".to_string() },
+    ];
+    let result = bot::build_handler(&handler, &buttons);
+    assert_eq!(result.get("yes"), Some(&request));
+    assert_eq!(result.get("да"), Some(&request));
+    assert!(!result.contains_key("нет"));
+}
+
+#[test]
+fn delivery_error_keeps_rpc_details() {
+    let error = utils::Error::from(grammers_mtsender::InvocationError::Rpc(
+        grammers_mtproto::mtp::RpcError { code: 420, name: "FLOOD_WAIT".to_string(), value: Some(31), caused_by: None }
+    ));
+    let result = api::DeliveryError::from(&error);
+    assert_eq!(result.code, 420);
+    assert_eq!(result.name, "FLOOD_WAIT");
+    assert_eq!(result.value, Some(31));
+}
+
+#[test]
+fn queue_retries_then_buries() {
+    let file_name = std::env::temp_dir().join("doca_tg_queue.json").to_string_lossy().to_string();
+    let _ = std::fs::remove_file(&file_name);
+    let queue = MessageQueue::load(&file_name);
+    let message = queue.push("telegram".to_string(), api::SendMessageRequest::default());
+
+    let flood = api::DeliveryError { code: 420, name: "FLOOD_WAIT".to_string(), value: Some(60), ..Default::default() };
+    assert_eq!(queue.fail(message.clone(), flood).queue_id, Some(message.id.clone()));
+    assert!(queue.due().is_empty());
+
+    let invalid = api::DeliveryError::new(400, "PEER_ID_INVALID", String::new());
+    queue.fail(message.clone(), invalid);
+    let reloaded = MessageQueue::load(&file_name).snapshot();
+    assert!(reloaded.pending.is_empty());
+    assert_eq!(reloaded.dead.len(), 1);
+
+    assert!(queue.replay(&message.id));
+    assert_eq!(queue.due().len(), 1);
+}
+
+#[tokio::test]
+async fn whatsapp_sends_through_graph_api() {
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+    use wiremock::matchers::{header, method, path};
+    use crate::bot::DocaBot;
+
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/100/messages"))
+        .and(header("authorization", "Bearer token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "messages": [{ "id": "wamid.1" }] })))
+        .mount(&server)
+        .await;
+
+    let mut bot = bot::whatsapp::WhatsApp::new(
+        bot::BotAuth::WhatsappAuth(bot::whatsapp::WhatsappAuth { graph_url: server.uri(), ..Default::default() }),
+        test_context("whatsapp", &server.uri())
+    );
+    bot.sign_in("whatsapp".to_string(), auth::AuthData::WhatsApp(auth::WhatsAppAuth {
+        phone_id: "100".to_string(),
+        token: "token".to_string(),
+        ..Default::default()
+    })).await.unwrap();
+    let request = api::SendMessageRequest {
+        user: api::UserData { phone: "+7 900 000-00-00".to_string(), messenger_id: None },
+        message: "Hello".to_string(),
+        ..Default::default()
+    };
+    let result = bot.send_message(request).await.unwrap();
+    assert_eq!(result.remote_id, Some("wamid.1".to_string()));
+    assert_eq!(result.chat_id, Some(79000000000));
+}
+
+#[tokio::test]
+async fn whatsapp_rate_limits_are_flood_waits() {
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+    use wiremock::matchers::method;
+    use crate::bot::DocaBot;
+
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(429)
+            .insert_header("Retry-After", "17")
+            .set_body_json(json!({ "error": { "code": 130429, "message": "Rate limit hit" } })))
+        .mount(&server)
+        .await;
+
+    let mut bot = bot::whatsapp::WhatsApp::new(
+        bot::BotAuth::WhatsappAuth(bot::whatsapp::WhatsappAuth { graph_url: server.uri(), ..Default::default() }),
+        test_context("whatsapp", &server.uri())
+    );
+    bot.sign_in("whatsapp".to_string(), auth::AuthData::WhatsApp(auth::WhatsAppAuth {
+        phone_id: "100".to_string(),
+        token: "token".to_string(),
+        ..Default::default()
+    })).await.unwrap();
+    let request = api::SendMessageRequest {
+        user: api::UserData { phone: "+79000000000".to_string(), messenger_id: None },
+        message: "Hello".to_string(),
+        ..Default::default()
+    };
+    let error = api::DeliveryError::from(bot.send_message(request).await.unwrap_err());
+    assert_eq!((error.name.as_str(), error.value), ("FLOOD_WAIT", Some(17)));
+}
+
+#[test]
+fn whatsapp_webhook_needs_the_app_signature() {
+    use crate::bot::whatsapp::verify_signature;
+
+    let body = br#"{"entry":[]}"#;
+    let signature = bot::webhook::sign("app-secret", body);
+    assert!(verify_signature("app-secret", body, Some(&signature)));
+    assert!(!verify_signature("app-secret", br#"{"entry":[{}]}"#, Some(&signature)));
+    assert!(!verify_signature("other-secret", body, Some(&signature)));
+    assert!(!verify_signature("app-secret", body, None));
+    // Without a configured secret nothing is trusted.
+    assert!(!verify_signature("", body, Some(&bot::webhook::sign("", body))));
+}
+
+#[test]
+fn whatsapp_webhook_reads_button_replies() {
+    let payload = json!({
+        "entry": [{ "changes": [{ "value": {
+            "metadata": { "phone_number_id": "100" },
+            "messages": [
+                { "from": "79000000000", "id": "wamid.2", "type": "interactive",
+                  "interactive": { "type": "button_reply", "button_reply": { "id": "Yes", "title": "Да" } } },
+                { "from": "79000000000", "id": "wamid.3", "type": "location" },
+                { "from": "79000000000", "id": "wamid.4", "type": "document",
+                  "document": { "id": "555", "mime_type": "application/pdf", "filename": "scan.pdf", "caption": "Results" } }
+            ]
+        } }] }]
+    });
+    let messages = bot::whatsapp::parse_webhook(&payload);
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0].phone_id, "100");
+    assert_eq!(messages[0].text, "Yes");
+    assert_eq!(messages[1].text, "Results");
+    assert_eq!(messages[1].meta.media, Some(json!({
+        "type": "document", "id": "555", "mime_type": "application/pdf", "name": "scan.pdf"
+    })));
+}
+
+#[test]
+fn reply_rules_match_and_substitute() {
+    let rules = RuleBook::load("configs/reply_rules.json");
+    assert!(rules.find("telegram", "1.").is_some());
+    assert!(rules.find("telegram", " '1' ").is_some());
+    assert!(rules.find("telegram", "12").is_none());
+
+    let message = api::TelegramMessage {
+        id: 7,
+        ctx: grammers_session::PackedChat { ty: grammers_session::PackedType::User, id: 42, access_hash: None },
+        user: "42".to_string(),
+        text: "1".to_string(),
+        meta: api::MessageMeta::default()
+    };
+    let request = rules.find("telegram", "1").unwrap().render_request(&message).unwrap();
+    assert_eq!(request.command, "bot_verify");
+    assert_eq!(request.data["context"]["user_id"], json!("42"));
+
+    let keywords = bot::rules::ReplyRule {
+        matcher: bot::rules::RuleMatch::Keywords(vec!["отмен".to_string()]),
+        request: None,
+        reply: None
+    };
+    assert!(keywords.matches("Прошу ОТМЕНИТЬ запись"));
+}
+
+#[test]
+fn login_errors_are_reported_by_name() {
+    let status = api::LoginStatus::PasswordRequired { hint: Some("cat".to_string()) };
+    assert_eq!(serde_json::to_value(status).unwrap(), json!({ "state": "password_required", "hint": "cat" }));
+
+    let error = utils::Error::from(grammers_client::SignInError::InvalidCode);
+    assert_eq!(api::DeliveryError::from(&error).name, "PHONE_CODE_INVALID");
+    assert_eq!(error.code(), 400);
+}
+
+#[tokio::test]
+async fn bots_keep_their_last_error() {
+    use crate::bot::DocaBot;
+
+    let bot = bot::whatsapp::WhatsApp::new(bot::BotAuth::WhatsappAuth(Default::default()), test_context("whatsapp", ""));
+    assert_eq!(bot.status().await.last_error, None);
+
+    let error = utils::Error::from(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "read-only"));
+    assert_eq!((error.code(), error.name()), (500, "STORAGE"));
+    bot.clone().report_error(&error);
+
+    let status = bot.status().await;
+    let last_error = status.last_error.unwrap();
+    assert_eq!((last_error.code, last_error.name.as_str()), (500, "STORAGE"));
+    assert!(status.last_error_at.is_some());
+    assert!(api::DeliveryError::from(&utils::Error::Transport("reset".to_string())).is_retryable());
+}
+
+#[test]
+fn auth_list_accepts_bot_tokens() {
+    let list: auth::AuthList = serde_json::from_value(json!({
+        "clinic": { "username": "+70000000000", "password": "", "api_url": "http://localhost" },
+        "clinic_bot": { "bot_token": "123:abc", "api_url": "http://localhost" }
+    })).unwrap();
+    assert!(!list["clinic"].is_bot());
+    assert!(list["clinic_bot"].is_bot());
+}
+
+#[tokio::test]
+async fn bot_storage_follows_added_and_removed_bots() {
+    use crate::wrapper::storage::BotStorage;
+
+    let (tx, _rx) = tokio::sync::mpsc::channel(16);
+    let storage = BotStorage::new(test_factory(), tx);
+    let auth = auth::AuthData::WhatsApp(auth::WhatsAppAuth { phone_id: "100".to_string(), ..Default::default() });
+    storage.add("clinic", auth).await.unwrap();
+
+    assert_eq!(storage.names(), vec!["clinic".to_string()]);
+    assert_eq!(storage.find_whatsapp("100"), Some("clinic".to_string()));
+    assert_eq!(storage.get("clinic").unwrap().status().await.messenger, "whatsapp");
+
+    storage.restart("clinic").await.unwrap();
+    assert!(storage.get("clinic").is_some());
+    assert!(storage.remove("clinic").await.unwrap());
+    assert!(!storage.remove("clinic").await.unwrap());
+    assert!(storage.get("clinic").is_none());
+}
+
+#[tokio::test]
+async fn bot_storage_keeps_accounts_whose_bot_is_down() {
+    use crate::wrapper::storage::BotStorage;
+
+    let auth_file = temp_config("storage_auth.json", &auth::AuthList::new());
+    let whatsapp_file = temp_config("storage_whatsapp.json", &auth::WhatsAppAuthList::new());
+    let (tx, _rx) = tokio::sync::mpsc::channel(16);
+    let storage = BotStorage::new(test_factory(), tx).with_files(&auth_file, &whatsapp_file);
+    let whatsapp = |phone_id: &str| auth::AuthData::WhatsApp(auth::WhatsAppAuth { phone_id: phone_id.to_string(), token: "token".to_string(), ..Default::default() });
+    // A WhatsApp account without a phone id can't be started.
+    assert!(storage.start("broken", whatsapp("")).await.is_err());
+    assert!(storage.get("broken").is_none());
+    let failure = storage.failure("broken").unwrap();
+    assert_eq!((failure.messenger.as_str(), failure.connected), ("whatsapp", false));
+    assert_eq!(failure.last_error.unwrap().name, "INVALID_AUTH");
+    assert!(storage.restart("broken").await.is_err());
+    storage.add("clinic", whatsapp("100")).await.unwrap();
+    assert!(storage.failure("clinic").is_none());
+    storage.add("shop", whatsapp("200")).await.unwrap();
+    assert!(storage.add("clinic", whatsapp("")).await.is_err());
+    // The failed replacement left the running bot alone.
+    assert_eq!(storage.find_whatsapp("100"), Some("clinic".to_string()));
+    assert!(storage.get("clinic").is_some());
+    assert!(storage.remove("shop").await.unwrap());
+
+    let mut saved: Vec<String> = auth::WhatsAppAuthList::from_file(&whatsapp_file).into_keys().collect();
+    saved.sort();
+    assert_eq!(saved, vec!["broken".to_string(), "clinic".to_string()]);
+    assert_eq!(storage.names(), vec!["broken".to_string(), "clinic".to_string()]);
+}
+
+#[tokio::test]
+async fn inbound_webhook_is_signed_and_retried() {
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+    use wiremock::matchers::{header_exists, method, path};
+    use crate::bot::webhook::{self, InboundMessage, WebhookConfig, SIGNATURE_HEADER};
+
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/inbox"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/inbox"))
+        .and(header_exists(SIGNATURE_HEADER))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+
+    let message = api::TelegramMessage {
+        id: 7,
+        ctx: grammers_session::PackedChat { ty: grammers_session::PackedType::User, id: 42, access_hash: None },
+        user: "42".to_string(),
+        text: "Hi".to_string(),
+        meta: api::MessageMeta { username: Some("patient".to_string()), reply_to: Some(5), ..Default::default() }
+    };
+    let handler = BotHandler::from([("yes".to_string(), ApiRequest::default())]);
+    let config = WebhookConfig { url: format!("{}/inbox", server.uri()), secret: "secret".to_string() };
+    assert!(webhook::deliver(&config, &InboundMessage::new("clinic", &message, Some(handler))).await);
+
+    let requests = server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 2);
+    let delivered = &requests[1];
+    assert_eq!(delivered.headers[SIGNATURE_HEADER], webhook::sign("secret", &delivered.body).as_str());
+    let body: serde_json::Value = serde_json::from_slice(&delivered.body).unwrap();
+    assert_eq!(body["sender_id"], json!("42"));
+    assert_eq!(body["username"], json!("patient"));
+    assert_eq!(body["reply_to"], json!(5));
+    assert!(body["handler"]["yes"].is_object());
+}
+
+#[tokio::test]
+async fn whatsapp_uploads_attachments_before_sending() {
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+    use wiremock::matchers::{body_partial_json, method, path};
+    use crate::bot::DocaBot;
+
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/100/media"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": "media.1" })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/100/messages"))
+        .and(body_partial_json(json!({
+            "type": "document",
+            "document": { "id": "media.1", "caption": "Your appointment", "filename": "visit.pdf" }
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "messages": [{ "id": "wamid.4" }] })))
+        .mount(&server)
+        .await;
+
+    let mut bot = bot::whatsapp::WhatsApp::new(
+        bot::BotAuth::WhatsappAuth(bot::whatsapp::WhatsappAuth { graph_url: server.uri(), ..Default::default() }),
+        test_context("whatsapp", &server.uri())
+    );
+    bot.sign_in("whatsapp".to_string(), auth::AuthData::WhatsApp(auth::WhatsAppAuth {
+        phone_id: "100".to_string(),
+        token: "token".to_string(),
+        ..Default::default()
+    })).await.unwrap();
+    let request: api::SendMessageRequest = serde_json::from_value(json!({
+        "messenger": "whatsapp",
+        "user": { "phone": "79000000000", "messenger_id": null },
+        "message": "Your appointment",
+        "access_hash": null,
+        "buttons": null,
+        "handlers": null,
+        "attachment": { "type": "document", "data": "JVBERi0xLjQ=", "file_name": "visit.pdf" }
+    })).unwrap();
+    let result = bot.send_message(request).await.unwrap();
+    assert_eq!(result.remote_id, Some("wamid.4".to_string()));
+
+    let pin = api::SendMessageRequest {
+        message: "Clinic".to_string(),
+        attachment: Some(api::Attachment::Location { latitude: 55.75, longitude: 37.62 }),
+        ..Default::default()
+    };
+    let payload = bot::whatsapp::WhatsApp::build_payload("79000000000", &pin, None);
+    assert_eq!(payload["type"], json!("location"));
+    assert_eq!(payload["location"]["latitude"], json!(55.75));
+}
 
-    // let request = BotRequest::from_file("configs/api_request.json");
-    // println!("{:?}", request);
+#[test]
+fn peer_cache_finds_recipients_by_any_key() {
+    use grammers_session::{PackedChat, PackedType};
+    use crate::bot::telegram::peers::PeerCache;
+
+    let peers = PeerCache::default();
+    let chat = PackedChat { ty: PackedType::User, id: 42, access_hash: Some(7) };
+    peers.insert(chat, Some("@Patient"), Some("+7 (900) 000-00-00"));
+    assert_eq!(peers.by_id(42), Some(chat));
+    assert_eq!(peers.by_username("patient"), Some(chat));
+    assert_eq!(peers.by_phone("79000000000"), Some(chat));
+    assert_eq!(peers.by_phone("42"), None);
+
+    peers.insert(PackedChat { access_hash: None, ..chat }, None, Some("79000000001"));
+    assert_eq!(peers.by_phone("79000000001"), Some(chat));
+}
+
+#[test]
+fn session_keeps_peers_and_reads_older_files() {
+    use grammers_session::{PackedChat, PackedType, Session};
+
+    let session = Session::new();
+    let chat = PackedChat { ty: PackedType::User, id: 42, access_hash: Some(7) };
+    session.insert_peer(chat, Some("@Patient"), Some("+7 900 000-00-00"));
+    session.insert_peer(PackedChat { access_hash: None, ..chat }, None, None);
+
+    let restored = Session::load(&session.save()).unwrap();
+    assert_eq!(restored.get_peer(42), Some(chat));
+    assert_eq!(restored.get_peer_by_username("patient"), Some(chat));
+    assert_eq!(restored.get_peer_by_phone("79000000000"), Some(chat));
+    assert_eq!(restored.get_peers().len(), 1);
+
+    // A username passed on to someone else finds the new owner only.
+    let other = PackedChat { id: 43, access_hash: Some(8), ..chat };
+    restored.insert_peer(other, Some("patient"), None);
+    restored.insert_peer(chat, Some("former_patient"), None);
+    assert_eq!(restored.get_peer_by_username("@PATIENT"), Some(other));
+    assert_eq!(restored.get_peer_by_username("former_patient"), Some(chat));
+    assert_eq!(restored.get_peer_by_phone(""), None);
+
+    // An empty session written before the peer table existed.
+    let legacy = [0xce, 0xb8, 0x3e, 0xa7, 0, 0, 0, 0, 0x15, 0xc4, 0xb5, 0x1c, 0, 0, 0, 0];
+    let legacy = Session::load(&legacy).unwrap();
+    assert!(!legacy.signed_in());
+    assert!(legacy.get_peers().is_empty());
+}
+
+#[test]
+fn session_storage_locks_accounts_per_instance() {
+    use std::sync::Arc;
+    use grammers_session::{PackedChat, PackedType};
+    use crate::bot::telegram::session::{instance_id, MemorySessionStorage, SessionStorage, SqliteSessionStorage, StoredSession};
+
+    let database = std::env::temp_dir().join(format!("doca_tg_sessions_{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&database);
+    let sqlite: Arc<dyn SessionStorage> = Arc::new(SqliteSessionStorage::open(&database.to_string_lossy()).unwrap());
+    let memory: Arc<dyn SessionStorage> = Arc::new(MemorySessionStorage::default());
+    for storage in [sqlite, memory] {
+        let (stored, session) = StoredSession::open(storage.clone(), "clinic").unwrap();
+        assert!(storage.load("clinic").unwrap().is_none());
+        session.insert_peer(PackedChat { ty: PackedType::User, id: 42, access_hash: Some(7) }, None, None);
+        stored.save(&session).unwrap();
+        let (_, restored) = StoredSession::open(storage.clone(), "clinic").unwrap();
+        assert_eq!(restored.get_peer(42).map(|chat| chat.access_hash), Some(Some(7)));
+
+        assert!(storage.lock("clinic", "another-instance").is_err());
+        storage.unlock("clinic", instance_id());
+        assert!(storage.lock("clinic", "another-instance").is_ok());
+        assert!(StoredSession::open(storage.clone(), "clinic").is_err());
+    }
+    let _ = std::fs::remove_file(&database);
+}
+
+#[test]
+fn metrics_are_rendered_for_prometheus() {
+    use std::sync::atomic::Ordering;
+    use crate::wrapper::metrics::Metrics;
+
+    let metrics = Metrics::default();
+    let clinic = metrics.bot("clinic");
+    clinic.sent.fetch_add(2, Ordering::Relaxed);
+    metrics.bot("clinic").flood_waits.fetch_add(1, Ordering::Relaxed);
+    clinic.observe_backend(std::time::Duration::from_millis(300));
+
+    let text = metrics.render(3);
+    assert!(text.contains("# TYPE doca_messages_sent_total counter\ndoca_messages_sent_total{bot=\"clinic\"} 2\n"));
+    assert!(text.contains("doca_flood_waits_total{bot=\"clinic\"} 1\n"));
+    assert!(text.contains("doca_backend_request_duration_seconds_bucket{bot=\"clinic\",le=\"0.25\"} 0\n"));
+    assert!(text.contains("doca_backend_request_duration_seconds_bucket{bot=\"clinic\",le=\"0.5\"} 1\n"));
+    assert!(text.contains("doca_backend_request_duration_seconds_count{bot=\"clinic\"} 1\n"));
+    assert!(text.contains("doca_channel_depth 3\n"));
+}
+
+#[actix_web::test]
+async fn api_keys_are_checked_and_scoped() {
+    use actix_web::{test, web, App, HttpResponse};
+    use crate::api::auth::{ApiAuth, ApiKey, Scope, ServerConfig};
+
+    let config = ServerConfig {
+        api_keys: vec![
+            ApiKey { key: "admin".to_string(), bots: vec![] },
+            ApiKey { key: "clinic-key".to_string(), bots: vec!["clinic".to_string()] }
+        ],
+        ..Default::default()
+    };
+    let app = test::init_service(App::new()
+        .wrap(ApiAuth::new(std::sync::Arc::new(config)))
+        .route("/healthz", web::get().to(HttpResponse::Ok))
+        .route("/bots/{name}/login", web::get().to(|name: web::Path<String>, scope: Scope| async move {
+            scope.check(&name).err().unwrap_or_else(|| HttpResponse::Ok().finish())
+        }))
+    ).await;
+    let call = |uri: &str, key: Option<(&str, &str)>| {
+        let mut request = test::TestRequest::get().uri(uri);
+        if let Some(header) = key {
+            request = request.insert_header(header);
+        }
+        request.to_request()
+    };
+
+    assert_eq!(test::call_service(&app, call("/healthz", None)).await.status(), 200);
+    assert_eq!(test::call_service(&app, call("/bots/clinic/login", None)).await.status(), 401);
+    assert_eq!(test::call_service(&app, call("/bots/clinic/login", Some(("X-Api-Key", "wrong")))).await.status(), 401);
+    assert_eq!(test::call_service(&app, call("/bots/clinic/login", Some(("X-Api-Key", "clinic-key")))).await.status(), 200);
+    assert_eq!(test::call_service(&app, call("/bots/other/login", Some(("Authorization", "Bearer clinic-key")))).await.status(), 403);
+    assert_eq!(test::call_service(&app, call("/bots/other/login", Some(("Authorization", "Bearer admin")))).await.status(), 200);
+}
+
+#[test]
+fn scheduler_keeps_messages_until_they_are_due() {
+    use crate::structs::wrapper::ScheduleMode;
+    use crate::wrapper::scheduler::Scheduler;
+
+    let file_name = std::env::temp_dir().join("doca_tg_schedule.json").to_string_lossy().to_string();
+    let _ = std::fs::remove_file(&file_name);
+    let now = chrono::Utc::now().timestamp();
+    let scheduler = Scheduler::load(&file_name);
+    let reminder = scheduler.add("clinic".to_string(), ScheduleMode::Local, now + 3600, api::SendMessageRequest::default(), None);
+    let sent = scheduler.add("clinic".to_string(), ScheduleMode::Telegram, now - 1, api::SendMessageRequest::default(), Some(api::DeliveryResult {
+        message_id: Some(7),
+        chat_id: Some(1),
+        ..Default::default()
+    }));
+    assert!(scheduler.due().is_empty());
+
+    // Telegram already sent its message, only the local reminder is left.
+    let restored = Scheduler::load(&file_name);
+    assert_eq!(restored.list(), vec![reminder.clone()]);
+    assert!(restored.get(&sent.id).is_none());
+
+    let moved = restored.reschedule(&reminder.id, now - 1).unwrap();
+    assert_eq!(restored.due(), vec![moved]);
+    assert!(restored.remove(&reminder.id).is_some());
+    assert!(Scheduler::load(&file_name).list().is_empty());
+}
+
+#[test]
+fn rate_limiter_paces_each_bot() {
+    use std::time::Duration;
+    use crate::wrapper::limiter::{Action, RateLimiter, RateLimits};
+
+    let limits = RateLimits { per_second: 0.0, per_minute: 2, contacts_per_day: 1, jitter_ms: 0, peer_flood_pause: 600 };
+    let limiter = RateLimiter::default();
+    assert!(limiter.acquire("clinic", &limits, Action::ImportContact).is_ok());
+    assert!(limiter.acquire("clinic", &limits, Action::ImportContact).unwrap_err() > Duration::from_secs(3600));
+    assert!(limiter.acquire("clinic", &limits, Action::Send).is_ok());
+    let wait = limiter.acquire("clinic", &limits, Action::Send).unwrap_err();
+    assert!(wait > Duration::from_secs(20) && wait <= Duration::from_secs(30));
+    // Other accounts have buckets of their own.
+    assert!(limiter.acquire("shop", &limits, Action::Send).is_ok());
+
+    let mut flood = api::DeliveryError::new(420, "FLOOD_WAIT", String::new());
+    flood.value = Some(90);
+    limiter.observe("shop", &limits, &flood);
+    assert!(limiter.acquire("shop", &limits, Action::Send).unwrap_err() > Duration::from_secs(80));
+    limiter.observe("shop", &limits, &api::DeliveryError::new(400, "PEER_FLOOD", String::new()));
+    assert!(limiter.acquire("shop", &limits, Action::Send).unwrap_err() > Duration::from_secs(500));
+}
+
+#[test]
+fn only_user_accounts_are_limited_by_default() {
+    use crate::wrapper::limiter::{Action, RateLimiter, RateLimits};
 
-}
\ No newline at end of file
+    let user: auth::TelegramAuth = serde_json::from_value(json!({ "username": "+79000000000", "api_url": "" })).unwrap();
+    let bot = auth::TelegramAuth { bot_token: Some("token".to_string()), ..user.clone() };
+    let whatsapp = auth::WhatsAppAuth::default();
+    assert_eq!(auth::AuthData::Telegram(user.clone()).rate_limits(), RateLimits::user_account());
+    assert_eq!(auth::AuthData::Telegram(bot).rate_limits(), RateLimits::default());
+    assert_eq!(auth::AuthData::WhatsApp(whatsapp).rate_limits(), RateLimits::default());
+    let custom = RateLimits { per_minute: 5, ..Default::default() };
+    let user = auth::TelegramAuth { rate_limits: Some(custom.clone()), ..user };
+    assert_eq!(auth::AuthData::Telegram(user).rate_limits(), custom);
+
+    let limiter = RateLimiter::default();
+    for _ in 0..100 {
+        assert!(limiter.acquire("clinic", &RateLimits::default(), Action::Send).is_ok());
+    }
+}
+
+#[test]
+fn pools_fail_over_on_account_errors() {
+    use std::time::Duration;
+    use crate::wrapper::limiter::RateLimits;
+    use crate::wrapper::pools::{failover_pause, PoolList, Pools};
+
+    let list = PoolList::from([("outreach".to_string(), vec!["clinic".to_string(), "shop".to_string()])]);
+    let pools = Pools::load(&temp_config("pools.json", &list));
+    assert_eq!(pools.members("outreach"), Some(&["clinic".to_string(), "shop".to_string()][..]));
+    assert!(pools.members("clinic").is_none());
+
+    let limits = RateLimits::default();
+    let ban = api::DeliveryError::new(400, "USER_DEACTIVATED_BAN", String::new());
+    assert_eq!(failover_pause(&ban, &limits), Some(Duration::from_secs(86400)));
+    let peer_flood = api::DeliveryError::new(400, "PEER_FLOOD", String::new());
+    assert_eq!(failover_pause(&peer_flood, &limits), Some(Duration::from_secs(limits.peer_flood_pause)));
+    let logged_out = api::DeliveryError::new(401, "AUTH_KEY_UNREGISTERED", String::new());
+    assert!(failover_pause(&logged_out, &limits).is_some());
+    // Another account would fail on a bad recipient just the same.
+    let bad_peer = api::DeliveryError::new(400, "PEER_NOT_RESOLVED", String::new());
+    assert!(failover_pause(&bad_peer, &limits).is_none());
+
+    pools.bench("clinic", Duration::from_secs(60));
+    pools.bench("shop", Duration::ZERO);
+    assert!(pools.is_benched("clinic"));
+    assert!(!pools.is_benched("shop"));
+
+    // A telegram schedule sent through a pool is moved through the account that holds it.
+    let mut scheduled = wrapper::ScheduledMessage {
+        id: "1".to_string(),
+        bot_name: "outreach".to_string(),
+        mode: wrapper::ScheduleMode::Telegram,
+        send_at: 0,
+        request: api::SendMessageRequest::default(),
+        remote: None
+    };
+    assert_eq!(scheduled.account(), "outreach");
+    scheduled.remote = Some(api::DeliveryResult { account: Some("shop".to_string()), ..Default::default() });
+    assert_eq!(scheduled.account(), "shop");
+}
+
+#[test]
+fn dialog_store_follows_new_messages() {
+    use crate::wrapper::dialogs::DialogStore;
+    use crate::structs::wrapper::{Dialog, DialogData, DialogMessage, PeerKind};
+
+    let dialog = |chat_id: i64, id: i32, date: i64, unread_count: i32| Dialog {
+        chat_id,
+        kind: PeerKind::User,
+        name: format!("user {}", chat_id),
+        username: None,
+        phone: None,
+        access_hash: Some(chat_id * 10),
+        unread_count,
+        last_message: Some(DialogMessage { id, text: format!("message {}", id), date, outgoing: false })
+    };
+    let file_name = temp_config("dialogs.json", &DialogData::default());
+    let store = DialogStore::load(&file_name);
+    store.replace("clinic", vec![dialog(1, 10, 100, 0), dialog(2, 20, 200, 2)]);
+    assert_eq!(store.list("clinic").iter().map(|dialog| dialog.chat_id).collect::<Vec<_>>(), vec![2, 1]);
+
+    // A reply moves the chat up and counts as unread, a message older than the sync doesn't.
+    store.record("clinic", dialog(1, 11, 300, 1));
+    store.record("clinic", dialog(2, 19, 150, 1));
+    store.flush();
+    let restored = DialogStore::load(&file_name);
+    let dialogs = restored.list("clinic");
+    assert_eq!(dialogs[0].chat_id, 1);
+    assert_eq!(dialogs[0].unread_count, 1);
+    assert_eq!(dialogs[0].last_message.as_ref().unwrap().text, "message 11");
+    assert_eq!(dialogs[1].unread_count, 2);
+    assert!(restored.contains("clinic", 2));
+    assert!(!restored.contains("shop", 2));
+
+    // Reading the chat on another device sets the count from Telegram's.
+    restored.mark_read("clinic", 2, 0);
+    restored.mark_read("shop", 2, 5);
+    assert_eq!(restored.list("clinic")[1].unread_count, 0);
+    assert!(restored.list("shop").is_empty());
+}
+
+#[tokio::test]
+async fn read_receipts_are_stored_and_reported() {
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+    use wiremock::matchers::{method, path};
+    use crate::bot::DocaBot;
+    use crate::structs::wrapper::{MessageStatus, ReceiptData, TrackedMessage};
+    use crate::wrapper::receipts::ReceiptStore;
+
+    let tracked = |id: i32| {
+        let mut message = TrackedMessage::sent("clinic", format!("42:{}", id), chrono::Utc::now().timestamp());
+        message.chat_id = Some(42);
+        message.message_id = Some(id);
+        message
+    };
+    let file_name = temp_config("receipts.json", &ReceiptData::default());
+    let store = ReceiptStore::load(&file_name);
+    store.track(tracked(1));
+    store.track(tracked(2));
+    store.track(tracked(3));
+    assert_eq!(store.unread_chats("clinic"), vec![(42, None)]);
+    // One message that knows the access hash is enough for the chat.
+    store.track(TrackedMessage { access_hash: Some(7), ..tracked(4) });
+    assert_eq!(store.unread_chats("clinic"), vec![(42, Some(7))]);
+    let read = store.read_up_to("clinic", 42, 2, 1000);
+    assert_eq!(read.iter().map(|message| message.id.as_str()).collect::<Vec<_>>(), vec!["42:1", "42:2"]);
+    assert_eq!(read[0].delivered_at, Some(1000));
+    assert!(store.read_up_to("clinic", 42, 2, 1001).is_empty());
+    // Receipts only move forward and survive a restart.
+    store.flush();
+    let restored = ReceiptStore::load(&file_name);
+    assert!(restored.advance("clinic", "42:1", MessageStatus::Delivered, 1002).is_none());
+    assert_eq!(restored.get("clinic", "42:3").unwrap().status, MessageStatus::Sent);
+
+    let payload = json!({ "entry": [{ "changes": [{ "value": {
+        "metadata": { "phone_number_id": "100" },
+        "statuses": [
+            { "id": "wamid.1", "status": "delivered", "timestamp": "1700000000", "recipient_id": "79000000000" },
+            { "id": "wamid.1", "status": "deleted", "timestamp": "1700000001" }
+        ]
+    }}]}]});
+    let statuses = bot::whatsapp::parse_statuses(&payload);
+    assert_eq!(statuses, vec![bot::whatsapp::WhatsAppStatus {
+        phone_id: "100".to_string(),
+        id: "wamid.1".to_string(),
+        status: MessageStatus::Delivered,
+        timestamp: 1700000000
+    }]);
+
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/backend"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    let receipts = std::sync::Arc::new(ReceiptStore::default());
+    receipts.track(TrackedMessage::sent("whatsapp", "wamid.1".to_string(), 1000));
+    let bot = bot::whatsapp::WhatsApp::new(
+        bot::BotAuth::WhatsappAuth(bot::whatsapp::WhatsappAuth::default()),
+        api::BotContext { receipts: receipts.clone(), ..test_context("whatsapp", &format!("{}/backend", server.uri())) }
+    );
+    bot.update_status("wamid.1", MessageStatus::Read, 1700000005);
+    assert_eq!(receipts.get("whatsapp", "wamid.1").unwrap().read_at, Some(1700000005));
+    let reported = wait_for_requests(&server, 1).await;
+    let body: serde_json::Value = serde_json::from_slice(&reported[0].body).unwrap();
+    assert_eq!(body["object"], "messages");
+    assert_eq!(body["command"], "status");
+    assert_eq!(body["data"]["status"], "read");
+}
+
+#[actix_rt::test]
+async fn unreachable_recipients_are_held_back() {
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+    use wiremock::matchers::{method, path};
+    use crate::bot::DocaBot;
+    use crate::structs::wrapper::{Reachability, ReachabilityData};
+    use crate::wrapper::reachability::ReachabilityStore;
+
+    assert_eq!(Reachability::classify("USER_IS_BLOCKED"), Some(Reachability::Blocked));
+    assert_eq!(Reachability::classify("INPUT_USER_DEACTIVATED"), Some(Reachability::Deleted));
+    assert_eq!(Reachability::classify("USER_PRIVACY_RESTRICTED"), Some(Reachability::Restricted));
+    assert_eq!(Reachability::classify("PEER_ID_INVALID"), Some(Reachability::Invalid));
+    assert_eq!(Reachability::classify("FLOOD_WAIT"), None);
+
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/backend"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    let file_name = temp_config("reachability.json", &ReachabilityData::default());
+    let reachability = std::sync::Arc::new(ReachabilityStore::load(&file_name));
+    let context = api::BotContext { reachability: reachability.clone(), ..test_context("whatsapp", &format!("{}/backend", server.uri())) };
+    let user = api::UserData { messenger_id: Some("42".to_string()), phone: "+7 900 000-00-00".to_string() };
+    bot::check_reachability(&context, &user, &utils::Error::request(400, "FLOOD_WAIT", "wait"));
+    assert!(reachability.get("whatsapp", &user).is_none());
+    bot::check_reachability(&context, &user, &utils::Error::request(403, "USER_IS_BLOCKED", "blocked"));
+    // The status survives a restart and is found by phone too.
+    reachability.flush();
+    let restored = ReachabilityStore::load(&file_name);
+    let by_phone = api::UserData { phone: "79000000000".to_string(), ..Default::default() };
+    assert_eq!(restored.get("whatsapp", &by_phone), restored.get("whatsapp", &user));
+    let status = restored.get("whatsapp", &by_phone).unwrap();
+    assert_eq!((status.recipient.as_str(), status.reachability), ("42", Reachability::Blocked));
+    assert_eq!(bot::unreachable(&status).name(), "RECIPIENT_UNREACHABLE");
+
+    let bot = bot::whatsapp::WhatsApp::new(bot::BotAuth::WhatsappAuth(bot::whatsapp::WhatsappAuth::default()), context);
+    assert!(bot.clear_unreachable("42"));
+    assert!(!bot.clear_unreachable("42"));
+    assert!(reachability.list("whatsapp").is_empty());
+    let reported = wait_for_requests(&server, 2).await;
+    let mut bodies: Vec<serde_json::Value> = reported.iter().map(|request| serde_json::from_slice(&request.body).unwrap()).collect();
+    bodies.sort_by_key(|body| body["data"]["reachability"].to_string());
+    assert_eq!(bodies.len(), 2);
+    assert_eq!((&bodies[0]["object"], &bodies[0]["command"]), (&json!("clients"), &json!("update")));
+    assert_eq!(bodies[0]["data"]["reachability"], "blocked");
+    assert_eq!(bodies[0]["data"]["phone"], "+7 900 000-00-00");
+    assert_eq!(bodies[1]["data"]["reachability"], "reachable");
+    assert_eq!(bodies[1]["data"]["reason"], "CLEARED");
+}
+
+#[actix_rt::test]
+async fn contact_imports_are_reported_by_api_id() {
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+    use wiremock::matchers::{method, path};
+    use crate::structs::api::{AddContactRequest, AddContactsRequest, ContactImport, ContactStatus};
+
+    let request: AddContactsRequest = serde_json::from_value(json!({
+        "messenger": "clinic",
+        "contacts": [
+            { "api_id": "17", "first_name": "Anna", "last_name": "", "phone": "+7 900 000-00-01" },
+            { "api_id": "crm-18", "first_name": "Boris", "last_name": "", "phone": "79000000002" },
+            { "api_id": "19", "first_name": "Vera", "last_name": "", "phone": "79000000003" }
+        ]
+    })).unwrap();
+    assert!(request.contacts.iter().all(|contact| contact.messenger.is_empty()));
+    let result = |contact: &AddContactRequest, status: ContactStatus, chat_id: Option<i64>| {
+        ContactImport { chat_id, ..ContactImport::new(contact, status) }
+    };
+    let mut limited = ContactImport::new(&request.contacts[2], ContactStatus::RateLimited);
+    limited.retry_after = Some(3600);
+    let contacts = vec![
+        result(&request.contacts[0], ContactStatus::Imported, Some(42)),
+        result(&request.contacts[1], ContactStatus::NotOnTelegram, None),
+        limited
+    ];
+    let reply = json!(api::DeliveryResult { contacts: contacts.clone(), ..Default::default() });
+    assert_eq!(reply["contacts"][1]["status"], "not_on_telegram");
+    assert_eq!(reply["contacts"][2]["retry_after"], 3600);
+    assert!(json!(api::DeliveryResult::default()).get("contacts").is_none());
+
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/backend"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    let context = test_context("clinic", &format!("{}/backend", server.uri()));
+    bot::report_contacts(&context, &contacts);
+    let reported = wait_for_requests(&server, 2).await;
+    // Rate limited contacts taught nothing about the client.
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    let reported = server.received_requests().await.unwrap_or(reported);
+    let bodies: Vec<serde_json::Value> = reported.iter().map(|request| serde_json::from_slice(&request.body).unwrap()).collect();
+    assert_eq!(bodies.len(), 2);
+    assert_eq!((&bodies[0]["object"], &bodies[0]["command"]), (&json!("clients"), &json!("update")));
+    assert_eq!(bodies[0]["data"], json!({ "id": 17, "messenger_id": 42, "phone": "+7 900 000-00-01", "status": "imported" }));
+    assert_eq!(bodies[1]["data"], json!({ "id": "crm-18", "messenger_id": null, "phone": "79000000002", "status": "not_on_telegram" }));
+}
+
+#[test]
+fn imported_contacts_are_purged_by_age() {
+    use crate::structs::api::{ContactInfo, ContactsQuery};
+    use crate::structs::wrapper::{AddedContact, ContactData};
+    use crate::wrapper::contacts::ContactStore;
+
+    let added = |user_id: i64, added_at: i64| AddedContact { user_id, phone: format!("7900000000{}", user_id), added_at };
+    let file_name = temp_config("contacts.json", &ContactData::default());
+    let store = ContactStore::load(&file_name);
+    store.record("clinic", added(1, 1000));
+    store.record("clinic", added(2, 3000));
+    store.record("clinic", added(3, 2000));
+    // A second import keeps the first time.
+    store.record("clinic", added(1, 5000));
+    store.record("shop", added(4, 1000));
+    let expired = |store: &ContactStore| store.added_before("clinic", 2500).iter().map(|contact| contact.user_id).collect::<Vec<_>>();
+    assert_eq!(expired(&store), vec![1, 3]);
+
+    store.flush();
+    let restored = ContactStore::load(&file_name);
+    assert_eq!(restored.get("clinic", 1).unwrap().added_at, 1000);
+    restored.forget("clinic", &[1, 4]);
+    assert_eq!(expired(&restored), vec![3]);
+    assert!(restored.get("shop", 4).is_some());
+    restored.flush();
+    assert!(ContactStore::load(&file_name).get("clinic", 1).is_none());
+
+    let query: ContactsQuery = serde_json::from_value(json!({})).unwrap();
+    assert_eq!((query.offset, query.limit), (0, None));
+    let contact = json!(ContactInfo { user_id: 2, phone: "79000000002".to_string(), mutual: true, ..Default::default() });
+    assert_eq!(contact, json!({ "user_id": 2, "phone": "79000000002", "first_name": "", "last_name": "", "mutual": true }));
+}
+
+#[test]
+fn persisted_data_is_written_on_flush_only() {
+    use crate::wrapper::persist::Persisted;
+    let file_name = temp_config("persisted.json", &wrapper::ContactData::default());
+    let persisted: Persisted<wrapper::ContactData> = Persisted::load(&file_name);
+    persisted.lock().bots.entry("clinic".to_string()).or_default();
+    persisted.changed();
+    assert!(wrapper::ContactData::from_file(&file_name).bots.is_empty());
+    persisted.flush();
+    assert!(wrapper::ContactData::from_file(&file_name).bots.contains_key("clinic"));
+    // Nothing changed since, so the next flush leaves the file alone.
+    std::fs::remove_file(&file_name).unwrap();
+    persisted.flush();
+    assert!(!std::path::Path::new(&file_name).exists());
+}
@@ -0,0 +1,6 @@
+// Account data the config tests write and read back. Placeholders, nothing here reaches
+// Telegram.
+pub const USERNAME: &str = "+70000000000";
+pub const PASSWORD: &str = "password";
+pub const APP_ID: i32 = 12345;
+pub const APP_HASH: &str = "hash";
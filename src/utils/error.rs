@@ -0,0 +1,147 @@
+use std::fmt;
+use grammers_client::SignInError;
+use grammers_mtsender::{AuthorizationError, InvocationError};
+
+/// Everything that can go wrong inside the service. Each variant maps onto a
+/// [`DeliveryError`](crate::structs::api::DeliveryError) when it has to be reported to a caller.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// A config file or an account's settings are missing or invalid.
+    Config(String),
+    /// Signing in failed, `name` tells why (e.g. `PHONE_CODE_INVALID`).
+    Auth { name: String, message: String },
+    /// Telegram rejected a call, with its RPC error name and value (e.g. `FLOOD_WAIT` and its seconds).
+    /// A Graph API rate limit is reported the same way.
+    Rpc { code: i32, name: String, value: Option<u32>, message: String },
+    /// The connection to Telegram, the Graph API or the backend failed.
+    Transport(String),
+    /// The backend or the Graph API answered with an error.
+    Api { code: i32, name: String, message: String },
+    /// The request can't be served as it was asked, such as an unknown recipient.
+    Request { code: i32, name: String, message: String },
+    /// Reading or writing sessions, the queue or other local state failed.
+    Storage(String)
+}
+
+impl Error {
+    pub fn request(code: i32, name: &str, message: impl Into<String>) -> Self {
+        Error::Request { code, name: name.to_string(), message: message.into() }
+    }
+
+    pub fn auth(name: &str, message: impl Into<String>) -> Self {
+        Error::Auth { name: name.to_string(), message: message.into() }
+    }
+
+    pub fn code(&self) -> i32 {
+        match self {
+            Error::Config(_) | Error::Storage(_) => 500,
+            Error::Auth { .. } => 400,
+            Error::Transport(_) => 503,
+            Error::Rpc { code, .. } | Error::Api { code, .. } | Error::Request { code, .. } => *code
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Error::Config(_) => "CONFIG",
+            Error::Transport(_) => "TRANSPORT",
+            Error::Storage(_) => "STORAGE",
+            Error::Auth { name, .. } | Error::Rpc { name, .. } | Error::Api { name, .. } | Error::Request { name, .. } => name
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Error::Config(message) | Error::Transport(message) | Error::Storage(message) => message,
+            Error::Auth { message, .. } | Error::Rpc { message, .. } | Error::Api { message, .. } | Error::Request { message, .. } => message
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}): {}", self.name(), self.code(), self.message())
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<InvocationError> for Error {
+    fn from(error: InvocationError) -> Self {
+        match error {
+            InvocationError::Rpc(ref rpc) => Error::Rpc {
+                code: rpc.code,
+                name: rpc.name.clone(),
+                value: rpc.value,
+                message: error.to_string()
+            },
+            _ => Error::Transport(error.to_string())
+        }
+    }
+}
+
+impl From<AuthorizationError> for Error {
+    fn from(error: AuthorizationError) -> Self {
+        match error {
+            AuthorizationError::Invoke(error) => error.into(),
+            AuthorizationError::Gen(_) => Error::Transport(error.to_string())
+        }
+    }
+}
+
+impl From<SignInError> for Error {
+    fn from(error: SignInError) -> Self {
+        match error {
+            SignInError::InvalidCode => Error::auth("PHONE_CODE_INVALID", error.to_string()),
+            SignInError::InvalidPassword => Error::auth("PASSWORD_HASH_INVALID", error.to_string()),
+            SignInError::SignUpRequired { .. } => Error::auth("SIGN_UP_REQUIRED", error.to_string()),
+            SignInError::PasswordRequired(_) => Error::auth("SESSION_PASSWORD_NEEDED", error.to_string()),
+            SignInError::Other(error) => error.into()
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Self {
+        match error.status() {
+            Some(status) => Error::Api { code: status.as_u16() as i32, name: "API_ERROR".to_string(), message: error.to_string() },
+            None => Error::Transport(error.to_string())
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Storage(error.to_string())
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(error: rusqlite::Error) -> Self {
+        Error::Storage(error.to_string())
+    }
+}
+
+impl From<grammers_session::Error> for Error {
+    fn from(error: grammers_session::Error) -> Self {
+        Error::Storage(format!("session: {}", error))
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::Config(error.to_string())
+    }
+}
+
+impl From<std::num::ParseIntError> for Error {
+    fn from(error: std::num::ParseIntError) -> Self {
+        Error::request(400, "INVALID_ID", error.to_string())
+    }
+}
+
+impl From<base64::DecodeError> for Error {
+    fn from(error: base64::DecodeError) -> Self {
+        Error::request(400, "MEDIA_INVALID", error.to_string())
+    }
+}
@@ -1,9 +1,10 @@
 use std::fs;
-// use std::fs::File;
-// use std::io::Write;
 use serde::{Deserialize, Serialize};
 
-pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+mod error;
+pub use error::Error;
+
+pub type Result<T> = std::result::Result<T, Error>;
 
 
 pub trait JsonConfigs: Default + Serialize + for<'a> Deserialize<'a> {
@@ -12,16 +13,24 @@ pub trait JsonConfigs: Default + Serialize + for<'a> Deserialize<'a> {
             println!("[!] {} not found", filename);
             return Self::default()
         }
-        let file_contents: String = fs::read_to_string(filename).unwrap();
-        serde_json::from_str::<Self>(&file_contents.clone()).unwrap_or_else(|e| {
+        let file_contents = fs::read_to_string(filename).unwrap_or_else(|e| {
+            println!("[!] Can't read {}: {}", filename, e);
+            String::new()
+        });
+        serde_json::from_str::<Self>(&file_contents).unwrap_or_else(|e| {
             println!("[!] Config file is corrupted: {:?}", e);
             Self::default()
         })
     }
-    // fn into_file(&self, filename: &str) -> Result<()> {
-    //     let mut file = File::create(filename).expect("[!] Can't create config file");
-    //     let data = serde_json::to_string(self).unwrap();
-    //     file.write_all(data.as_bytes()).unwrap();
-    //     Ok(())
-    // }
+    fn to_file(&self, filename: &str) -> Result<()> {
+        write_file(filename, &serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// Writes through a temporary file, so a crash never leaves a half-written config behind.
+pub fn write_file(filename: &str, contents: &str) -> Result<()> {
+    let temp_file = format!("{}.tmp", filename);
+    fs::write(&temp_file, contents)?;
+    fs::rename(&temp_file, filename)?;
+    Ok(())
 }
\ No newline at end of file
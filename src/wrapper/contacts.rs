@@ -0,0 +1,55 @@
+use crate::structs::wrapper::{AddedContact, ContactData};
+use crate::wrapper::persist::{Flush, Persisted};
+
+/// File-backed log of the contacts each bot imported, with when. Telegram doesn't say when a
+/// contact was added, this is what lets one-off imports be purged later.
+#[derive(Default)]
+pub struct ContactStore {
+    data: Persisted<ContactData>
+}
+
+impl ContactStore {
+    pub fn load(file_name: &str) -> Self {
+        ContactStore {
+            data: Persisted::load(file_name)
+        }
+    }
+
+    /// Keeps the first import time when the contact is imported again.
+    pub fn record(&self, bot_name: &str, contact: AddedContact) {
+        let mut data = self.data.lock();
+        data.bots.entry(bot_name.to_string()).or_default().entry(contact.user_id).or_insert(contact);
+        self.data.changed();
+    }
+
+    pub fn get(&self, bot_name: &str, user_id: i64) -> Option<AddedContact> {
+        let data = self.data.lock();
+        data.bots.get(bot_name)?.get(&user_id).cloned()
+    }
+
+    /// Contacts imported before `before`, oldest first.
+    pub fn added_before(&self, bot_name: &str, before: i64) -> Vec<AddedContact> {
+        let data = self.data.lock();
+        let mut contacts: Vec<AddedContact> = data.bots.get(bot_name)
+            .map(|list| list.values().filter(|contact| contact.added_at < before).cloned().collect())
+            .unwrap_or_default();
+        contacts.sort_by_key(|contact| contact.added_at);
+        contacts
+    }
+
+    pub fn forget(&self, bot_name: &str, user_ids: &[i64]) {
+        let mut data = self.data.lock();
+        let Some(list) = data.bots.get_mut(bot_name) else { return };
+        let before = list.len();
+        list.retain(|user_id, _| !user_ids.contains(user_id));
+        if list.len() != before {
+            self.data.changed();
+        }
+    }
+}
+
+impl Flush for ContactStore {
+    fn flush(&self) {
+        self.data.flush();
+    }
+}
@@ -0,0 +1,85 @@
+use crate::structs::wrapper::{Dialog, DialogData};
+use crate::wrapper::persist::{Flush, Persisted};
+
+/// File-backed dialog lists of all bots. A full sync replaces a bot's list, new messages keep
+/// it current in between, so the list survives restarts and shows who wrote last.
+#[derive(Default)]
+pub struct DialogStore {
+    data: Persisted<DialogData>
+}
+
+impl DialogStore {
+    pub fn load(file_name: &str) -> Self {
+        DialogStore {
+            data: Persisted::load(file_name)
+        }
+    }
+
+    /// Takes the result of a full sync as the bot's dialog list.
+    pub fn replace(&self, bot_name: &str, dialogs: Vec<Dialog>) {
+        let mut data = self.data.lock();
+        let list = dialogs.into_iter().map(|dialog| (dialog.chat_id.to_string(), dialog)).collect();
+        data.bots.insert(bot_name.to_string(), list);
+        self.data.changed();
+    }
+
+    /// Adds a message seen after the last sync. `dialog.unread_count` is what the message
+    /// adds to the chat's count, 1 for incoming and 0 for outgoing messages.
+    pub fn record(&self, bot_name: &str, dialog: Dialog) {
+        let mut data = self.data.lock();
+        let list = data.bots.entry(bot_name.to_string()).or_default();
+        match list.get_mut(&dialog.chat_id.to_string()) {
+            Some(known) => {
+                let is_newer = match (&known.last_message, &dialog.last_message) {
+                    (Some(known), Some(message)) => message.id > known.id,
+                    (None, _) => true,
+                    (_, None) => false
+                };
+                if !is_newer {
+                    return;
+                }
+                known.unread_count += dialog.unread_count;
+                known.last_message = dialog.last_message;
+                known.name = dialog.name;
+                known.username = dialog.username.or(known.username.take());
+                known.phone = dialog.phone.or(known.phone.take());
+                known.access_hash = dialog.access_hash.or(known.access_hash);
+            }
+            None => {
+                list.insert(dialog.chat_id.to_string(), dialog);
+            }
+        }
+        self.data.changed();
+    }
+
+    /// Takes Telegram's count of what is still unread after the chat was read elsewhere.
+    pub fn mark_read(&self, bot_name: &str, chat_id: i64, unread_count: i32) {
+        let mut data = self.data.lock();
+        let known = data.bots.get_mut(bot_name).and_then(|list| list.get_mut(&chat_id.to_string()));
+        if let Some(known) = known {
+            if known.unread_count != unread_count {
+                known.unread_count = unread_count;
+                self.data.changed();
+            }
+        }
+    }
+
+    /// The bot's dialogs, most recent message first.
+    pub fn list(&self, bot_name: &str) -> Vec<Dialog> {
+        let data = self.data.lock();
+        let mut result: Vec<Dialog> = data.bots.get(bot_name).map(|list| list.values().cloned().collect()).unwrap_or_default();
+        result.sort_by_key(|dialog| std::cmp::Reverse(dialog.last_message.as_ref().map(|message| message.date).unwrap_or_default()));
+        result
+    }
+
+    pub fn contains(&self, bot_name: &str, chat_id: i64) -> bool {
+        let data = self.data.lock();
+        data.bots.get(bot_name).is_some_and(|list| list.contains_key(&chat_id.to_string()))
+    }
+}
+
+impl Flush for DialogStore {
+    fn flush(&self) {
+        self.data.flush();
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use crate::structs::api::DeliveryError;
+
+/// How fast one account may send and import contacts. A zero turns that limit off, which is
+/// the default. User accounts that message strangers in bursts get flood-limited or banned,
+/// so they get [`RateLimits::user_account`] unless they set their own.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimits {
+    pub per_second: f64,
+    pub per_minute: u32,
+    pub contacts_per_day: u32,
+    /// Up to this many milliseconds are added at random between two sends.
+    pub jitter_ms: u64,
+    /// How long the account rests after Telegram answered `PEER_FLOOD`.
+    pub peer_flood_pause: u64
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits { per_second: 0.0, per_minute: 0, contacts_per_day: 0, jitter_ms: 0, peer_flood_pause: 3600 }
+    }
+}
+
+impl RateLimits {
+    /// Cautious pacing for Telegram user accounts.
+    pub fn user_account() -> Self {
+        RateLimits { per_second: 1.0, per_minute: 20, contacts_per_day: 50, jitter_ms: 1500, ..Default::default() }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Send,
+    ImportContact
+}
+
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill: f64,
+    updated: Instant
+}
+
+impl TokenBucket {
+    /// Starts full and lets `rate` actions through every `period`, bursts included.
+    fn new(rate: f64, period: Duration, now: Instant) -> Self {
+        let capacity = rate.max(1.0);
+        TokenBucket { capacity, tokens: capacity, refill: rate.max(0.0) / period.as_secs_f64(), updated: now }
+    }
+
+    /// Time until a token is available, zero when there is one now.
+    fn wait(&mut self, now: Instant) -> Duration {
+        self.tokens = (self.tokens + now.duration_since(self.updated).as_secs_f64() * self.refill).min(self.capacity);
+        self.updated = now;
+        match self.tokens >= 1.0 || self.refill <= 0.0 {
+            true => Duration::ZERO,
+            false => Duration::from_secs_f64((1.0 - self.tokens) / self.refill)
+        }
+    }
+
+    fn take(&mut self) {
+        if self.refill > 0.0 {
+            self.tokens -= 1.0;
+        }
+    }
+}
+
+struct BotLimiter {
+    limits: RateLimits,
+    per_second: TokenBucket,
+    per_minute: TokenBucket,
+    contacts: TokenBucket,
+    next_send: Instant,
+    paused_until: Instant
+}
+
+impl BotLimiter {
+    fn new(limits: &RateLimits, now: Instant) -> Self {
+        BotLimiter {
+            limits: limits.clone(),
+            per_second: TokenBucket::new(limits.per_second, Duration::from_secs(1), now),
+            per_minute: TokenBucket::new(limits.per_minute as f64, Duration::from_secs(60), now),
+            contacts: TokenBucket::new(limits.contacts_per_day as f64, Duration::from_secs(86400), now),
+            next_send: now,
+            paused_until: now
+        }
+    }
+}
+
+/// Paces every bot on its own. The wrapper asks before each send or contact import and gets
+/// back how long to hold it when the bot is over its limits or paused by Telegram.
+#[derive(Default)]
+pub struct RateLimiter {
+    bots: Mutex<HashMap<String, BotLimiter>>
+}
+
+impl RateLimiter {
+    /// Takes a token for the action, or returns how long to wait for one.
+    pub fn acquire(&self, bot_name: &str, limits: &RateLimits, action: Action) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut bots = self.bots.lock().unwrap();
+        let bot = bots.entry(bot_name.to_string()).or_insert_with(|| BotLimiter::new(limits, now));
+        if &bot.limits != limits {
+            // The account was re-added with other limits.
+            *bot = BotLimiter::new(limits, now);
+        }
+        let mut wait = bot.paused_until.saturating_duration_since(now)
+            .max(bot.next_send.saturating_duration_since(now))
+            .max(bot.per_second.wait(now))
+            .max(bot.per_minute.wait(now));
+        if action == Action::ImportContact {
+            wait = wait.max(bot.contacts.wait(now));
+        }
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        bot.per_second.take();
+        bot.per_minute.take();
+        if action == Action::ImportContact {
+            bot.contacts.take();
+        }
+        bot.next_send = now + jitter(limits.jitter_ms);
+        Ok(())
+    }
+
+    /// Pauses the bot on `FLOOD_WAIT` for as long as Telegram asked, and on `PEER_FLOOD`
+    /// for the configured rest.
+    pub fn observe(&self, bot_name: &str, limits: &RateLimits, error: &DeliveryError) {
+        let pause = match (error.name.as_str(), error.value) {
+            ("FLOOD_WAIT", Some(seconds)) => Duration::from_secs(seconds as u64),
+            ("PEER_FLOOD", _) => Duration::from_secs(limits.peer_flood_pause),
+            _ => return
+        };
+        log::warn!("[{}] {}, pausing for {}s", bot_name, error.name, pause.as_secs());
+        let now = Instant::now();
+        let mut bots = self.bots.lock().unwrap();
+        let bot = bots.entry(bot_name.to_string()).or_insert_with(|| BotLimiter::new(limits, now));
+        bot.paused_until = bot.paused_until.max(now + pause);
+    }
+}
+
+/// Cheap randomness is enough to keep sends from looking machine-timed.
+fn jitter(max_ms: u64) -> Duration {
+    if max_ms == 0 {
+        return Duration::ZERO;
+    }
+    let seed = chrono::Utc::now().timestamp_subsec_nanos() as u64;
+    Duration::from_millis((seed.wrapping_mul(6364136223846793005) >> 33) % (max_ms + 1))
+}
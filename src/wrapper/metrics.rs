@@ -0,0 +1,100 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds, in seconds, of the backend latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 8] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// A counter of [`BotMetrics`] with its Prometheus name and help text.
+type Counter = (&'static str, &'static str, fn(&BotMetrics) -> &AtomicU64);
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64
+}
+
+/// Counters of one bot. The bot and the wrapper keep a handle and bump them as things happen.
+#[derive(Default)]
+pub struct BotMetrics {
+    pub sent: AtomicU64,
+    pub received: AtomicU64,
+    pub failed: AtomicU64,
+    pub flood_waits: AtomicU64,
+    /// Times the MTProto sender asked the reconnection policy for another attempt.
+    pub reconnects: AtomicU64,
+    backend_latency: Mutex<Histogram>
+}
+
+impl BotMetrics {
+    pub fn observe_backend(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        let mut histogram = self.backend_latency.lock().unwrap();
+        for (bucket, bound) in histogram.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        histogram.count += 1;
+        histogram.sum += seconds;
+    }
+}
+
+/// Per-bot counters of the whole process, rendered by `/metrics`. Counters outlive restarts of
+/// the bot, as Prometheus expects of a counter.
+#[derive(Default)]
+pub struct Metrics {
+    bots: RwLock<BTreeMap<String, Arc<BotMetrics>>>
+}
+
+impl Metrics {
+    pub fn bot(&self, bot_name: &str) -> Arc<BotMetrics> {
+        if let Some(metrics) = self.bots.read().unwrap().get(bot_name) {
+            return metrics.clone();
+        }
+        self.bots.write().unwrap().entry(bot_name.to_string()).or_default().clone()
+    }
+
+    /// Prometheus text exposition format, version 0.0.4.
+    pub fn render(&self, channel_depth: usize) -> String {
+        let bots = self.bots.read().unwrap();
+        let mut out = String::new();
+        let counters: [Counter; 5] = [
+            ("doca_messages_sent_total", "Messages the messenger accepted.", |m| &m.sent),
+            ("doca_messages_received_total", "Inbound messages handed to the bot.", |m| &m.received),
+            ("doca_messages_failed_total", "Send attempts that failed.", |m| &m.failed),
+            ("doca_flood_waits_total", "Sends rejected with FLOOD_WAIT.", |m| &m.flood_waits),
+            ("doca_reconnects_total", "MTProto reconnection attempts.", |m| &m.reconnects)
+        ];
+        for (name, help, counter) in counters {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+            for (bot_name, metrics) in bots.iter() {
+                let _ = writeln!(out, "{}{{bot=\"{}\"}} {}", name, escape(bot_name), counter(metrics).load(Ordering::Relaxed));
+            }
+        }
+
+        let name = "doca_backend_request_duration_seconds";
+        let _ = writeln!(out, "# HELP {} Latency of requests to the backend API.\n# TYPE {} histogram", name, name);
+        for (bot_name, metrics) in bots.iter() {
+            let bot_name = escape(bot_name);
+            let histogram = metrics.backend_latency.lock().unwrap();
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                let _ = writeln!(out, "{}_bucket{{bot=\"{}\",le=\"{}\"}} {}", name, bot_name, bound, count);
+            }
+            let _ = writeln!(out, "{}_bucket{{bot=\"{}\",le=\"+Inf\"}} {}", name, bot_name, histogram.count);
+            let _ = writeln!(out, "{}_sum{{bot=\"{}\"}} {}", name, bot_name, histogram.sum);
+            let _ = writeln!(out, "{}_count{{bot=\"{}\"}} {}", name, bot_name, histogram.count);
+        }
+
+        let name = "doca_channel_depth";
+        let _ = writeln!(out, "# HELP {} Commands waiting for the wrapper.\n# TYPE {} gauge\n{} {}", name, name, name, channel_depth);
+        out
+    }
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
@@ -1 +1,13 @@
-pub mod wrapper;
\ No newline at end of file
+#[allow(clippy::module_inception)]
+pub mod wrapper;
+pub mod contacts;
+pub mod dialogs;
+pub mod limiter;
+pub mod metrics;
+pub mod persist;
+pub mod pools;
+pub mod queue;
+pub mod reachability;
+pub mod receipts;
+pub mod scheduler;
+pub mod storage;
@@ -0,0 +1,82 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use crate::utils;
+use crate::utils::JsonConfigs;
+
+/// How often changed stores are written out. A crash loses at most this much.
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Something that keeps its data in memory and writes it out on `flush`.
+pub trait Flush: Send + Sync {
+    fn flush(&self);
+}
+
+/// A store's data with the file it lives in. Changes only mark it dirty, `flush` writes the
+/// file at most once however many changes came in since the last one.
+#[derive(Default)]
+pub struct Persisted<T> {
+    file_name: String,
+    data: Mutex<T>,
+    dirty: AtomicBool
+}
+
+impl<T: JsonConfigs + Send> Persisted<T> {
+    pub fn load(file_name: &str) -> Self {
+        Persisted {
+            file_name: file_name.to_string(),
+            data: Mutex::new(T::from_file(file_name)),
+            dirty: AtomicBool::new(false)
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.data.lock().unwrap()
+    }
+
+    /// Writes the data right away, for stores that can't lose a change. `data` is what the
+    /// caller holds the lock for.
+    pub fn save(&self, data: &T) {
+        // The default store only lives in memory.
+        if self.file_name.is_empty() {
+            return;
+        }
+        self.dirty.store(false, Ordering::Relaxed);
+        if let Err(e) = data.to_file(&self.file_name) {
+            log::error!("Can't save {}: {}", self.file_name, e);
+        }
+    }
+
+    /// Marks the data as changed, the next `flush` writes it.
+    pub fn changed(&self) {
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+}
+
+impl<T: JsonConfigs + Send> Flush for Persisted<T> {
+    fn flush(&self) {
+        // The default store only lives in memory.
+        if self.file_name.is_empty() || !self.dirty.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        // Only serializing happens under the lock, the write doesn't hold up the store.
+        let json = serde_json::to_string(&*self.lock());
+        let result = json.map_err(utils::Error::from).and_then(|json| utils::write_file(&self.file_name, &json));
+        if let Err(e) = result {
+            self.changed();
+            log::error!("Can't save {}: {}", self.file_name, e);
+        }
+    }
+}
+
+/// Flushes the stores every `FLUSH_INTERVAL` on a blocking thread, away from the async
+/// workers.
+pub fn flush_periodically(stores: Vec<Arc<dyn Flush>>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(FLUSH_INTERVAL).await;
+            let stores = stores.clone();
+            let _ = tokio::task::spawn_blocking(move || stores.iter().for_each(|store| store.flush())).await;
+        }
+    });
+}
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use crate::structs::api::DeliveryError;
+use crate::utils::JsonConfigs;
+use crate::wrapper::limiter::RateLimits;
+
+/// How long a banned account stays out of its pools. It takes a restart or a new login to
+/// bring it back sooner.
+const BAN_PAUSE: Duration = Duration::from_secs(86400);
+/// How long an account that lost its authorization stays out, enough to sign it in again.
+const AUTH_PAUSE: Duration = Duration::from_secs(600);
+
+/// Pool name to the accounts in it, in order of preference.
+pub type PoolList = HashMap<String, Vec<String>>;
+
+impl JsonConfigs for PoolList {}
+
+/// Named groups of accounts. A send addressed to a pool goes out through the healthiest
+/// member and moves on to the next one when a member is flood-limited, banned or logged out.
+#[derive(Default)]
+pub struct Pools {
+    pools: PoolList,
+    benched: Mutex<HashMap<String, Instant>>
+}
+
+impl Pools {
+    pub fn new(pools: PoolList) -> Self {
+        Pools { pools, benched: Mutex::default() }
+    }
+
+    pub fn load(file_name: &str) -> Self {
+        Pools::new(PoolList::from_file(file_name))
+    }
+
+    /// The pool's accounts, or `None` when the name is not a pool.
+    pub fn members(&self, name: &str) -> Option<&[String]> {
+        self.pools.get(name).map(Vec::as_slice)
+    }
+
+    /// Leaves the account out of pool sends for `pause`.
+    pub fn bench(&self, bot_name: &str, pause: Duration) {
+        let until = Instant::now() + pause;
+        let mut benched = self.benched.lock().unwrap();
+        let entry = benched.entry(bot_name.to_string()).or_insert(until);
+        *entry = (*entry).max(until);
+    }
+
+    pub fn is_benched(&self, bot_name: &str) -> bool {
+        let mut benched = self.benched.lock().unwrap();
+        match benched.get(bot_name) {
+            Some(until) if *until > Instant::now() => true,
+            Some(_) => {
+                benched.remove(bot_name);
+                false
+            }
+            None => false
+        }
+    }
+}
+
+/// How long to keep the account that failed with `error` out of its pools, or `None` when the
+/// error is about the message rather than the account and another member would fail the same way.
+pub fn failover_pause(error: &DeliveryError, limits: &RateLimits) -> Option<Duration> {
+    match error.name.as_str() {
+        "FLOOD_WAIT" => Some(Duration::from_secs(error.value.unwrap_or_default() as u64)),
+        "PEER_FLOOD" => Some(Duration::from_secs(limits.peer_flood_pause)),
+        "USER_DEACTIVATED_BAN" | "USER_DEACTIVATED" => Some(BAN_PAUSE),
+        _ if error.code == 401 => Some(AUTH_PAUSE),
+        _ => None
+    }
+}
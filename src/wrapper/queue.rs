@@ -0,0 +1,123 @@
+use chrono::Utc;
+use crate::structs::api::{DeliveryError, SendMessageRequest};
+use crate::structs::wrapper::{QueueData, QueuedMessage};
+use crate::wrapper::persist::Persisted;
+
+const MAX_ATTEMPTS: u32 = 8;
+const BASE_DELAY: i64 = 5;
+const MAX_DELAY: i64 = 3600;
+
+/// File-backed outbound queue. Every send is written down before the first attempt and removed
+/// only once Telegram accepted it, so nothing is lost on restart.
+pub struct MessageQueue {
+    data: Persisted<QueueData>
+}
+
+impl MessageQueue {
+    pub fn load(file_name: &str) -> Self {
+        MessageQueue {
+            data: Persisted::load(file_name)
+        }
+    }
+
+    pub fn push(&self, bot_name: String, request: SendMessageRequest) -> QueuedMessage {
+        let now = Utc::now();
+        let seed = format!("{}{}{}", bot_name, now.timestamp_nanos_opt().unwrap_or_default(), request.message);
+        let message = QueuedMessage {
+            id: format!("{:x}", md5::compute(seed)),
+            bot_name,
+            request,
+            attempts: 0,
+            next_attempt: now.timestamp(),
+            last_error: None
+        };
+        let mut data = self.data.lock();
+        data.pending.push(message.clone());
+        self.data.save(&data);
+        message
+    }
+
+    /// Messages whose retry time has come, in the order they were queued.
+    pub fn due(&self) -> Vec<QueuedMessage> {
+        let now = Utc::now().timestamp();
+        self.data.lock().pending.iter()
+            .filter(|message| message.next_attempt <= now)
+            .cloned()
+            .collect()
+    }
+
+    pub fn complete(&self, id: &str) {
+        let mut data = self.data.lock();
+        data.pending.retain(|message| message.id != id);
+        self.data.save(&data);
+    }
+
+    /// Schedules the next attempt with exponential backoff (or the flood wait Telegram asked
+    /// for), or moves the message to the dead-letter list once it can't succeed.
+    pub fn fail(&self, mut message: QueuedMessage, mut error: DeliveryError) -> DeliveryError {
+        message.attempts += 1;
+        let mut data = self.data.lock();
+        data.pending.retain(|pending| pending.id != message.id);
+        if error.is_retryable() && message.attempts < MAX_ATTEMPTS {
+            let delay = match (error.name.as_str(), error.value) {
+                ("FLOOD_WAIT", Some(seconds)) => seconds as i64,
+                _ => (BASE_DELAY << message.attempts.min(16)).min(MAX_DELAY)
+            };
+            message.next_attempt = Utc::now().timestamp() + delay;
+            error.queue_id = Some(message.id.clone());
+            message.last_error = Some(error.clone());
+            data.pending.push(message);
+        } else {
+            message.last_error = Some(error.clone());
+            data.dead.push(message);
+        }
+        self.data.save(&data);
+        error
+    }
+
+    /// Holds the message back until `next_attempt` without counting it as an attempt, for
+    /// sends the rate limiter didn't let through yet.
+    pub fn postpone(&self, mut message: QueuedMessage, next_attempt: i64) {
+        let mut data = self.data.lock();
+        data.pending.retain(|pending| pending.id != message.id);
+        message.next_attempt = next_attempt;
+        data.pending.push(message);
+        self.data.save(&data);
+    }
+
+    pub fn pending_for(&self, bot_name: &str) -> usize {
+        self.data.lock().pending.iter()
+            .filter(|message| message.bot_name == bot_name)
+            .count()
+    }
+
+    pub fn find(&self, id: &str) -> Option<QueuedMessage> {
+        let data = self.data.lock();
+        data.pending.iter().chain(data.dead.iter()).find(|message| message.id == id).cloned()
+    }
+
+    pub fn snapshot(&self) -> QueueData {
+        self.data.lock().clone()
+    }
+
+    /// Moves a dead message back to the queue for an immediate attempt.
+    pub fn replay(&self, id: &str) -> bool {
+        let mut data = self.data.lock();
+        let Some(position) = data.dead.iter().position(|message| message.id == id) else { return false };
+        let mut message = data.dead.remove(position);
+        message.attempts = 0;
+        message.next_attempt = Utc::now().timestamp();
+        data.pending.push(message);
+        self.data.save(&data);
+        true
+    }
+
+    pub fn discard(&self, id: &str) -> bool {
+        let mut data = self.data.lock();
+        let count = data.dead.len();
+        data.dead.retain(|message| message.id != id);
+        let removed = data.dead.len() != count;
+        self.data.save(&data);
+        removed
+    }
+}
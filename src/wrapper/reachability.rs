@@ -0,0 +1,86 @@
+use chrono::Utc;
+use crate::bot::telegram::peers::phone_digits;
+use crate::structs::api::UserData;
+use crate::structs::wrapper::{Reachability, ReachabilityData, RecipientStatus};
+use crate::wrapper::persist::{Flush, Persisted};
+
+/// The keys a recipient may be known under: their messenger id and their phone number.
+fn recipient_keys(messenger_id: Option<&str>, phone: &str) -> Vec<String> {
+    let messenger_id = messenger_id.map(str::trim).filter(|id| !id.is_empty()).map(String::from);
+    let phone = Some(phone_digits(phone)).filter(|phone| !phone.is_empty());
+    messenger_id.into_iter().chain(phone).collect()
+}
+
+/// Whether the status belongs to a recipient known under one of `keys`.
+fn matches(status: &RecipientStatus, keys: &[String]) -> bool {
+    keys.contains(&status.recipient) || keys.contains(&phone_digits(&status.phone))
+}
+
+/// File-backed list of recipients each bot can't reach, so sends to them stop until an
+/// operator clears the status or the recipient writes again.
+#[derive(Default)]
+pub struct ReachabilityStore {
+    data: Persisted<ReachabilityData>
+}
+
+impl ReachabilityStore {
+    pub fn load(file_name: &str) -> Self {
+        ReachabilityStore {
+            data: Persisted::load(file_name)
+        }
+    }
+
+    /// The status of an unreachable recipient, `None` when they may be written to.
+    pub fn get(&self, bot_name: &str, user: &UserData) -> Option<RecipientStatus> {
+        let data = self.data.lock();
+        let list = data.bots.get(bot_name)?;
+        let keys = recipient_keys(user.messenger_id.as_deref(), &user.phone);
+        list.values().find(|status| matches(status, &keys)).cloned()
+    }
+
+    /// Stores the status under the recipient's messenger id, or their phone without one.
+    pub fn set(&self, bot_name: &str, user: &UserData, reachability: Reachability, reason: &str) -> Option<RecipientStatus> {
+        let keys = recipient_keys(user.messenger_id.as_deref(), &user.phone);
+        let recipient = keys.first()?.clone();
+        let status = RecipientStatus {
+            bot_name: bot_name.to_string(),
+            recipient: recipient.clone(),
+            messenger_id: user.messenger_id.clone(),
+            phone: user.phone.clone(),
+            reachability,
+            reason: reason.to_string(),
+            since: Utc::now().timestamp()
+        };
+        let mut data = self.data.lock();
+        let list = data.bots.entry(bot_name.to_string()).or_default();
+        // The recipient may have been stored under their phone before their id was known.
+        list.retain(|_, stored| !matches(stored, &keys));
+        list.insert(recipient, status.clone());
+        self.data.changed();
+        Some(status)
+    }
+
+    /// Forgets the recipient's status under any of their keys, returns what was removed.
+    pub fn clear(&self, bot_name: &str, messenger_id: Option<&str>, phone: &str) -> Vec<RecipientStatus> {
+        let mut data = self.data.lock();
+        let Some(list) = data.bots.get_mut(bot_name) else { return Vec::new() };
+        let keys = recipient_keys(messenger_id, phone);
+        let (removed, kept): (Vec<_>, Vec<_>) = std::mem::take(list).into_values().partition(|status| matches(status, &keys));
+        *list = kept.into_iter().map(|status| (status.recipient.clone(), status)).collect();
+        if !removed.is_empty() {
+            self.data.changed();
+        }
+        removed
+    }
+
+    pub fn list(&self, bot_name: &str) -> Vec<RecipientStatus> {
+        let data = self.data.lock();
+        data.bots.get(bot_name).map(|list| list.values().cloned().collect()).unwrap_or_default()
+    }
+}
+
+impl Flush for ReachabilityStore {
+    fn flush(&self) {
+        self.data.flush();
+    }
+}
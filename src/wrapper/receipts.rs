@@ -0,0 +1,85 @@
+use std::collections::BTreeMap;
+use chrono::Utc;
+use crate::structs::wrapper::{MessageStatus, ReceiptData, TrackedMessage};
+use crate::wrapper::persist::{Flush, Persisted};
+
+/// How long a sent message is followed. Reminders are read within days or not at all.
+const RETENTION: i64 = 30 * 86400;
+
+/// File-backed status of the messages the bots sent, so read receipts that arrive after a
+/// restart still find their message.
+#[derive(Default)]
+pub struct ReceiptStore {
+    data: Persisted<ReceiptData>
+}
+
+impl ReceiptStore {
+    pub fn load(file_name: &str) -> Self {
+        ReceiptStore {
+            data: Persisted::load(file_name)
+        }
+    }
+
+    /// Starts following a message and forgets the ones past the retention.
+    pub fn track(&self, message: TrackedMessage) {
+        let oldest = Utc::now().timestamp() - RETENTION;
+        let mut data = self.data.lock();
+        for list in data.bots.values_mut() {
+            list.retain(|_, message| message.sent_at >= oldest);
+        }
+        data.bots.entry(message.bot_name.clone()).or_default().insert(message.id.clone(), message);
+        self.data.changed();
+    }
+
+    pub fn get(&self, bot_name: &str, id: &str) -> Option<TrackedMessage> {
+        self.data.lock().bots.get(bot_name)?.get(id).cloned()
+    }
+
+    /// Moves one message on, returns it when its status changed.
+    pub fn advance(&self, bot_name: &str, id: &str, status: MessageStatus, at: i64) -> Option<TrackedMessage> {
+        let mut data = self.data.lock();
+        let message = data.bots.get_mut(bot_name)?.get_mut(id)?;
+        if !message.advance(status, at) {
+            return None;
+        }
+        let message = message.clone();
+        self.data.changed();
+        Some(message)
+    }
+
+    /// Marks every message in the chat up to `max_id` as read, returns the ones that weren't.
+    pub fn read_up_to(&self, bot_name: &str, chat_id: i64, max_id: i32, at: i64) -> Vec<TrackedMessage> {
+        let mut data = self.data.lock();
+        let Some(list) = data.bots.get_mut(bot_name) else { return Vec::new() };
+        let read: Vec<TrackedMessage> = list.values_mut()
+            .filter(|message| message.chat_id == Some(chat_id) && message.message_id.is_some_and(|id| id <= max_id))
+            .filter_map(|message| message.advance(MessageStatus::Read, at).then(|| message.clone()))
+            .collect();
+        if !read.is_empty() {
+            self.data.changed();
+        }
+        read
+    }
+
+    /// Chats with messages not read yet, with the access hash to ask Telegram about them. A
+    /// chat is listed once, with a hash if any of its messages has one.
+    pub fn unread_chats(&self, bot_name: &str) -> Vec<(i64, Option<i64>)> {
+        let data = self.data.lock();
+        let mut chats: BTreeMap<i64, Option<i64>> = BTreeMap::new();
+        let unread = data.bots.get(bot_name).into_iter()
+            .flat_map(|list| list.values())
+            .filter(|message| message.status < MessageStatus::Read);
+        for message in unread {
+            let Some(chat_id) = message.chat_id else { continue };
+            let access_hash = chats.entry(chat_id).or_default();
+            *access_hash = access_hash.or(message.access_hash);
+        }
+        chats.into_iter().collect()
+    }
+}
+
+impl Flush for ReceiptStore {
+    fn flush(&self) {
+        self.data.flush();
+    }
+}
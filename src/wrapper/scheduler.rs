@@ -0,0 +1,90 @@
+use std::sync::Mutex;
+use chrono::Utc;
+use crate::structs::api::{DeliveryResult, SendMessageRequest};
+use crate::structs::wrapper::{ScheduleData, ScheduleMode, ScheduledMessage};
+use crate::utils::JsonConfigs;
+
+/// File-backed list of messages to send later. Local entries are handed to the outbound queue
+/// by the wrapper once due; Telegram-side entries are only kept so they can be moved or
+/// cancelled, and drop out once Telegram has sent them.
+pub struct Scheduler {
+    file_name: String,
+    data: Mutex<ScheduleData>
+}
+
+impl Scheduler {
+    pub fn load(file_name: &str) -> Self {
+        Scheduler {
+            file_name: file_name.to_string(),
+            data: Mutex::new(ScheduleData::from_file(file_name))
+        }
+    }
+
+    fn save(&self, data: &ScheduleData) {
+        if let Err(e) = data.to_file(&self.file_name) {
+            log::error!("Can't save {}: {}", self.file_name, e);
+        }
+    }
+
+    pub fn add(&self, bot_name: String, mode: ScheduleMode, send_at: i64, request: SendMessageRequest, remote: Option<DeliveryResult>) -> ScheduledMessage {
+        let now = Utc::now();
+        let seed = format!("schedule{}{}{}", bot_name, now.timestamp_nanos_opt().unwrap_or_default(), request.message);
+        let message = ScheduledMessage {
+            id: format!("{:x}", md5::compute(seed)),
+            bot_name,
+            mode,
+            send_at,
+            request,
+            remote
+        };
+        let mut data = self.data.lock().unwrap();
+        data.scheduled.push(message.clone());
+        self.save(&data);
+        message
+    }
+
+    /// Everything still waiting, soonest first.
+    pub fn list(&self) -> Vec<ScheduledMessage> {
+        let now = Utc::now().timestamp();
+        let mut data = self.data.lock().unwrap();
+        let count = data.scheduled.len();
+        data.scheduled.retain(|message| message.mode == ScheduleMode::Local || message.send_at > now);
+        if data.scheduled.len() != count {
+            self.save(&data);
+        }
+        let mut result = data.scheduled.clone();
+        result.sort_by_key(|message| message.send_at);
+        result
+    }
+
+    pub fn get(&self, id: &str) -> Option<ScheduledMessage> {
+        self.data.lock().unwrap().scheduled.iter().find(|message| message.id == id).cloned()
+    }
+
+    /// Local messages whose time has come. They stay listed until [`Scheduler::remove`], so a
+    /// crash before they reach the queue doesn't lose them.
+    pub fn due(&self) -> Vec<ScheduledMessage> {
+        let now = Utc::now().timestamp();
+        self.data.lock().unwrap().scheduled.iter()
+            .filter(|message| message.mode == ScheduleMode::Local && message.send_at <= now)
+            .cloned()
+            .collect()
+    }
+
+    pub fn reschedule(&self, id: &str, send_at: i64) -> Option<ScheduledMessage> {
+        let mut data = self.data.lock().unwrap();
+        let message = data.scheduled.iter_mut().find(|message| message.id == id)?;
+        message.send_at = send_at;
+        let message = message.clone();
+        self.save(&data);
+        Some(message)
+    }
+
+    pub fn remove(&self, id: &str) -> Option<ScheduledMessage> {
+        let mut data = self.data.lock().unwrap();
+        let position = data.scheduled.iter().position(|message| message.id == id)?;
+        let message = data.scheduled.remove(position);
+        self.save(&data);
+        Some(message)
+    }
+}
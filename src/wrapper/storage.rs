@@ -0,0 +1,303 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::Sender;
+use tokio::task::JoinHandle;
+use crate::bot::{BotAuth, DocaBot};
+use crate::bot::rules::RuleBook;
+use crate::bot::telegram::{Telegram, TelegramAuth};
+use crate::bot::telegram::session::SessionStorage;
+use crate::bot::whatsapp::{WhatsApp, WhatsappAuth};
+use crate::structs::api::{BotContext, BotStatus, DeliveryError};
+use crate::structs::auth::{AuthData, AuthList, WhatsAppAuthList};
+use crate::structs::wrapper::ChannelTx;
+use crate::utils;
+use crate::utils::JsonConfigs;
+use crate::wrapper::limiter::RateLimits;
+use crate::wrapper::dialogs::DialogStore;
+use crate::wrapper::metrics::Metrics;
+use crate::wrapper::contacts::ContactStore;
+use crate::wrapper::reachability::ReachabilityStore;
+use crate::wrapper::receipts::ReceiptStore;
+
+/// Everything needed to build a bot from its account credentials.
+pub struct BotFactory {
+    pub telegram: TelegramAuth,
+    pub sessions: Arc<dyn SessionStorage>,
+    pub whatsapp: WhatsappAuth,
+    pub rules: Arc<RuleBook>,
+    pub metrics: Arc<Metrics>,
+    pub dialogs: Arc<DialogStore>,
+    pub receipts: Arc<ReceiptStore>,
+    pub reachability: Arc<ReachabilityStore>,
+    pub contacts: Arc<ContactStore>
+}
+
+impl BotFactory {
+    pub async fn build(&self, bot_name: &str, auth: AuthData) -> utils::Result<Box<dyn DocaBot>> {
+        match auth {
+            AuthData::Telegram(ref auth_data) => {
+                let mut bot = Telegram::new(
+                    bot_name.to_string(),
+                    BotAuth::TelegramAuth(self.telegram.clone()),
+                    BotContext{
+                        bot_name: bot_name.to_string(),
+                        api_url: auth_data.api_url.clone(),
+                        rules: self.rules.clone(),
+                        webhook: auth_data.webhook.clone(),
+                        metrics: self.metrics.bot(bot_name),
+                        dialogs: self.dialogs.clone(),
+                        receipts: self.receipts.clone(),
+                        reachability: self.reachability.clone(),
+                        contacts: self.contacts.clone()
+                    },
+                    self.sessions.clone()
+                ).await?;
+                bot.sign_in(bot_name.to_string(), auth).await?;
+                Ok(Box::new(bot))
+            }
+            AuthData::WhatsApp(ref auth_data) => {
+                let mut bot = WhatsApp::new(
+                    BotAuth::WhatsappAuth(self.whatsapp.clone()),
+                    BotContext{
+                        bot_name: bot_name.to_string(),
+                        api_url: auth_data.api_url.clone(),
+                        rules: self.rules.clone(),
+                        webhook: auth_data.webhook.clone(),
+                        metrics: self.metrics.bot(bot_name),
+                        dialogs: self.dialogs.clone(),
+                        receipts: self.receipts.clone(),
+                        reachability: self.reachability.clone(),
+                        contacts: self.contacts.clone()
+                });
+                bot.sign_in(bot_name.to_string(), auth).await?;
+                Ok(Box::new(bot))
+            }
+        }
+    }
+}
+
+/// First pause before a bot that failed to start is built again, doubled with every failed
+/// attempt up to `MAX_RETRY_DELAY`.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+const SUPERVISE_INTERVAL: Duration = Duration::from_secs(1);
+
+enum BotEntry {
+    Running {
+        bot: Arc<dyn DocaBot>,
+        handler: JoinHandle<()>
+    },
+    /// The bot couldn't be built, `supervise` tries again at `retry_at`.
+    Failed {
+        error: DeliveryError,
+        failed_at: i64,
+        attempts: u32,
+        retry_at: Instant
+    }
+}
+
+impl BotEntry {
+    fn failed(error: &utils::Error, attempts: u32) -> Self {
+        let delay = RETRY_DELAY.saturating_mul(1 << attempts.saturating_sub(1).min(10)).min(MAX_RETRY_DELAY);
+        BotEntry::Failed {
+            error: error.into(),
+            failed_at: chrono::Utc::now().timestamp(),
+            attempts,
+            retry_at: Instant::now() + delay
+        }
+    }
+}
+
+/// The live routing table: bots can be added, removed and restarted while the wrapper and the
+/// HTTP API keep using it. Each bot's `message_handler` runs as a task owned by its entry.
+/// The configured accounts are kept apart from the running bots, so an account whose bot is
+/// down is still written back to the auth configs.
+pub struct BotStorage {
+    accounts: RwLock<BTreeMap<String, AuthData>>,
+    bots: RwLock<HashMap<String, BotEntry>>,
+    factory: BotFactory,
+    tx: Sender<ChannelTx>,
+    runtime: tokio::runtime::Handle,
+    auth_file: String,
+    whatsapp_file: String
+}
+
+impl BotStorage {
+    /// A storage that keeps its accounts in memory only.
+    pub fn new(factory: BotFactory, tx: Sender<ChannelTx>) -> Self {
+        BotStorage {
+            accounts: RwLock::new(BTreeMap::new()),
+            bots: RwLock::new(HashMap::new()),
+            factory,
+            tx,
+            runtime: tokio::runtime::Handle::current(),
+            auth_file: String::new(),
+            whatsapp_file: String::new()
+        }
+    }
+
+    /// Writes account changes back to the Telegram and WhatsApp auth configs.
+    pub fn with_files(mut self, auth_file: &str, whatsapp_file: &str) -> Self {
+        self.auth_file = auth_file.to_string();
+        self.whatsapp_file = whatsapp_file.to_string();
+        self
+    }
+
+    pub fn get(&self, bot_name: &str) -> Option<Arc<dyn DocaBot>> {
+        match self.bots.read().unwrap().get(bot_name) {
+            Some(BotEntry::Running { bot, .. }) => Some(bot.clone()),
+            _ => None
+        }
+    }
+
+    /// The status of an account whose bot failed to start, `None` for running bots.
+    pub fn failure(&self, bot_name: &str) -> Option<BotStatus> {
+        let messenger = match self.accounts.read().unwrap().get(bot_name)? {
+            AuthData::Telegram(_) => "telegram",
+            AuthData::WhatsApp(_) => "whatsapp"
+        };
+        match self.bots.read().unwrap().get(bot_name)? {
+            BotEntry::Failed { error, failed_at, .. } => Some(BotStatus {
+                name: bot_name.to_string(),
+                messenger: messenger.to_string(),
+                last_error: Some(error.clone()),
+                last_error_at: Some(*failed_at),
+                ..Default::default()
+            }),
+            BotEntry::Running { .. } => None
+        }
+    }
+
+    pub fn rate_limits(&self, bot_name: &str) -> RateLimits {
+        self.accounts.read().unwrap().get(bot_name).map(AuthData::rate_limits).unwrap_or_default()
+    }
+
+    /// Every configured account, whether its bot runs or not.
+    pub fn names(&self) -> Vec<String> {
+        self.accounts.read().unwrap().keys().cloned().collect()
+    }
+
+    pub fn find_whatsapp(&self, phone_id: &str) -> Option<String> {
+        self.accounts.read().unwrap().iter()
+            .find(|(_, auth)| matches!(auth, AuthData::WhatsApp(auth) if auth.phone_id == phone_id))
+            .map(|(bot_name, _)| bot_name.clone())
+    }
+
+    async fn spawn(&self, bot_name: &str, auth: AuthData) -> utils::Result<BotEntry> {
+        let bot: Arc<dyn DocaBot> = Arc::from(self.factory.build(bot_name, auth).await?);
+        let handler_bot = bot.clone();
+        let tx = self.tx.clone();
+        let handler = self.runtime.spawn(async move {
+            handler_bot.message_handler(tx).await;
+        });
+        Ok(BotEntry::Running { bot, handler })
+    }
+
+    /// Puts the entry in place and stops the bot it replaces.
+    fn swap(&self, bot_name: &str, entry: BotEntry) {
+        if let Some(BotEntry::Running { handler, .. }) = self.bots.write().unwrap().insert(bot_name.to_string(), entry) {
+            handler.abort();
+        }
+    }
+
+    /// Starts a configured account's bot without writing the configs. A bot that fails to
+    /// start stays registered and `supervise` keeps trying it.
+    pub async fn start(&self, bot_name: &str, auth: AuthData) -> utils::Result<()> {
+        self.accounts.write().unwrap().insert(bot_name.to_string(), auth.clone());
+        let result = self.spawn(bot_name, auth).await;
+        let (entry, result) = match result {
+            Ok(entry) => (entry, Ok(())),
+            Err(e) => (BotEntry::failed(&e, 1), Err(e))
+        };
+        self.swap(bot_name, entry);
+        result
+    }
+
+    /// Builds the bot, starts its handler task and replaces any bot with the same name. The
+    /// account is only saved once its bot is running.
+    pub async fn add(&self, bot_name: &str, auth: AuthData) -> utils::Result<()> {
+        let entry = self.spawn(bot_name, auth.clone()).await?;
+        self.accounts.write().unwrap().insert(bot_name.to_string(), auth);
+        self.swap(bot_name, entry);
+        self.save()
+    }
+
+    /// Signs the account out, which also releases its session lock, and drops it from the
+    /// configs. Returns `false` for an unknown account.
+    pub async fn remove(&self, bot_name: &str) -> utils::Result<bool> {
+        if self.accounts.write().unwrap().remove(bot_name).is_none() {
+            return Ok(false);
+        }
+        let entry = self.bots.write().unwrap().remove(bot_name);
+        if let Some(BotEntry::Running { bot, handler }) = entry {
+            handler.abort();
+            bot.sign_out().await;
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Reconnects the bot from its stored credentials. The old bot keeps running until the
+    /// new one is ready, so a failed restart leaves it as it was.
+    pub async fn restart(&self, bot_name: &str) -> utils::Result<()> {
+        let auth = self.accounts.read().unwrap().get(bot_name).cloned();
+        let Some(auth) = auth else { return Err(DeliveryError::unknown_bot(bot_name).into()) };
+        match self.spawn(bot_name, auth).await {
+            Ok(entry) => {
+                self.swap(bot_name, entry);
+                Ok(())
+            }
+            Err(e) => {
+                let mut bots = self.bots.write().unwrap();
+                let attempts = match bots.get(bot_name) {
+                    Some(BotEntry::Running { .. }) => return Err(e),
+                    Some(BotEntry::Failed { attempts, .. }) => attempts + 1,
+                    None => 1
+                };
+                bots.insert(bot_name.to_string(), BotEntry::failed(&e, attempts));
+                Err(e)
+            }
+        }
+    }
+
+    /// Builds the bots that failed to start again once their pause is over.
+    pub fn supervise(self: &Arc<Self>) {
+        let storage = self.clone();
+        self.runtime.spawn(async move {
+            loop {
+                tokio::time::sleep(SUPERVISE_INTERVAL).await;
+                let now = Instant::now();
+                let due: Vec<String> = storage.bots.read().unwrap().iter()
+                    .filter(|(_, entry)| matches!(entry, BotEntry::Failed { retry_at, .. } if *retry_at <= now))
+                    .map(|(bot_name, _)| bot_name.clone())
+                    .collect();
+                for bot_name in due {
+                    match storage.restart(&bot_name).await {
+                        Ok(()) => log::info!("[{}] Started after an earlier failure", bot_name),
+                        Err(e) => log::warn!("[{}] Still failing to start: {}", bot_name, e)
+                    }
+                }
+            }
+        });
+    }
+
+    /// Writes the configured accounts back to the auth configs, so they survive a restart.
+    fn save(&self) -> utils::Result<()> {
+        // The default storage only lives in memory.
+        if self.auth_file.is_empty() {
+            return Ok(());
+        }
+        let mut telegram = AuthList::new();
+        let mut whatsapp = WhatsAppAuthList::new();
+        for (bot_name, auth) in self.accounts.read().unwrap().iter() {
+            match auth {
+                AuthData::Telegram(auth) => { telegram.insert(bot_name.clone(), auth.clone()); }
+                AuthData::WhatsApp(auth) => { whatsapp.insert(bot_name.clone(), auth.clone()); }
+            }
+        }
+        telegram.to_file(&self.auth_file)?;
+        whatsapp.to_file(&self.whatsapp_file)?;
+        Ok(())
+    }
+}
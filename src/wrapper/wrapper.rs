@@ -1,44 +1,345 @@
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use tokio::sync::mpsc::{Receiver};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::sync::mpsc::{self, Receiver, UnboundedSender};
 use crate::bot::DocaBot;
-use crate::structs::wrapper::{ChannelData, ChannelTx};
+use crate::bot::telegram::peers::phone_digits;
+use crate::structs::api::{AddContactRequest, AddContactsRequest, ContactImport, ContactStatus, DeliveryError, DeliveryReply, DeliveryResult};
+use crate::structs::wrapper::{ChannelData, ChannelTx, QueuedMessage};
+use crate::wrapper::limiter::{Action, RateLimiter};
+use crate::wrapper::metrics::Metrics;
+use crate::wrapper::pools::{failover_pause, Pools};
+use crate::wrapper::queue::MessageQueue;
+use crate::wrapper::scheduler::Scheduler;
+use crate::wrapper::storage::BotStorage;
 
-pub type BotStorage = HashMap<String, Box<dyn DocaBot>>;
 pub type BotReceiver = Arc<Mutex<Receiver<ChannelTx>>>;
 
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// `value` holds the seconds until the bot may act again.
+fn rate_limited(seconds: i64) -> DeliveryError {
+    let mut error = DeliveryError::new(429, "RATE_LIMITED", format!("the bot is over its rate limits for {}s", seconds));
+    error.value = Some(seconds.max(0) as u32);
+    error
+}
+
+/// Retryable, the pool's members come back once their pauses run out.
+fn pool_unavailable(pool: &str, last_error: Option<&DeliveryError>) -> DeliveryError {
+    let message = match last_error {
+        Some(error) => format!("no account in pool {} can send, the last one failed with {}", pool, error),
+        None => format!("no account in pool {} can send", pool)
+    };
+    DeliveryError::new(503, "POOL_UNAVAILABLE", message)
+}
+
+/// Work for one bot's lane. Sends are queued by the router already, so it knows which
+/// messages are in flight and the retry timer doesn't pick them up twice.
+enum Job {
+    Send(QueuedMessage, Option<tokio::sync::oneshot::Sender<DeliveryReply>>),
+    Command(ChannelTx)
+}
+
 pub struct Wrapper {
     messengers: Arc<BotStorage>,
-    commands_rc: BotReceiver
+    commands_rc: BotReceiver,
+    queue: Arc<MessageQueue>,
+    scheduler: Arc<Scheduler>,
+    metrics: Arc<Metrics>,
+    pools: Arc<Pools>,
+    limiter: RateLimiter,
+    /// One task per bot or pool name works through its jobs in order, so a slow upload or
+    /// backend call only holds up its own bot.
+    lanes: std::sync::Mutex<HashMap<String, UnboundedSender<Job>>>,
+    in_flight: std::sync::Mutex<HashSet<String>>
 }
 
 impl Wrapper {
-    pub fn new(msg: Arc<BotStorage>, commands: Receiver<ChannelTx>) -> Wrapper {
-        return Wrapper {
+    pub fn new(msg: Arc<BotStorage>, commands: Receiver<ChannelTx>, queue: Arc<MessageQueue>, scheduler: Arc<Scheduler>, metrics: Arc<Metrics>, pools: Arc<Pools>) -> Wrapper {
+        Wrapper {
             messengers: msg,
-            commands_rc: BotReceiver::new(Mutex::<Receiver<ChannelTx>>::new(commands))
+            commands_rc: BotReceiver::new(Mutex::<Receiver<ChannelTx>>::new(commands)),
+            queue,
+            scheduler,
+            metrics,
+            pools,
+            limiter: RateLimiter::default(),
+            lanes: Default::default(),
+            in_flight: Default::default()
         }
     }
 
-    async fn internal(&self) {
-        loop {
-            let data_option: Option<ChannelTx> = self.commands_rc.lock().unwrap().recv().await;
-            if data_option.is_none() {
-                continue
-            }
-            let data_unwrapped: ChannelTx = data_option.unwrap();
-            let bot_name: String = data_unwrapped.bot_name;
-            let command: ChannelData = data_unwrapped.data;
-            let bot_instance: Option<&Box<dyn DocaBot>> = self.messengers.get(&bot_name);
-            if bot_instance.is_none() {
+    /// The accounts to try for a message, best first. A bot name gives just that bot. A pool
+    /// gives its connected and authorized members that aren't benched, and among those the
+    /// ones that already have a chat with the recipient come first.
+    async fn accounts(&self, message: &QueuedMessage) -> Vec<(String, Arc<dyn DocaBot>)> {
+        let Some(members) = self.pools.members(&message.bot_name) else {
+            return self.messengers.get(&message.bot_name)
+                .map(|bot| (message.bot_name.clone(), bot))
+                .into_iter()
+                .collect();
+        };
+        let mut ranked = Vec::new();
+        for bot_name in members {
+            let Some(bot) = self.messengers.get(bot_name) else { continue };
+            if self.pools.is_benched(bot_name) {
+                continue;
+            }
+            let status = bot.status().await;
+            if !status.authorized || !status.connected {
+                continue;
+            }
+            ranked.push((!bot.has_dialog(&message.request.user), bot_name.clone(), bot));
+        }
+        // A stable sort, the pool's own order breaks the ties.
+        ranked.sort_by_key(|(no_dialog, ..)| *no_dialog);
+        ranked.into_iter().map(|(_, bot_name, bot)| (bot_name, bot)).collect()
+    }
+
+    async fn deliver(&self, message: QueuedMessage) -> DeliveryReply {
+        let pooled = self.pools.members(&message.bot_name).is_some();
+        let accounts = self.accounts(&message).await;
+        if accounts.is_empty() {
+            let error = match pooled {
+                true => pool_unavailable(&message.bot_name, None),
+                false => DeliveryError::unknown_bot(&message.bot_name)
+            };
+            return Err(self.fail(message, error));
+        }
+        let mut wait: Option<Duration> = None;
+        let mut failed_over: Option<DeliveryError> = None;
+        let mut request = message.request.clone();
+        for (bot_name, bot_instance) in accounts {
+            let limits = self.messengers.rate_limits(&bot_name);
+            if let Err(until) = self.limiter.acquire(&bot_name, &limits, Action::Send) {
+                wait = Some(wait.map_or(until, |wait| wait.min(until)));
                 continue;
             }
-            let _ = match command {
-                ChannelData::ReceiveMessage(msg) => bot_instance.unwrap().handle_message(msg.user, msg.text).await,
-                ChannelData::SendMessage(msg) => bot_instance.unwrap().send_message(msg).await,
-                ChannelData::AddContact(contact) => bot_instance.unwrap().add_contact(contact).await,
-                // ChannelData::Handler(handler) => bot_instance.unwrap().add_handler(handler.user, handler.handler),
+            let metrics = self.metrics.bot(&bot_name);
+            let error = match bot_instance.send_message(request.clone()).await {
+                Ok(mut result) => {
+                    metrics.sent.fetch_add(1, Ordering::Relaxed);
+                    self.queue.complete(&message.id);
+                    if pooled {
+                        result.account = Some(bot_name);
+                    }
+                    return Ok(result);
+                }
+                Err(e) => {
+                    bot_instance.report_error(&e);
+                    DeliveryError::from(&e)
+                }
+            };
+            metrics.failed.fetch_add(1, Ordering::Relaxed);
+            if error.name == "FLOOD_WAIT" {
+                metrics.flood_waits.fetch_add(1, Ordering::Relaxed);
+            }
+            self.limiter.observe(&bot_name, &limits, &error);
+            log::error!("[{}] {}", bot_name, error.message);
+            let Some(pause) = failover_pause(&error, &limits).filter(|_| pooled) else {
+                return Err(self.fail(message, error));
+            };
+            log::warn!("[{}] Leaving pool {} for {}s", bot_name, message.bot_name, pause.as_secs());
+            self.pools.bench(&bot_name, pause);
+            // The caller's access hash belongs to one account, the next one resolves the
+            // recipient itself.
+            request.access_hash = None;
+            failed_over = Some(error);
+        }
+        match wait {
+            Some(wait) if message.request.schedule_date.is_some() => {
+                let error = rate_limited(wait.as_secs_f64().ceil() as i64);
+                Err(self.fail(message, error))
+            }
+            Some(wait) => Ok(self.postpone(message, wait)),
+            None => {
+                let error = pool_unavailable(&message.bot_name, failed_over.as_ref());
+                Err(self.fail(message, error))
+            }
+        }
+    }
+
+    /// Telegram schedules are sent once, by the request that creates them. Left in the queue
+    /// they would go out later with no scheduler record, so they are dropped and the caller
+    /// gets the error.
+    fn fail(&self, message: QueuedMessage, error: DeliveryError) -> DeliveryError {
+        if message.request.schedule_date.is_none() {
+            return self.queue.fail(message, error);
+        }
+        self.queue.complete(&message.id);
+        error
+    }
+
+    /// Leaves the message in the queue until the bot may send again. The caller gets its
+    /// queue id, the message goes out on its own.
+    fn postpone(&self, message: QueuedMessage, wait: Duration) -> DeliveryResult {
+        let send_at = chrono::Utc::now().timestamp() + wait.as_secs_f64().ceil() as i64;
+        let result = DeliveryResult { queue_id: Some(message.id.clone()), send_at: Some(send_at), ..Default::default() };
+        self.queue.postpone(message, send_at);
+        result
+    }
+
+    /// Contact imports aren't queued, the caller is told when to try again.
+    async fn add_contact(&self, bot_name: &str, bot_instance: &dyn DocaBot, contact: AddContactRequest) -> DeliveryReply {
+        let limits = self.messengers.rate_limits(bot_name);
+        // Nothing is imported for a phone linked already.
+        if !bot_instance.is_linked(&contact.phone) {
+            if let Err(wait) = self.limiter.acquire(bot_name, &limits, Action::ImportContact) {
+                return Err(rate_limited(wait.as_secs_f64().ceil() as i64));
+            }
+        }
+        bot_instance.add_contact(contact).await.map_err(|e| {
+            bot_instance.report_error(&e);
+            let error = DeliveryError::from(&e);
+            self.limiter.observe(bot_name, &limits, &error);
+            error
+        })
+    }
+
+    /// Each phone not linked yet takes one contact import, phones past the account's daily
+    /// allowance come back `rate_limited` and the rest is imported.
+    async fn add_contacts(&self, bot_name: &str, bot_instance: &dyn DocaBot, request: AddContactsRequest) -> DeliveryReply {
+        let limits = self.messengers.rate_limits(bot_name);
+        let mut permitted: HashSet<String> = HashSet::new();
+        let mut limited: HashMap<String, i64> = HashMap::new();
+        let mut contacts = Vec::new();
+        let mut results = Vec::new();
+        for contact in request.contacts {
+            let phone = phone_digits(&contact.phone);
+            let wait = match (bot_instance.is_linked(&contact.phone) || permitted.contains(&phone), limited.get(&phone)) {
+                (true, _) => None,
+                (false, Some(wait)) => Some(*wait),
+                (false, None) => match self.limiter.acquire(bot_name, &limits, Action::ImportContact) {
+                    Ok(()) => {
+                        permitted.insert(phone.clone());
+                        None
+                    }
+                    Err(wait) => Some(wait.as_secs_f64().ceil() as i64)
+                }
             };
+            match wait {
+                Some(wait) => {
+                    limited.insert(phone, wait);
+                    let mut result = ContactImport::new(&contact, ContactStatus::RateLimited);
+                    result.retry_after = Some(wait);
+                    results.push(result);
+                }
+                None => contacts.push(contact)
+            }
+        }
+        if !contacts.is_empty() {
+            let imported = bot_instance.import_contacts(contacts).await.map_err(|e| {
+                bot_instance.report_error(&e);
+                let error = DeliveryError::from(&e);
+                self.limiter.observe(bot_name, &limits, &error);
+                error
+            })?;
+            results.splice(0..0, imported);
+        }
+        Ok(DeliveryResult { contacts: results, ..Default::default() })
+    }
+
+    /// Hands the job to the lane of `name`, starting the lane on first use.
+    fn route(self: &Arc<Self>, name: &str, job: Job) {
+        let mut lanes = self.lanes.lock().unwrap();
+        let lane = lanes.entry(name.to_string()).or_insert_with(|| {
+            let (tx, mut rx) = mpsc::unbounded_channel::<Job>();
+            let wrapper = self.clone();
+            tokio::spawn(async move {
+                while let Some(job) = rx.recv().await {
+                    wrapper.run(job).await;
+                }
+            });
+            tx
+        });
+        let _ = lane.send(job);
+    }
+
+    /// Routes a queued message to its lane unless it is on its way already.
+    fn send(self: &Arc<Self>, message: QueuedMessage, reply: Option<tokio::sync::oneshot::Sender<DeliveryReply>>) {
+        if !self.in_flight.lock().unwrap().insert(message.id.clone()) {
+            return;
+        }
+        let name = message.bot_name.clone();
+        self.route(&name, Job::Send(message, reply));
+    }
+
+    async fn run(&self, job: Job) {
+        match job {
+            Job::Send(message, reply) => {
+                let (bot_name, id) = (message.bot_name.clone(), message.id.clone());
+                let result = self.deliver(message).await;
+                self.in_flight.lock().unwrap().remove(&id);
+                self.reply(&bot_name, reply, result);
+            }
+            Job::Command(data) => self.handle(data).await
+        }
+    }
+
+    fn reply(&self, bot_name: &str, reply: Option<tokio::sync::oneshot::Sender<DeliveryReply>>, result: DeliveryReply) {
+        if let Err(e) = &result {
+            log::error!("[{}] {}", bot_name, e.message);
+        }
+        if let Some(reply) = reply {
+            let _ = reply.send(result);
+        }
+    }
+
+    async fn handle(&self, data: ChannelTx) {
+        let bot_name: String = data.bot_name;
+        let result = match (data.data, self.messengers.get(&bot_name)) {
+            (ChannelData::SendMessage(_), _) => unreachable!("sends are routed as Job::Send"),
+            (_, None) => Err(DeliveryError::unknown_bot(&bot_name)),
+            (ChannelData::ReceiveMessage(msg), Some(bot_instance)) => {
+                self.metrics.bot(&bot_name).received.fetch_add(1, Ordering::Relaxed);
+                bot_instance.handle_message(msg).await
+                    .map(|_| DeliveryResult::default())
+                    .map_err(|e| {
+                        bot_instance.report_error(&e);
+                        DeliveryError::from(&e)
+                    })
+            }
+            (ChannelData::AddContact(contact), Some(bot_instance)) => self.add_contact(&bot_name, bot_instance.as_ref(), contact).await,
+            (ChannelData::AddContacts(request), Some(bot_instance)) => self.add_contacts(&bot_name, bot_instance.as_ref(), request).await,
+            // ChannelData::Handler(handler) => bot_instance.unwrap().add_handler(handler.user, handler.handler),
+        };
+        self.reply(&bot_name, data.reply, result);
+    }
+
+    /// Only routes: sends are queued here, everything else goes to its bot's lane as is.
+    async fn internal(self: &Arc<Self>) {
+        let mut retry_timer = tokio::time::interval(RETRY_INTERVAL);
+        loop {
+            tokio::select! {
+                data_option = async { self.commands_rc.lock().await.recv().await } => {
+                    let Some(data) = data_option else { continue };
+                    match data.data {
+                        // Sends may name a pool instead of a bot, `deliver` picks the account.
+                        ChannelData::SendMessage(msg) => {
+                            let message = self.queue.push(data.bot_name, msg);
+                            self.send(message, data.reply);
+                        }
+                        _ => {
+                            let name = data.bot_name.clone();
+                            self.route(&name, Job::Command(data));
+                        }
+                    }
+                }
+                _ = retry_timer.tick() => {
+                    // Due local schedules move to the outbound queue, which takes care of
+                    // retries from there.
+                    for scheduled in self.scheduler.due() {
+                        let message = self.queue.push(scheduled.bot_name, scheduled.request);
+                        self.scheduler.remove(&scheduled.id);
+                        self.send(message, None);
+                    }
+                    for message in self.queue.due() {
+                        self.send(message, None);
+                    }
+                }
+            }
         }
     }
 
@@ -48,4 +349,4 @@ impl Wrapper {
             0
         });
     }
-}
\ No newline at end of file
+}